@@ -0,0 +1,211 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Derives for the `FromRow` and `ToParams` traits of the `sqlite3` crate.
+//!
+//! `#[derive(FromRow)]` reads the fields of a struct from the columns of the
+//! same name, or from the columns in order for tuple structs. Fields accept:
+//!
+//! - `#[sqlite(rename = "column")]` to read another column,
+//! - `#[sqlite(index = 2)]` to read a column by position,
+//! - `#[sqlite(default)]` to use `Default::default()` when the column is missing,
+//! - `#[sqlite(from_sql_with = "path")]` to read the column with a function
+//!   like `FromSql::from_sql`.
+//!
+//! `#[derive(ToParams)]` binds the fields of a struct to the parameters of
+//! the same name (`:field`, `@field` or `$field`), or to the parameters in
+//! order for tuple structs; a field without a parameter fails with
+//! `SQLITE_RANGE`. Fields accept `rename` and `skip`.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use] extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{Data, DeriveInput, Fields, Index, LitInt, LitStr, Path};
+
+#[derive(Default)]
+struct FieldAttrs {
+    rename: Option<String>,
+    index: Option<usize>,
+    default: bool,
+    from_sql_with: Option<Path>,
+    skip: bool,
+}
+
+fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in &field.attrs {
+        if !attr.path().is_ident("sqlite") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                attrs.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("index") {
+                attrs.index = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("default") {
+                attrs.default = true;
+            } else if meta.path.is_ident("from_sql_with") {
+                attrs.from_sql_with = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            } else if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else {
+                return Err(meta.error("unknown sqlite attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+fn struct_fields(input: &DeriveInput) -> syn::Result<&Fields> {
+    match input.data {
+        Data::Struct(ref data) => Ok(&data.fields),
+        _ => Err(syn::Error::new_spanned(&input.ident, "only structs are supported")),
+    }
+}
+
+#[proc_macro_derive(FromRow, attributes(sqlite))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    from_row(&input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+fn from_row(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = struct_fields(input)?;
+    let mut reads = Vec::new();
+    for (position, field) in fields.iter().enumerate() {
+        let attrs = field_attrs(field)?;
+        let ty = &field.ty;
+        let read = match attrs.from_sql_with {
+            Some(ref f) => quote!(#f(cursor, i)),
+            None => quote!(<#ty as ::sqlite3::FromSql>::from_sql(cursor, i)),
+        };
+        let column = match (attrs.index, &field.ident) {
+            (Some(index), _) => {
+                let index = index as isize;
+                quote!(Some(#index).filter(|&i| i < cursor.get_column_count()))
+            }
+            (None, Some(ident)) => {
+                let name = attrs.rename.clone().unwrap_or_else(|| ident.to_string());
+                quote!(cursor.get_column_index(#name))
+            }
+            (None, None) => {
+                let index = position as isize;
+                quote!(Some(#index).filter(|&i| i < cursor.get_column_count()))
+            }
+        };
+        let name = match (&attrs.rename, &field.ident) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(ident)) if attrs.index.is_none() => ident.to_string(),
+            _ => attrs.index.unwrap_or(position).to_string(),
+        };
+        let missing = if attrs.default {
+            quote!(::std::default::Default::default())
+        } else {
+            quote!(return Err(::sqlite3::FromRowError::MissingColumn(#name.to_string())))
+        };
+        let value = quote! {
+            match #column {
+                Some(i) => #read.map_err(|e| ::sqlite3::FromRowError::Column {
+                    column: cursor.get_column_name(i).to_string(),
+                    error: e,
+                })?,
+                None => #missing,
+            }
+        };
+        reads.push(match field.ident {
+            Some(ref ident) => quote!(#ident: #value),
+            None => value,
+        });
+    }
+    let construct = match *fields {
+        Fields::Named(_) => quote!(Self { #(#reads),* }),
+        Fields::Unnamed(_) => quote!(Self(#(#reads),*)),
+        Fields::Unit => quote!(Self),
+    };
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::sqlite3::FromRow for #ident #ty_generics #where_clause {
+            fn from_row(cursor: &mut ::sqlite3::Cursor) -> ::std::result::Result<Self, ::sqlite3::FromRowError> {
+                Ok(#construct)
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(ToParams, attributes(sqlite))]
+pub fn derive_to_params(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    to_params(&input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+fn to_params(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = struct_fields(input)?;
+    let mut binds = Vec::new();
+    let mut position = 0isize;
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = field_attrs(field)?;
+        if attrs.skip {
+            continue;
+        }
+        binds.push(match field.ident {
+            Some(ref ident) => {
+                let name = attrs.rename.unwrap_or_else(|| ident.to_string());
+                quote!(cursor.bind_named(#name, &self.#ident)?;)
+            }
+            None => {
+                let index = Index::from(i);
+                position += 1;
+                quote! {
+                    let r = ::sqlite3::ToSql::bind_param(&self.#index, cursor, #position);
+                    if r != ::sqlite3::ResultCode::SQLITE_OK {
+                        return Err(r);
+                    }
+                }
+            }
+        });
+    }
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::sqlite3::ToParams for #ident #ty_generics #where_clause {
+            fn bind_to(&self, cursor: &mut ::sqlite3::Cursor) -> ::sqlite3::SqliteResult<()> {
+                #(#binds)*
+                Ok(())
+            }
+        }
+    })
+}
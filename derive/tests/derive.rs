@@ -0,0 +1,102 @@
+extern crate sqlite3;
+#[macro_use] extern crate sqlite3_derive;
+
+use sqlite3::*;
+use sqlite3::BindArg::*;
+use sqlite3::ResultCode::*;
+
+#[derive(FromRow, ToParams, PartialEq, Debug)]
+struct User {
+    id: i64,
+    #[sqlite(rename = "user_name")]
+    name: String,
+    email: Option<String>,
+    #[sqlite(default, skip)]
+    visits: u32,
+    #[sqlite(from_sql_with = "upper")]
+    role: String,
+}
+
+fn upper(cursor: &mut Cursor, i: isize) -> Result<String, FromSqlError> {
+    String::from_sql(cursor, i).map(|s| s.to_uppercase())
+}
+
+#[derive(FromRow, ToParams, PartialEq, Debug)]
+struct Pair(i32, Option<f64>);
+
+#[derive(FromRow, PartialEq, Debug)]
+struct Indexed {
+    #[sqlite(index = 1)]
+    second: String,
+}
+
+fn setup() -> Database {
+    let db = open(":memory:").unwrap();
+    db.exec("CREATE TABLE users (id INTEGER PRIMARY KEY, user_name TEXT, email TEXT, role TEXT);").unwrap();
+    db
+}
+
+#[test]
+fn round_trip() {
+    let db = setup();
+    let user = User { id: 1, name: "ann".to_string(), email: None, visits: 3, role: "admin".to_string() };
+    {
+        let mut c = db.prepare("INSERT INTO users VALUES (:id, :user_name, :email, :role)", &None).unwrap();
+        c.bind_to_params(&user).unwrap();
+        assert_eq!(c.step(), SQLITE_DONE);
+    }
+    let mut c = db.prepare("SELECT * FROM users", &None).unwrap();
+    let read: User = c.step_row_as().unwrap().unwrap();
+    assert_eq!(read, User { visits: 0, role: "ADMIN".to_string(), ..user });
+    assert_eq!(c.step_row_as::<User>().unwrap(), None);
+}
+
+#[test]
+fn tuple_structs_and_indexes() {
+    let db = setup();
+    let mut c = db.prepare("SELECT ?, ?", &None).unwrap();
+    c.bind_to_params(&Pair(4, Some(0.5))).unwrap();
+    assert_eq!(c.step_row_as::<Pair>().unwrap(), Some(Pair(4, Some(0.5))));
+
+    let mut c = db.prepare("SELECT 1, 'two'", &None).unwrap();
+    assert_eq!(c.step_row_as::<Indexed>().unwrap(), Some(Indexed { second: "two".to_string() }));
+    let mut c = db.prepare("SELECT 1, 'two'", &None).unwrap();
+    assert_eq!(c.step_row_as::<(i64, String)>().unwrap(), Some((1, "two".to_string())));
+}
+
+#[test]
+fn named_parameters() {
+    let db = setup();
+    let mut c = db.prepare("SELECT :a, @a, $b, ?", &None).unwrap();
+    c.bind_named("a", "text").unwrap();
+    c.bind_named("b", &b"blob"[..]).unwrap();
+    assert_eq!(c.bind_named("c", &1), Err(SQLITE_RANGE));
+    assert_eq!(c.step_row_as::<(String, String, Vec<u8>, Option<i64>)>().unwrap(),
+               Some(("text".to_string(), "text".to_string(), b"blob".to_vec(), None)));
+
+    let user = User { id: 1, name: "ann".to_string(), email: None, visits: 3, role: "admin".to_string() };
+    let mut c = db.prepare("INSERT INTO users (id, user_name, email) VALUES (:id, :user_name, :email)", &None).unwrap();
+    assert_eq!(c.bind_to_params(&user), Err(SQLITE_RANGE));
+}
+
+#[test]
+fn errors() {
+    let db = setup();
+    let mut c = db.prepare("SELECT 1 AS id, 'x' AS role", &None).unwrap();
+    assert_eq!(c.step_row_as::<User>(), Err(FromRowError::MissingColumn("user_name".to_string())));
+
+    let mut c = db.prepare("SELECT 'one' AS id, 'ann' AS user_name, NULL AS email, 'x' AS role", &None).unwrap();
+    assert_eq!(c.step_row_as::<User>(), Err(FromRowError::Column {
+        column: "id".to_string(),
+        error: FromSqlError::InvalidType { expected: "INTEGER", found: ColumnType::SQLITE_TEXT },
+    }));
+
+    let mut c = db.prepare("SELECT 300", &None).unwrap();
+    assert_eq!(c.step_row_as::<(u8,)>(), Err(FromRowError::Column {
+        column: "300".to_string(),
+        error: FromSqlError::OutOfRange(300),
+    }));
+    let mut c = db.prepare("SELECT 1", &None).unwrap();
+    assert_eq!(c.step_row_as::<Indexed>(), Err(FromRowError::MissingColumn("1".to_string())));
+    assert_eq!(Null.to_sql(), Null);
+}
@@ -0,0 +1,218 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Handling of lock contention (`SQLITE_BUSY`).
+//!
+//! Besides `Database::set_busy_timeout`, a connection can be given an
+//! arbitrary busy handler or a `RetryPolicy`. A `RetryPolicy` can also run
+//! a whole write transaction, retrying it when the database stays locked.
+
+use ffi::*;
+use libc::{c_int, c_void};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+use database::Database;
+use types::*;
+use types::ResultCode::*;
+
+pub type BusyHook = Box<dyn FnMut(i32) -> bool + Send>;
+
+extern "C" fn busy_callback(ctx: *mut c_void, attempt: c_int) -> c_int {
+    let hook = unsafe { &mut *(ctx as *mut BusyHook) };
+    match panic::catch_unwind(AssertUnwindSafe(|| hook(attempt))) {
+        Ok(true) => 1,
+        _ => 0,
+    }
+}
+
+impl Database {
+
+    /// Registers a busy handler, replacing any busy handler or busy timeout.
+    ///
+    /// The handler is called with the number of times it has already been
+    /// invoked for the same locking event and returns whether SQLite should
+    /// try again; returning `false` makes the operation fail with `SQLITE_BUSY`.
+    /// See http://www.sqlite.org/c3ref/busy_handler.html
+    pub fn busy_handler<F>(&mut self, f: F) -> SqliteResult<()>
+        where F: FnMut(i32) -> bool + Send + 'static
+    {
+        let mut hook: Box<BusyHook> = Box::new(Box::new(f));
+        let r = unsafe {
+            sqlite3_busy_handler(self.dbh, Some(busy_callback), &mut *hook as *mut BusyHook as *mut c_void)
+        };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        self.busy_hook = Some(hook);
+        Ok(())
+    }
+
+    /// Removes the busy handler or busy timeout, so that lock contention
+    /// fails immediately with `SQLITE_BUSY`.
+    /// See http://www.sqlite.org/c3ref/busy_handler.html
+    pub fn remove_busy_handler(&mut self) -> SqliteResult<()> {
+        let r = unsafe {
+            sqlite3_busy_handler(self.dbh, None, ptr::null_mut())
+        };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        self.busy_hook = None;
+        Ok(())
+    }
+
+    /// Installs a busy handler which waits according to `policy`.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) -> SqliteResult<()> {
+        let mut started = Instant::now();
+        self.busy_handler(move |attempt| {
+            if attempt == 0 {
+                started = Instant::now();
+            }
+            policy.wait(attempt as u32, started)
+        })
+    }
+}
+
+/// Exponential backoff with optional jitter, bounded by a total wait time.
+///
+/// The `n`-th retry waits `initial_delay * 2^n`, capped to `max_delay`.
+/// With jitter, each delay is scaled by a random factor between 0.5 and 1
+/// so that competing connections do not retry in lockstep. Once `max_wait`
+/// would be exceeded, the operation fails with `SQLITE_BUSY`.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_wait: Duration,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(100),
+            max_wait: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    pub fn initial_delay(mut self, delay: Duration) -> RetryPolicy {
+        self.initial_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> RetryPolicy {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Sets the total time to keep retrying for.
+    pub fn max_wait(mut self, wait: Duration) -> RetryPolicy {
+        self.max_wait = wait;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Returns the delay before retrying for the `attempt`-th time (from 0).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        let delay = self.initial_delay.checked_mul(factor).unwrap_or(self.max_delay);
+        let delay = if delay > self.max_delay { self.max_delay } else { delay };
+        if self.jitter {
+            // `RandomState` is seeded afresh each time, which is random enough here.
+            let r = RandomState::new().build_hasher().finish();
+            delay.mul_f64(0.5 + (r % 1024) as f64 / 2048.0)
+        } else {
+            delay
+        }
+    }
+
+    /// Sleeps before the `attempt`-th retry unless that would exceed `max_wait`
+    /// since `started`, and returns whether to retry.
+    fn wait(&self, attempt: u32, started: Instant) -> bool {
+        let delay = self.delay(attempt);
+        if started.elapsed() + delay > self.max_wait {
+            return false;
+        }
+        thread::sleep(delay);
+        true
+    }
+
+    /// Runs `f` inside a `BEGIN IMMEDIATE` transaction and commits it.
+    ///
+    /// When beginning or committing the transaction, or `f` itself, fails
+    /// with `SQLITE_BUSY`, the transaction is rolled back and `f` is run again
+    /// after a delay, until `max_wait` is exceeded. Any other error rolls
+    /// the transaction back and is returned as is.
+    pub fn transaction<T, F>(&self, db: &mut Database, mut f: F) -> SqliteResult<T>
+        where F: FnMut(&mut Database) -> SqliteResult<T>
+    {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            let r = match db.exec("BEGIN IMMEDIATE") {
+                Ok(..) => {
+                    let r = f(db).and_then(|v| db.exec("COMMIT").map(|_| v));
+                    if r.is_err() {
+                        let _ = db.exec("ROLLBACK");
+                    }
+                    r
+                }
+                Err(e) => Err(e),
+            };
+            match r {
+                Err(SQLITE_BUSY) => {
+                    debug!("`RetryPolicy.transaction()`: busy, attempt={}", attempt);
+                    if !self.wait(attempt, started) {
+                        return Err(SQLITE_BUSY);
+                    }
+                    attempt += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+}
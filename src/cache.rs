@@ -0,0 +1,186 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A per-connection cache of prepared statements, keyed by their SQL text.
+
+use cursor::*;
+use ffi::*;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use database::Database;
+use types::*;
+
+const DEFAULT_CAPACITY: usize = 16;
+
+/// The statement cache owned by a `Database`.
+pub(crate) struct StatementCache {
+    capacity: usize,
+    // least recently used first.
+    entries: VecDeque<(String, *mut stmt)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for StatementCache {
+    fn default() -> StatementCache {
+        StatementCache {
+            capacity: DEFAULT_CAPACITY,
+            entries: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl StatementCache {
+    fn take(&mut self, sql: &str) -> Option<*mut stmt> {
+        let pos = self.entries.iter().position(|e| e.0 == sql);
+        pos.and_then(|i| self.entries.remove(i)).map(|(_, stmt)| stmt)
+    }
+
+    fn put(&mut self, sql: String, stmt: *mut stmt) {
+        self.entries.push_back((sql, stmt));
+        self.evict();
+    }
+
+    fn clear(&mut self) {
+        for (_, stmt) in self.entries.drain(..) {
+            unsafe { sqlite3_finalize(stmt); }
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let (_, stmt) = self.entries.pop_front().unwrap();
+            unsafe { sqlite3_finalize(stmt); }
+        }
+    }
+}
+
+/// Whether `sql` may change the schema, i.e. has a CREATE, DROP or ALTER
+/// keyword. Words in literals are false positives, which merely flush.
+pub(crate) fn changes_schema(sql: &str) -> bool {
+    sql.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').any(|word| {
+        ["CREATE", "DROP", "ALTER"].iter().any(|k| word.eq_ignore_ascii_case(k))
+    })
+}
+
+/// Counters of a statement cache.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// The number of statements currently held by the cache.
+    pub size: usize,
+    pub capacity: usize,
+}
+
+/// A cursor borrowed from the statement cache.
+///
+/// It derefs to `Cursor`. When dropped, the statement is reset, its bindings
+/// are cleared and it is returned to the cache instead of being finalized.
+pub struct CachedCursor<'db> {
+    db: &'db Database,
+    sql: String,
+    cursor: Option<Cursor<'db>>,
+}
+
+impl<'db> Deref for CachedCursor<'db> {
+    type Target = Cursor<'db>;
+    fn deref(&self) -> &Cursor<'db> {
+        self.cursor.as_ref().unwrap()
+    }
+}
+
+impl<'db> DerefMut for CachedCursor<'db> {
+    fn deref_mut(&mut self) -> &mut Cursor<'db> {
+        self.cursor.as_mut().unwrap()
+    }
+}
+
+impl<'db> Drop for CachedCursor<'db> {
+    fn drop(&mut self) {
+        let mut cursor = self.cursor.take().unwrap();
+        cursor.reset();
+        cursor.clear_bindings();
+        let stmt = cursor.into_raw();
+        let sql = ::std::mem::take(&mut self.sql);
+        self.db.stmt_cache.borrow_mut().put(sql, stmt);
+    }
+}
+
+impl Database {
+
+    /// Prepares an SQL statement like `prepare`, reusing a previously
+    /// prepared statement with the same SQL text when one is cached.
+    ///
+    /// Cached statements prepare themselves again after a schema change.
+    /// The cache is flushed when `exec` runs a CREATE, DROP or ALTER
+    /// statement; call `flush_cache` after changing the schema otherwise,
+    /// e.g. through a prepared statement.
+    pub fn prepare_cached<'db>(&'db self, sql: &str) -> SqliteResult<CachedCursor<'db>> {
+        let cached = {
+            let mut cache = self.stmt_cache.borrow_mut();
+            let cached = cache.take(sql);
+            if cached.is_some() { cache.hits += 1; } else { cache.misses += 1; }
+            cached
+        };
+        let cursor = match cached {
+            Some(stmt) => cursor_with_statement(stmt, &self.dbh),
+            None => self.prepare(sql, &None)?,
+        };
+        Ok(CachedCursor { db: self, sql: sql.to_string(), cursor: Some(cursor) })
+    }
+
+    /// Sets the maximum number of statements kept by the statement cache,
+    /// finalizing the least recently used ones beyond it. 0 disables caching.
+    pub fn set_prepared_statement_cache_capacity(&mut self, capacity: usize) {
+        let mut cache = self.stmt_cache.borrow_mut();
+        cache.capacity = capacity;
+        cache.evict();
+    }
+
+    /// Returns the hit and miss counters of the statement cache.
+    pub fn prepared_statement_cache_stats(&self) -> CacheStats {
+        let cache = self.stmt_cache.borrow();
+        CacheStats {
+            hits: cache.hits,
+            misses: cache.misses,
+            size: cache.entries.len(),
+            capacity: cache.capacity,
+        }
+    }
+
+    /// Finalizes all statements held by the statement cache.
+    pub fn flush_cache(&self) {
+        self.stmt_cache.borrow_mut().clear();
+    }
+}
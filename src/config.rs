@@ -0,0 +1,98 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Process-wide library configuration.
+//! See http://www.sqlite.org/c3ref/config.html
+
+use ffi::*;
+use libc::{c_int, c_void, c_char};
+use log::LogLevel;
+use std::ffi::CStr;
+use std::ptr;
+use std::sync::Mutex;
+use types::*;
+use types::ResultCode::*;
+
+const SQLITE_CONFIG_LOG: c_int = 16;
+
+// primary result codes only ever seen through the error log.
+const SQLITE_NOTICE: c_int = 27;
+const SQLITE_WARNING: c_int = 28;
+
+static LOGGER_INSTALLED: Mutex<bool> = Mutex::new(false);
+
+/// Maps the (possibly extended) result code of a logged message to a log level.
+fn log_level(code: c_int) -> LogLevel {
+    match code & 0xff {
+        SQLITE_NOTICE => LogLevel::Info,
+        SQLITE_WARNING => LogLevel::Warn,
+        // a statement being reprepared after a schema change is recoverable.
+        c if c == SQLITE_SCHEMA as c_int => LogLevel::Warn,
+        c if c == SQLITE_OK as c_int => LogLevel::Info,
+        _ => LogLevel::Error,
+    }
+}
+
+extern "C" fn log_callback(_ctx: *mut c_void, code: c_int, msg: *const c_char) {
+    // SQLite may log from any thread and while holding mutexes; only format and forward.
+    let msg = unsafe { CStr::from_ptr(msg) };
+    log!(target: "sqlite3::log", log_level(code), "({}) {}", code, msg.to_string_lossy());
+}
+
+/// Routes the SQLite error log (`SQLITE_CONFIG_LOG`) to the `log` crate,
+/// under the `sqlite3::log` target.
+///
+/// Notices are logged at `Info`, warnings and schema changes at `Warn`,
+/// everything else at `Error`. Once installed, later calls return `Ok`
+/// without touching the library; a failed call can be retried.
+///
+/// `sqlite3_config` is not threadsafe: call this at startup, before any
+/// other thread uses SQLite. SQLite before 3.42 accepts it only before it
+/// is initialized, i.e. before the first database is opened, and fails with
+/// `SQLITE_MISUSE` otherwise.
+/// See http://www.sqlite.org/errlog.html
+pub fn install_logger() -> SqliteResult<()> {
+    let mut installed = LOGGER_INSTALLED.lock().unwrap();
+    if *installed {
+        return Ok(());
+    }
+    let r = unsafe {
+        sqlite3_config(SQLITE_CONFIG_LOG,
+                       log_callback as extern "C" fn(*mut c_void, c_int, *const c_char),
+                       ptr::null_mut::<c_void>())
+    };
+    if r == SQLITE_OK {
+        *installed = true;
+        Ok(())
+    } else {
+        Err(r)
+    }
+}
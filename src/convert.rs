@@ -0,0 +1,365 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Conversions between Rust types and SQLite values.
+//!
+//! `FromSql` reads a column of the current row and `ToSql` makes the
+//! `BindArg` a value binds as. `FromRow` and `ToParams` do the same for
+//! whole rows and parameter lists; they can be derived with the
+//! `sqlite3-derive` crate.
+
+use cursor::Cursor;
+use std::error;
+use std::fmt;
+use types::*;
+use types::BindArg::*;
+use types::ColumnType::*;
+use types::ResultCode::*;
+
+/// Why a column could not be converted.
+#[derive(Clone, PartialEq, Debug)]
+pub enum FromSqlError {
+    /// The column has a type the Rust type cannot be read from.
+    InvalidType { expected: &'static str, found: ColumnType },
+    /// The integer does not fit in the Rust type.
+    OutOfRange(i64),
+    Other(String),
+}
+
+impl fmt::Display for FromSqlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FromSqlError::InvalidType { expected, found } => write!(f, "expected {}, found {:?}", expected, found),
+            FromSqlError::OutOfRange(i) => write!(f, "{} is out of range", i),
+            FromSqlError::Other(ref message) => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for FromSqlError {}
+
+/// A type which can be read from a column.
+pub trait FromSql: Sized {
+    /// Reads column `i` of the current row of `cursor`.
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<Self, FromSqlError>;
+}
+
+/// A type which can be bound to a parameter.
+pub trait ToSql {
+    fn to_sql(&self) -> BindArg;
+
+    /// Binds the value to parameter `i` of `cursor`. Text and blobs bind
+    /// from a borrow instead of through `to_sql`.
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        cursor.bind_param(i, &self.to_sql())
+    }
+}
+
+fn expect(cursor: &Cursor, i: isize, expected: &'static str, types: &[ColumnType]) -> Result<(), FromSqlError> {
+    let found = cursor.get_column_type(i);
+    if types.contains(&found) { Ok(()) } else { Err(FromSqlError::InvalidType { expected, found }) }
+}
+
+impl FromSql for i64 {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<i64, FromSqlError> {
+        expect(cursor, i, "INTEGER", &[SQLITE_INTEGER])?;
+        Ok(cursor.get_i64(i))
+    }
+}
+
+macro_rules! integer_from_sql {
+    ($($t:ty)*) => {
+        $(
+            impl FromSql for $t {
+                fn from_sql(cursor: &mut Cursor, i: isize) -> Result<$t, FromSqlError> {
+                    let v = i64::from_sql(cursor, i)?;
+                    if v < <$t>::MIN as i64 || v as i128 > <$t>::MAX as i128 {
+                        return Err(FromSqlError::OutOfRange(v));
+                    }
+                    Ok(v as $t)
+                }
+            }
+        )*
+    }
+}
+
+integer_from_sql! { i8 i16 i32 isize u8 u16 u32 u64 usize }
+
+impl FromSql for f64 {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<f64, FromSqlError> {
+        expect(cursor, i, "REAL", &[SQLITE_FLOAT, SQLITE_INTEGER])?;
+        Ok(cursor.get_f64(i))
+    }
+}
+
+impl FromSql for f32 {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<f32, FromSqlError> {
+        f64::from_sql(cursor, i).map(|v| v as f32)
+    }
+}
+
+impl FromSql for bool {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<bool, FromSqlError> {
+        i64::from_sql(cursor, i).map(|v| v != 0)
+    }
+}
+
+impl FromSql for String {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<String, FromSqlError> {
+        expect(cursor, i, "TEXT", &[SQLITE_TEXT])?;
+        Ok(cursor.get_text(i).unwrap_or("").to_string())
+    }
+}
+
+impl FromSql for Vec<u8> {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<Vec<u8>, FromSqlError> {
+        expect(cursor, i, "BLOB", &[SQLITE_BLOB, SQLITE_TEXT])?;
+        Ok(cursor.get_blob(i).unwrap_or(&[]).to_vec())
+    }
+}
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<Option<T>, FromSqlError> {
+        if cursor.get_column_type(i) == SQLITE_NULL {
+            return Ok(None);
+        }
+        T::from_sql(cursor, i).map(Some)
+    }
+}
+
+impl FromSql for BindArg {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<BindArg, FromSqlError> {
+        Ok(match cursor.get_column_type(i) {
+            SQLITE_INTEGER => Integer64(cursor.get_i64(i)),
+            SQLITE_FLOAT => Float64(cursor.get_f64(i)),
+            SQLITE_TEXT => Text(cursor.get_text(i).unwrap_or("").to_string()),
+            SQLITE_BLOB => Blob(cursor.get_blob(i).unwrap_or(&[]).to_vec()),
+            SQLITE_NULL => Null,
+        })
+    }
+}
+
+macro_rules! integer_to_sql {
+    ($($t:ty)*) => {
+        $(
+            impl ToSql for $t {
+                fn to_sql(&self) -> BindArg {
+                    Integer64(*self as i64)
+                }
+            }
+        )*
+    }
+}
+
+integer_to_sql! { i8 i16 i32 i64 isize u8 u16 u32 bool }
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> BindArg {
+        Float64(*self)
+    }
+}
+
+impl ToSql for f32 {
+    fn to_sql(&self) -> BindArg {
+        Float64(*self as f64)
+    }
+}
+
+impl ToSql for str {
+    fn to_sql(&self) -> BindArg {
+        Text(self.to_string())
+    }
+
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        cursor.bind_text(i, self)
+    }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> BindArg {
+        Text(self.clone())
+    }
+
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        cursor.bind_text(i, self)
+    }
+}
+
+impl ToSql for [u8] {
+    fn to_sql(&self) -> BindArg {
+        Blob(self.to_vec())
+    }
+
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        cursor.bind_blob(i, self)
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn to_sql(&self) -> BindArg {
+        Blob(self.clone())
+    }
+
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        cursor.bind_blob(i, self)
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> BindArg {
+        match *self {
+            Some(ref v) => v.to_sql(),
+            None => Null,
+        }
+    }
+
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        match *self {
+            Some(ref v) => v.bind_param(cursor, i),
+            None => cursor.bind_param(i, &Null),
+        }
+    }
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn to_sql(&self) -> BindArg {
+        (**self).to_sql()
+    }
+
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        (**self).bind_param(cursor, i)
+    }
+}
+
+impl ToSql for BindArg {
+    fn to_sql(&self) -> BindArg {
+        self.clone()
+    }
+
+    fn bind_param(&self, cursor: &mut Cursor, i: isize) -> ResultCode {
+        cursor.bind_param(i, self)
+    }
+}
+
+/// Why a row could not be converted.
+#[derive(Clone, PartialEq, Debug)]
+pub enum FromRowError {
+    /// Stepping the statement failed.
+    Sqlite(ResultCode),
+    MissingColumn(String),
+    Column { column: String, error: FromSqlError },
+}
+
+impl fmt::Display for FromRowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FromRowError::Sqlite(code) => write!(f, "{:?}", code),
+            FromRowError::MissingColumn(ref column) => write!(f, "missing column {}", column),
+            FromRowError::Column { ref column, ref error } => write!(f, "column {}: {}", column, error),
+        }
+    }
+}
+
+impl error::Error for FromRowError {}
+
+/// A type which can be read from a row.
+pub trait FromRow: Sized {
+    /// Reads the current row of `cursor`.
+    fn from_row(cursor: &mut Cursor) -> Result<Self, FromRowError>;
+}
+
+/// A type which can be bound to the parameters of a statement.
+pub trait ToParams {
+    fn bind_to(&self, cursor: &mut Cursor) -> SqliteResult<()>;
+}
+
+/// Reads column `i` of the current row, for `FromRow` implementations.
+pub fn column_from_sql<T: FromSql>(cursor: &mut Cursor, i: isize) -> Result<T, FromRowError> {
+    T::from_sql(cursor, i).map_err(|e| FromRowError::Column { column: cursor.get_column_name(i).to_string(), error: e })
+}
+
+macro_rules! tuple_from_row {
+    ($($t:ident $i:tt),*) => {
+        /// Reads columns by position.
+        impl<$($t: FromSql),*> FromRow for ($($t,)*) {
+            fn from_row(cursor: &mut Cursor) -> Result<($($t,)*), FromRowError> {
+                Ok(($(column_from_sql::<$t>(cursor, $i)?,)*))
+            }
+        }
+    }
+}
+
+tuple_from_row!(A 0);
+tuple_from_row!(A 0, B 1);
+tuple_from_row!(A 0, B 1, C 2);
+tuple_from_row!(A 0, B 1, C 2, D 3);
+tuple_from_row!(A 0, B 1, C 2, D 3, E 4);
+tuple_from_row!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+impl<'db> Cursor<'db> {
+    /// Reads column `i` of the current row.
+    pub fn get<T: FromSql>(&mut self, i: isize) -> Result<T, FromSqlError> {
+        T::from_sql(self, i)
+    }
+
+    /// Returns the index of the column named `name`, ignoring case.
+    pub fn get_column_index(&self, name: &str) -> Option<isize> {
+        self.column_index(name)
+    }
+
+    /// Steps the cursor, reading the next row as a `T` if there is one.
+    pub fn step_row_as<T: FromRow>(&mut self) -> Result<Option<T>, FromRowError> {
+        match self.step() {
+            SQLITE_ROW => T::from_row(self).map(Some),
+            SQLITE_DONE => Ok(None),
+            r => Err(FromRowError::Sqlite(r)),
+        }
+    }
+
+    /// Binds `value` to the parameters named `name` with any prefix
+    /// (':', '@' or '$'). Fails with `SQLITE_RANGE` if there is none.
+    pub fn bind_named<T: ToSql + ?Sized>(&mut self, name: &str, value: &T) -> SqliteResult<()> {
+        let params = self.named_params();
+        let indexes = params.get(name).ok_or(SQLITE_RANGE)?;
+        for &i in indexes {
+            let r = value.bind_param(self, i);
+            if r != SQLITE_OK {
+                return Err(r);
+            }
+        }
+        Ok(())
+    }
+
+    /// Binds the parameters of `params`.
+    pub fn bind_to_params<T: ToParams + ?Sized>(&mut self, params: &T) -> SqliteResult<()> {
+        params.bind_to(self)
+    }
+}
@@ -0,0 +1,341 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A virtual table reading CSV files.
+//!
+//! Once registered with `Database::load_csv_module`, a table is declared as
+//!
+//! ```sql
+//! CREATE VIRTUAL TABLE temp.t USING csv(filename='data.csv', header=yes);
+//! ```
+//!
+//! with the following parameters:
+//!
+//! - `filename`: the file to read, or `data`: the CSV text itself.
+//! - `header`: whether the first row holds column names (`no` by default).
+//! - `schema`: a `CREATE TABLE` statement declaring the columns. By default,
+//!   columns are named after the header, or `c0`, `c1`, ... otherwise.
+//! - `columns`: the number of columns, when neither `schema` nor `header` is given.
+//! - `delimiter` and `quote`: single characters, `,` and `"` by default.
+//!   `delimiter=tab` is accepted for tab separated files.
+//! - `infer_types`: whether fields which look like integers or reals are
+//!   returned as such (`no` by default, returning everything as TEXT).
+//! - `empty_as_null`: whether empty fields are returned as NULL (`no` by default).
+//!
+//! The file is read lazily, row by row, every time the table is scanned.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use database::Database;
+use types::*;
+use types::BindArg::*;
+use vtab::*;
+
+/// A minimal RFC 4180 reader, accepting quoted fields spanning lines.
+struct CsvReader {
+    input: Box<dyn BufRead>,
+    delimiter: u8,
+    quote: u8,
+}
+
+impl CsvReader {
+    fn read_record(&mut self) -> io::Result<Option<Vec<String>>> {
+        let mut line = Vec::new();
+        loop {
+            if self.input.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            // blank lines, e.g. trailing ones, are not records.
+            if line != b"\n" && line != b"\r\n" {
+                break;
+            }
+            line.clear();
+        }
+        let mut fields = Vec::new();
+        let mut field = Vec::new();
+        let mut quoted = false;
+        let mut i = 0;
+        loop {
+            if i == line.len() {
+                if !quoted {
+                    break;
+                }
+                // the quoted field goes on with the next line.
+                line.clear();
+                i = 0;
+                if self.input.read_until(b'\n', &mut line)? == 0 {
+                    break;
+                }
+                continue;
+            }
+            let b = line[i];
+            if quoted {
+                if b == self.quote {
+                    if line.get(i + 1) == Some(&self.quote) {
+                        field.push(b);
+                        i += 1;
+                    } else {
+                        quoted = false;
+                    }
+                } else {
+                    field.push(b);
+                }
+            } else if b == self.quote && field.is_empty() {
+                quoted = true;
+            } else if b == self.delimiter {
+                fields.push(String::from_utf8_lossy(&field).into_owned());
+                field.clear();
+            } else if b == b'\n' || (b == b'\r' && line.get(i + 1) == Some(&b'\n')) {
+                break;
+            } else {
+                field.push(b);
+            }
+            i += 1;
+        }
+        fields.push(String::from_utf8_lossy(&field).into_owned());
+        Ok(Some(fields))
+    }
+}
+
+#[derive(Clone)]
+enum Source {
+    File(String),
+    Data(String),
+}
+
+#[derive(Clone)]
+struct CsvOptions {
+    source: Source,
+    header: bool,
+    delimiter: u8,
+    quote: u8,
+    infer_types: bool,
+    empty_as_null: bool,
+}
+
+impl CsvOptions {
+    fn reader(&self) -> VTabResult<CsvReader> {
+        let input: Box<dyn BufRead> = match self.source {
+            Source::File(ref path) => {
+                let file = File::open(path)
+                    .map_err(|e| VTabError::message(format!("csv: cannot open {}: {}", path, e)))?;
+                Box::new(BufReader::new(file))
+            }
+            Source::Data(ref data) => Box::new(io::Cursor::new(data.clone().into_bytes())),
+        };
+        Ok(CsvReader { input, delimiter: self.delimiter, quote: self.quote })
+    }
+
+    fn value(&self, field: &str) -> BindArg {
+        if field.is_empty() && self.empty_as_null {
+            return Null;
+        }
+        if self.infer_types {
+            if let Ok(v) = field.parse::<i64>() {
+                return Integer64(v);
+            }
+            // `parse` also accepts "inf", "NaN" and the like, which are better left as text.
+            let numeric = field.bytes().any(|b| b.is_ascii_digit()) &&
+                field.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+            if numeric {
+                if let Ok(v) = field.parse::<f64>() {
+                    return Float64(v);
+                }
+            }
+        }
+        Text(field.to_string())
+    }
+}
+
+fn csv_error(e: io::Error) -> VTabError {
+    VTabError::message(format!("csv: read error: {}", e))
+}
+
+/// Removes SQL quotes around a parameter value.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let bytes = value.as_bytes();
+    if bytes.len() >= 2 && (bytes[0] == b'\'' || bytes[0] == b'"') && bytes[bytes.len() - 1] == bytes[0] {
+        let q = &value[..1];
+        value[1..value.len() - 1].replace(&format!("{}{}", q, q), q)
+    } else {
+        value.to_string()
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> VTabResult<bool> {
+    match &*value.to_lowercase() {
+        "yes" | "on" | "true" | "1" => Ok(true),
+        "no" | "off" | "false" | "0" => Ok(false),
+        _ => Err(VTabError::message(format!("csv: invalid value for {}: {}", key, value))),
+    }
+}
+
+fn parse_char(key: &str, value: &str) -> VTabResult<u8> {
+    match value {
+        "tab" | "\\t" => Ok(b'\t'),
+        v if v.len() == 1 => Ok(v.as_bytes()[0]),
+        _ => Err(VTabError::message(format!("csv: {} must be a single character", key))),
+    }
+}
+
+/// The `csv` virtual table.
+pub struct CsvTable {
+    options: CsvOptions,
+    columns: usize,
+}
+
+/// A cursor over a `CsvTable`.
+pub struct CsvCursor {
+    options: CsvOptions,
+    reader: Option<CsvReader>,
+    row: Vec<String>,
+    rowid: i64,
+    eof: bool,
+}
+
+impl VTab for CsvTable {
+    type Aux = ();
+    type Cursor = CsvCursor;
+
+    fn connect(_aux: &(), args: &[&str]) -> VTabResult<(String, CsvTable)> {
+        let mut source = None;
+        let mut schema = None;
+        let mut columns = None;
+        let mut options = CsvOptions {
+            source: Source::Data(String::new()),
+            header: false,
+            delimiter: b',',
+            quote: b'"',
+            infer_types: false,
+            empty_as_null: false,
+        };
+        for arg in &args[3..] {
+            let (key, value) = match arg.find('=') {
+                Some(i) => (arg[..i].trim(), unquote(&arg[i + 1..])),
+                None => return Err(VTabError::message(format!("csv: expected key=value, got {}", arg))),
+            };
+            match key {
+                "filename" => source = Some(Source::File(value)),
+                "data" => source = Some(Source::Data(value)),
+                "schema" => schema = Some(value),
+                "columns" => columns = Some(value.parse::<usize>()
+                    .map_err(|_| VTabError::message(format!("csv: invalid value for columns: {}", value)))?),
+                "header" => options.header = parse_bool(key, &value)?,
+                "infer_types" => options.infer_types = parse_bool(key, &value)?,
+                "empty_as_null" => options.empty_as_null = parse_bool(key, &value)?,
+                "delimiter" => options.delimiter = parse_char(key, &value)?,
+                "quote" => options.quote = parse_char(key, &value)?,
+                _ => return Err(VTabError::message(format!("csv: unknown parameter {}", key))),
+            }
+        }
+        options.source = source.ok_or_else(|| VTabError::message("csv: filename or data is required"))?;
+
+        // only peek at the first row when the schema has to be derived from it.
+        let first = if schema.is_none() && (options.header || columns.is_none()) {
+            options.reader()?.read_record().map_err(csv_error)?.unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let decl_type = if options.infer_types { "" } else { " TEXT" };
+        let names: Vec<String> = if options.header {
+            first
+        } else {
+            (0..columns.unwrap_or(first.len())).map(|i| format!("c{}", i)).collect()
+        };
+        let schema = schema.unwrap_or_else(|| {
+            let cols: Vec<String> = names.iter().map(|n| format!("{}{}", quote_identifier(n), decl_type)).collect();
+            format!("CREATE TABLE x ({})", cols.join(", "))
+        });
+        let columns = columns.unwrap_or(names.len());
+        Ok((schema, CsvTable { options, columns }))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> VTabResult<()> {
+        // only full scans are possible.
+        info.set_estimated_cost(1000000.0);
+        Ok(())
+    }
+
+    fn open(&self) -> VTabResult<CsvCursor> {
+        debug!("`CsvTable.open()`: columns={}", self.columns);
+        Ok(CsvCursor { options: self.options.clone(), reader: None, row: Vec::new(), rowid: 0, eof: true })
+    }
+}
+
+impl VTabCursor for CsvCursor {
+    fn filter(&mut self, _idx_num: i32, _idx_str: Option<&str>, _args: &[Value]) -> VTabResult<()> {
+        let mut reader = self.options.reader()?;
+        if self.options.header {
+            reader.read_record().map_err(csv_error)?;
+        }
+        self.reader = Some(reader);
+        self.rowid = 0;
+        self.eof = false;
+        self.next()
+    }
+
+    fn next(&mut self) -> VTabResult<()> {
+        match self.reader.as_mut().unwrap().read_record().map_err(csv_error)? {
+            Some(row) => {
+                self.row = row;
+                self.rowid += 1;
+            }
+            None => {
+                self.eof = true;
+                self.reader = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.eof
+    }
+
+    fn column(&self, i: i32) -> VTabResult<BindArg> {
+        Ok(match self.row.get(i as usize) {
+            Some(field) => self.options.value(field),
+            None => Null,
+        })
+    }
+
+    fn rowid(&self) -> VTabResult<i64> {
+        Ok(self.rowid)
+    }
+}
+
+impl Database {
+    /// Registers the `csv` virtual table module on this connection.
+    pub fn load_csv_module(&mut self) -> SqliteResult<()> {
+        self.create_module::<CsvTable>("csv", ())
+    }
+}
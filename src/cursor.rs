@@ -29,12 +29,17 @@
 ** POSSIBILITY OF SUCH DAMAGE.
 */
 
+use database::Database;
 use ffi::*;
 use libc::{c_int, c_void, c_char};
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
-use std::mem::transmute;
+use std::marker::PhantomData;
+use std::mem::{self, transmute};
+use std::ptr;
 use std::str;
 use std::fmt;
+use std::rc::Rc;
 use std::slice;
 use std::ffi::{CString, CStr};
 use types::*;
@@ -50,12 +55,24 @@ use types::ResultCode::*;
 /// or by the task-shared reference to `Database` being expired.
 pub struct Cursor<'db> {
     stmt: *mut stmt,
-    _dbh: &'db *mut dbh // make this non-`Send`able
+    _dbh: *mut dbh, // make this non-`Send`able
+    _marker: PhantomData<&'db Database>,
+    /// The parameter indexes by name without prefix, resolved on first use.
+    params: OnceCell<Rc<HashMap<String, Vec<isize>>>>,
+    /// The column indexes by the names they were looked up with.
+    columns: RefCell<HashMap<String, Option<isize>>>,
 }
 
+#[allow(clippy::redundant_field_names)]
 pub fn cursor_with_statement<'db>(stmt: *mut stmt, dbh: &'db *mut dbh) -> Cursor<'db> {
     debug!("`Cursor.cursor_with_statement()`: stmt={:?}", stmt);
-    Cursor { stmt: stmt, _dbh: dbh }
+    Cursor { stmt: stmt, _dbh: *dbh, _marker: PhantomData, params: OnceCell::new(), columns: RefCell::new(HashMap::new()) }
+}
+
+/// Wraps a statement in a cursor of an arbitrary lifetime. The caller
+/// must ensure that the connection outlives the cursor.
+pub(crate) unsafe fn cursor_from_raw<'db>(stmt: *mut stmt, dbh: *mut dbh) -> Cursor<'db> {
+    Cursor { stmt, _dbh: dbh, _marker: PhantomData, params: OnceCell::new(), columns: RefCell::new(HashMap::new()) }
 }
 
 impl<'db> fmt::Debug for Cursor<'db> {
@@ -75,8 +92,19 @@ impl<'db> Drop for Cursor<'db> {
     }
 }
 
+#[allow(clippy::needless_return, clippy::needless_lifetimes, clippy::empty_docs, clippy::explicit_counter_loop)]
 impl<'db> Cursor<'db> {
 
+    pub(crate) fn stmt(&self) -> *mut stmt {
+        self.stmt
+    }
+
+    /// Releases the statement without finalizing it.
+    pub(crate) fn into_raw(mut self) -> *mut stmt {
+        // finalizing a null statement is a no-op.
+        mem::replace(&mut self.stmt, ptr::null_mut())
+    }
+
     /// Resets a prepared SQL statement, but does not reset its bindings.
     /// See http://www.sqlite.org/c3ref/reset.html
     pub fn reset(&mut self) -> ResultCode {
@@ -155,7 +183,7 @@ impl<'db> Cursor<'db> {
     /// See http://www.sqlite.org/c3ref/column_blob.html
     pub fn get_i64(&mut self, i: isize) -> i64 {
         unsafe {
-            return sqlite3_column_int64(self.stmt, i as c_int) as i64;
+            return sqlite3_column_int64(self.stmt, i as c_int);
         }
     }
 
@@ -189,6 +217,27 @@ impl<'db> Cursor<'db> {
         }
     }
 
+    /// Returns the largest parameter index of the statement.
+    /// See http://www.sqlite.org/c3ref/bind_parameter_count.html
+    pub fn get_bind_count(&self) -> isize {
+        unsafe {
+            return sqlite3_bind_parameter_count(self.stmt) as isize;
+        }
+    }
+
+    /// Returns the name of the parameter with index `i`, including its
+    /// prefix, e.g. ":name", or `None` for positional parameters.
+    /// See http://www.sqlite.org/c3ref/bind_parameter_name.html
+    pub fn get_bind_name(&self, i: isize) -> Option<String> {
+        unsafe {
+            let name = sqlite3_bind_parameter_name(self.stmt, i as c_int);
+            if name.is_null() {
+                return None;
+            }
+            return Some(CStr::from_ptr(name).to_string_lossy().into_owned());
+        }
+    }
+
     /// Returns the number of columns in a result set.
     /// See http://www.sqlite.org/c3ref/data_count.html
     pub fn get_column_count(&self) -> isize {
@@ -220,7 +269,7 @@ impl<'db> Cursor<'db> {
             3 /* SQLITE_TEXT    */ => SQLITE_TEXT,
             4 /* SQLITE_BLOB    */ => SQLITE_BLOB,
             5 /* SQLITE_NULL    */ => SQLITE_NULL,
-            _ => panic!(format!("sqlite internal error: Got an unknown column type ({}) back from the library.", ct)),
+            _ => panic!("sqlite internal error: Got an unknown column type ({}) back from the library.", ct),
         };
         return res;
     }
@@ -259,20 +308,8 @@ impl<'db> Cursor<'db> {
 
         let r = match *value {
             Text(ref v) => {
-                let l = v.len();
-                debug!("  `Text`: v={}, l={}", v, l);
-
-                let v = CString::new(v.as_bytes()).unwrap();
-                unsafe {
-                    // FIXME: do not copy the data
-                    sqlite3_bind_text(
-                          self.stmt   // the SQL statement
-                        , i as c_int  // the SQL parameter index (starting from 1)
-                        , v.as_ptr()  // the value to bind
-                        , l as c_int  // the number of bytes
-                        , -1isize as *mut c_void// SQLITE_TRANSIENT => SQLite makes a copy
-                        )
-                }
+                debug!("  `Text`: v={}, l={}", v, v.len());
+                self.bind_text(i, v)
             }
 
             StaticText(ref v) => {
@@ -288,26 +325,15 @@ impl<'db> Cursor<'db> {
                             , i as c_int  // the SQL parameter index (starting from 1)
                             , _v.as_ptr() as *const c_char // the value to bind
                             , l as c_int  // the number of bytes
-                            , 0 as *mut c_void// SQLITE_STATIC
+                            , ptr::null_mut() // SQLITE_STATIC
                             )
                     }
                 }
             }
 
             Blob(ref v) => {
-                let l = v.len();
-                debug!("`Blob`: v={:?}, l={}", v, l);
-
-                unsafe {
-                    // FIXME: do not copy the data
-                    sqlite3_bind_blob(
-                          self.stmt   // the SQL statement
-                        , i as c_int  // the SQL parameter index (starting from 1)
-                        , v.as_ptr()  // the value to bind
-                        , l as c_int  // the number of bytes
-                        , -1isize as *mut c_void // SQLITE_TRANSIENT => SQLite makes a copy
-                        )
-                }
+                debug!("`Blob`: v={:?}, l={}", v, v.len());
+                self.bind_blob(i, v)
             }
 
             Integer(ref v) => { unsafe { sqlite3_bind_int(self.stmt, i as c_int, *v as c_int) } }
@@ -323,3 +349,57 @@ impl<'db> Cursor<'db> {
         return r;
     }
 }
+
+impl<'db> Cursor<'db> {
+    /// Binds a copy of `value` to parameter `i`.
+    /// See http://www.sqlite.org/c3ref/bind_blob.html
+    pub fn bind_text(&mut self, i: isize, value: &str) -> ResultCode {
+        if value.len() > c_int::MAX as usize {
+            return SQLITE_TOOBIG;
+        }
+        unsafe {
+            sqlite3_bind_text(self.stmt, i as c_int, value.as_ptr() as *const c_char, value.len() as c_int,
+                              -1isize as *mut c_void) // SQLITE_TRANSIENT
+        }
+    }
+
+    /// Binds a copy of `value` to parameter `i`.
+    /// See http://www.sqlite.org/c3ref/bind_blob.html
+    pub fn bind_blob(&mut self, i: isize, value: &[u8]) -> ResultCode {
+        if value.len() > c_int::MAX as usize {
+            return SQLITE_TOOBIG;
+        }
+        unsafe {
+            sqlite3_bind_blob(self.stmt, i as c_int, value.as_ptr(), value.len() as c_int,
+                              -1isize as *mut c_void) // SQLITE_TRANSIENT
+        }
+    }
+
+    /// The parameters of the statement by name without their ':', '@' or
+    /// '$' prefix, listed once per statement.
+    pub(crate) fn named_params(&self) -> Rc<HashMap<String, Vec<isize>>> {
+        self.params.get_or_init(|| {
+            let mut params: HashMap<String, Vec<isize>> = HashMap::new();
+            for i in 1..=self.get_bind_count() {
+                if let Some(name) = self.get_bind_name(i) {
+                    if let Some(name) = name.strip_prefix([':', '@', '$']) {
+                        params.entry(name.to_string()).or_default().push(i);
+                    }
+                }
+            }
+            Rc::new(params)
+        }).clone()
+    }
+
+    /// The index of the column named `name` ignoring case, which is only
+    /// searched for the first time `name` is looked up.
+    pub(crate) fn column_index(&self, name: &str) -> Option<isize> {
+        if let Some(&i) = self.columns.borrow().get(name) {
+            return i;
+        }
+        let count = unsafe { sqlite3_column_count(self.stmt) } as isize;
+        let i = (0..count).find(|&i| self.get_column_name(i).eq_ignore_ascii_case(name));
+        self.columns.borrow_mut().insert(name.to_string(), i);
+        i
+    }
+}
@@ -29,14 +29,19 @@
 ** POSSIBILITY OF SUCH DAMAGE.
 */
 
+use busy::BusyHook;
+use cache::{self, StatementCache};
 use cursor::*;
 use ffi::*;
 use libc::c_int;
+use std::cell::RefCell;
+use std::mem;
 use std::str;
 use std::ptr;
 use std::fmt;
 use std::borrow::ToOwned;
 use std::ffi::{CString, CStr};
+use trace::TraceHook;
 use types::*;
 use types::ResultCode::*;
 
@@ -47,13 +52,22 @@ use types::ResultCode::*;
 /// (as it grants an exclusive access to the connection)
 /// but cannot be shared through `std::sync::RWLock`.
 pub struct Database {
-    dbh: *mut dbh,
+    pub(crate) dbh: *mut dbh,
+    pub(crate) trace_hook: Option<Box<TraceHook>>,
+    pub(crate) busy_hook: Option<Box<BusyHook>>,
+    pub(crate) stmt_cache: RefCell<StatementCache>,
 }
 
 unsafe impl Send for Database {}
 
+#[allow(clippy::redundant_field_names)]
 pub fn database_with_handle(dbh: *mut dbh) -> Database {
-    Database { dbh: dbh }
+    Database {
+        dbh: dbh,
+        trace_hook: None,
+        busy_hook: None,
+        stmt_cache: RefCell::new(StatementCache::default()),
+    }
 }
 
 impl fmt::Debug for Database {
@@ -64,11 +78,19 @@ impl fmt::Debug for Database {
 
 impl Drop for Database {
     /// Closes the database connection.
+    /// Registered callbacks are released after the connection is closed,
+    /// so they still observe any final events (e.g. `TraceEvent::Close`).
+    /// If the connection cannot be closed because a statement is still
+    /// alive, it stays open and its callbacks are leaked rather than freed.
     /// See http://www.sqlite.org/c3ref/close.html
     fn drop(&mut self) {
         debug!("`Database.drop()`: self={:?}", *self);
-        unsafe {
-            sqlite3_close(self.dbh);
+        self.flush_cache();
+        let r = unsafe { sqlite3_close(self.dbh) };
+        if r != SQLITE_OK {
+            warn!("`Database.drop()`: sqlite3_close failed ({:?}), leaking callbacks", r);
+            mem::forget(self.trace_hook.take());
+            mem::forget(self.busy_hook.take());
         }
     }
 }
@@ -100,9 +122,13 @@ impl Database {
         }
     }
 
-    /// Executes an SQL statement.
+    /// Executes an SQL statement. The statement cache is flushed first if
+    /// `sql` may change the schema.
     /// See http://www.sqlite.org/c3ref/exec.html
-    pub fn exec(&mut self, sql: &str) -> SqliteResult<bool> {
+    pub fn exec(&self, sql: &str) -> SqliteResult<bool> {
+        if cache::changes_schema(sql) {
+            self.flush_cache();
+        }
         let sql = CString::new(sql.as_bytes()).unwrap();
         let r = unsafe {
             sqlite3_exec(self.dbh, sql.as_ptr(), ptr::null_mut(), ptr::null_mut(), ptr::null_mut())
@@ -128,11 +154,15 @@ impl Database {
         }
     }
 
-    /// Sets a busy timeout.
+    /// Sets a busy timeout, replacing any busy handler.
     /// See http://www.sqlite.org/c3ref/busy_timeout.html
     pub fn set_busy_timeout(&mut self, ms: isize) -> ResultCode {
-        unsafe {
+        let r = unsafe {
             sqlite3_busy_timeout(self.dbh, ms as c_int)
+        };
+        if r == SQLITE_OK {
+            self.busy_hook = None;
         }
+        r
     }
 }
@@ -0,0 +1,476 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Date and time conversions.
+//!
+//! SQLite has no date type; its date functions take ISO-8601 TEXT, unix
+//! time as an INTEGER or a julian day number as a REAL. Reading a date
+//! accepts any of the three. Writing stores TEXT in the format of SQLite's
+//! `datetime()` with nanoseconds, `YYYY-MM-DD HH:MM:SS.SSSSSSSSS`, unless the
+//! value is wrapped in `UnixTime` or `JulianDay`. UTC and zoneless values
+//! sort in time order as text. Years outside 0000-9999, which the format
+//! cannot hold, are stored as unix time instead.
+//!
+//! `SystemTime` is always supported. The `chrono` feature adds
+//! `DateTime<Utc>`, `NaiveDateTime` and `NaiveDate`, and the `time` feature
+//! adds `OffsetDateTime`, stored as UTC unless wrapped in `WithOffset`.
+//! See http://www.sqlite.org/lang_datefunc.html
+
+use convert::*;
+use cursor::Cursor;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use types::BindArg;
+use types::BindArg::*;
+use types::ColumnType::*;
+
+/// The unix epoch as a julian day number.
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+
+/// A point in time, as stored in a column.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timestamp {
+    /// Seconds from the unix epoch to the wall-clock time, ignoring `offset`.
+    pub local: i64,
+    pub nanos: u32,
+    /// The offset from UTC in seconds, or `None` for a time without a zone,
+    /// which SQLite takes to be UTC.
+    pub offset: Option<i32>,
+}
+
+impl Timestamp {
+    /// Makes a UTC timestamp from unix time.
+    pub fn from_unix_time(secs: i64, nanos: u32) -> Timestamp {
+        Timestamp { local: secs, nanos, offset: Some(0) }
+    }
+
+    /// Makes a UTC timestamp from a julian day number, to the millisecond.
+    pub fn from_julian_day(day: f64) -> Timestamp {
+        let ms = ((day - UNIX_EPOCH_JULIAN_DAY) * 86_400_000.0).round() as i64;
+        Timestamp::from_unix_time(ms.div_euclid(1000), ms.rem_euclid(1000) as u32 * 1_000_000)
+    }
+
+    /// Parses `YYYY-MM-DD`, optionally followed by `T` or a space,
+    /// `HH:MM[:SS[.SSS]]` and a `Z` or `[+-]HH:MM` zone.
+    pub fn parse(text: &str) -> Option<Timestamp> {
+        let mut p = Parser { bytes: text.trim().as_bytes(), pos: 0 };
+        let year = p.digits(4)?;
+        p.expect(b'-')?;
+        let month = p.digits(2)?;
+        p.expect(b'-')?;
+        let day = p.digits(2)?;
+        if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+            return None;
+        }
+        let mut ts = Timestamp { local: days_from_civil(year, month, day) * 86_400, nanos: 0, offset: None };
+        if p.at_end() {
+            return Some(ts);
+        }
+        if !p.eat(b'T') && !p.eat(b' ') {
+            return None;
+        }
+        let hour = p.digits(2)?;
+        p.expect(b':')?;
+        let minute = p.digits(2)?;
+        let mut second = 0;
+        if p.eat(b':') {
+            second = p.digits(2)?;
+            if p.eat(b'.') {
+                ts.nanos = p.fraction()?;
+            }
+        }
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        ts.local += hour * 3600 + minute * 60 + second;
+        while p.eat(b' ') {}
+        if p.eat(b'Z') || p.eat(b'z') {
+            ts.offset = Some(0);
+        } else if p.peek() == Some(b'+') || p.peek() == Some(b'-') {
+            let sign = if p.eat(b'-') { -1 } else { p.eat(b'+'); 1 };
+            let hours = p.digits(2)?;
+            p.eat(b':');
+            let minutes = p.digits(2)?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            ts.offset = Some(sign * (hours * 3600 + minutes * 60) as i32);
+        }
+        if p.at_end() { Some(ts) } else { None }
+    }
+
+    /// Returns the unix time in whole seconds.
+    pub fn unix_time(&self) -> i64 {
+        self.local - self.offset.unwrap_or(0) as i64
+    }
+
+    /// Returns the julian day number.
+    pub fn julian_day(&self) -> f64 {
+        UNIX_EPOCH_JULIAN_DAY + (self.unix_time() as f64 + self.nanos as f64 / 1e9) / 86_400.0
+    }
+
+    /// Formats the local date as `YYYY-MM-DD`.
+    pub fn to_date_string(&self) -> String {
+        let (year, month, day) = civil_from_days(self.local.div_euclid(86_400));
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    /// Returns the same time with the offset truncated to whole minutes, the
+    /// precision of the text format, and the seconds dropped from it moved
+    /// into the local time.
+    fn to_whole_minute_offset(self) -> Timestamp {
+        match self.offset {
+            Some(offset) if offset % 60 != 0 => {
+                Timestamp { local: self.local - (offset % 60) as i64, offset: Some(offset - offset % 60), ..self }
+            }
+            _ => self,
+        }
+    }
+
+    /// Whether the local year fits the four digits of the text format.
+    fn has_text_year(&self) -> bool {
+        let (year, _, _) = civil_from_days(self.to_whole_minute_offset().local.div_euclid(86_400));
+        (0..=9999).contains(&year)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// Formats the timestamp like `2024-02-29 12:34:56.500000000`, always
+    /// with nine fractional digits so that the text sorts like the time.
+    /// An offset other than UTC follows as `+HH:MM` or `-HH:MM`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ts = self.to_whole_minute_offset();
+        let secs = ts.local.rem_euclid(86_400);
+        write!(f, "{} {:02}:{:02}:{:02}.{:09}", ts.to_date_string(), secs / 3600, secs / 60 % 60, secs % 60,
+               ts.nanos)?;
+        match ts.offset {
+            None | Some(0) => Ok(()),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.abs() / 60;
+                write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Option<()> {
+        if self.eat(b) { Some(()) } else { None }
+    }
+
+    fn digits(&mut self, n: usize) -> Option<i64> {
+        let mut v = 0;
+        for _ in 0..n {
+            match self.peek() {
+                Some(b @ b'0'..=b'9') => v = v * 10 + (b - b'0') as i64,
+                _ => return None,
+            }
+            self.pos += 1;
+        }
+        Some(v)
+    }
+
+    /// Reads the digits of a fraction of a second as nanoseconds; digits
+    /// past the ninth are dropped.
+    fn fraction(&mut self) -> Option<u32> {
+        let start = self.pos;
+        let mut nanos = 0;
+        let mut scale = 100_000_000;
+        while let Some(b @ b'0'..=b'9') = self.peek() {
+            nanos += (b - b'0') as u32 * scale;
+            scale /= 10;
+            self.pos += 1;
+        }
+        if self.pos == start { None } else { Some(nanos) }
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from the unix epoch to a date of the proleptic Gregorian calendar.
+/// See http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if month <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, month, day)
+}
+
+/// Reads column `i` as a timestamp from TEXT, INTEGER or REAL.
+pub fn timestamp_from_sql(cursor: &mut Cursor, i: isize) -> Result<Timestamp, FromSqlError> {
+    match cursor.get_column_type(i) {
+        SQLITE_INTEGER => Ok(Timestamp::from_unix_time(cursor.get_i64(i), 0)),
+        SQLITE_FLOAT => Ok(Timestamp::from_julian_day(cursor.get_f64(i))),
+        SQLITE_TEXT => {
+            let text = cursor.get_text(i).unwrap_or("");
+            Timestamp::parse(text).ok_or_else(|| FromSqlError::Other(format!("invalid date and time {:?}", text)))
+        }
+        found => Err(FromSqlError::InvalidType { expected: "TEXT, INTEGER or REAL", found }),
+    }
+}
+
+fn out_of_range() -> FromSqlError {
+    FromSqlError::Other("date and time out of range".to_string())
+}
+
+/// A date or time type, which can be read from and written to a column
+/// through a `Timestamp`.
+pub trait TimeValue: Sized {
+    fn to_timestamp(&self) -> Timestamp;
+
+    fn from_timestamp(ts: Timestamp) -> Result<Self, FromSqlError>;
+
+    /// Returns the text the value is stored as by default.
+    fn to_iso8601(&self) -> String {
+        self.to_timestamp().to_string()
+    }
+}
+
+macro_rules! time_value_sql {
+    ($($t:ty),*) => {
+        $(
+            impl FromSql for $t {
+                fn from_sql(cursor: &mut Cursor, i: isize) -> Result<$t, FromSqlError> {
+                    timestamp_from_sql(cursor, i).and_then(<$t as TimeValue>::from_timestamp)
+                }
+            }
+
+            impl ToSql for $t {
+                fn to_sql(&self) -> BindArg {
+                    let ts = self.to_timestamp();
+                    if ts.has_text_year() { Text(self.to_iso8601()) } else { Integer64(ts.unix_time()) }
+                }
+            }
+        )*
+    }
+}
+
+/// Stores the wrapped value as unix time, in whole seconds.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct UnixTime<T>(pub T);
+
+impl<T: TimeValue> FromSql for UnixTime<T> {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<UnixTime<T>, FromSqlError> {
+        timestamp_from_sql(cursor, i).and_then(T::from_timestamp).map(UnixTime)
+    }
+}
+
+impl<T: TimeValue> ToSql for UnixTime<T> {
+    fn to_sql(&self) -> BindArg {
+        Integer64(self.0.to_timestamp().unix_time())
+    }
+}
+
+/// Stores the wrapped value as a julian day number.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub struct JulianDay<T>(pub T);
+
+impl<T: TimeValue> FromSql for JulianDay<T> {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<JulianDay<T>, FromSqlError> {
+        timestamp_from_sql(cursor, i).and_then(T::from_timestamp).map(JulianDay)
+    }
+}
+
+impl<T: TimeValue> ToSql for JulianDay<T> {
+    fn to_sql(&self) -> BindArg {
+        Float64(self.0.to_timestamp().julian_day())
+    }
+}
+
+impl TimeValue for SystemTime {
+    fn to_timestamp(&self) -> Timestamp {
+        match self.duration_since(UNIX_EPOCH) {
+            Ok(d) => Timestamp::from_unix_time(d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                match d.subsec_nanos() {
+                    0 => Timestamp::from_unix_time(-(d.as_secs() as i64), 0),
+                    n => Timestamp::from_unix_time(-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+                }
+            }
+        }
+    }
+
+    fn from_timestamp(ts: Timestamp) -> Result<SystemTime, FromSqlError> {
+        let secs = ts.unix_time();
+        let t = if secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+        };
+        t.and_then(|t| t.checked_add(Duration::from_nanos(ts.nanos as u64))).ok_or_else(out_of_range)
+    }
+}
+
+time_value_sql!(SystemTime);
+
+#[cfg(feature = "chrono")]
+mod chrono_impls {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+    use convert::*;
+    use cursor::Cursor;
+    use super::*;
+    use types::BindArg;
+
+    impl TimeValue for DateTime<Utc> {
+        fn to_timestamp(&self) -> Timestamp {
+            Timestamp::from_unix_time(self.timestamp(), self.timestamp_subsec_nanos())
+        }
+
+        fn from_timestamp(ts: Timestamp) -> Result<DateTime<Utc>, FromSqlError> {
+            DateTime::from_timestamp(ts.unix_time(), ts.nanos).ok_or_else(out_of_range)
+        }
+    }
+
+    /// Naive values are stored without a zone; on reading, any zone is
+    /// dropped and the wall-clock time kept.
+    impl TimeValue for NaiveDateTime {
+        fn to_timestamp(&self) -> Timestamp {
+            let utc = self.and_utc();
+            Timestamp { local: utc.timestamp(), nanos: utc.timestamp_subsec_nanos(), offset: None }
+        }
+
+        fn from_timestamp(ts: Timestamp) -> Result<NaiveDateTime, FromSqlError> {
+            DateTime::from_timestamp(ts.local, ts.nanos).map(|t| t.naive_utc()).ok_or_else(out_of_range)
+        }
+    }
+
+    impl TimeValue for NaiveDate {
+        fn to_timestamp(&self) -> Timestamp {
+            self.and_time(Default::default()).to_timestamp()
+        }
+
+        fn from_timestamp(ts: Timestamp) -> Result<NaiveDate, FromSqlError> {
+            <NaiveDateTime as TimeValue>::from_timestamp(ts).map(|t| t.date())
+        }
+
+        fn to_iso8601(&self) -> String {
+            self.to_timestamp().to_date_string()
+        }
+    }
+
+    time_value_sql!(DateTime<Utc>, NaiveDateTime, NaiveDate);
+}
+
+#[cfg(feature = "time")]
+pub use self::time_impls::WithOffset;
+
+#[cfg(feature = "time")]
+mod time_impls {
+    use convert::*;
+    use cursor::Cursor;
+    use super::*;
+    use time::{OffsetDateTime, UtcOffset};
+    use types::BindArg;
+
+    /// Stored as UTC, so that the text sorts in time order. Read with the
+    /// offset of the text, or as UTC if it has no zone.
+    impl TimeValue for OffsetDateTime {
+        fn to_timestamp(&self) -> Timestamp {
+            Timestamp::from_unix_time(self.unix_timestamp(), self.nanosecond())
+        }
+
+        fn from_timestamp(ts: Timestamp) -> Result<OffsetDateTime, FromSqlError> {
+            let offset = UtcOffset::from_whole_seconds(ts.offset.unwrap_or(0)).map_err(|_| out_of_range())?;
+            OffsetDateTime::from_unix_timestamp(ts.unix_time()).ok()
+                .and_then(|t| t.replace_nanosecond(ts.nanos).ok())
+                .and_then(|t| t.checked_to_offset(offset))
+                .ok_or_else(out_of_range)
+        }
+    }
+
+    /// Stores the wrapped time with its offset, e.g. `... 09:04:56.000000000-03:30`.
+    /// Such text no longer sorts in time order against other offsets.
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+    pub struct WithOffset(pub OffsetDateTime);
+
+    impl TimeValue for WithOffset {
+        fn to_timestamp(&self) -> Timestamp {
+            let offset = self.0.offset().whole_seconds();
+            Timestamp { local: self.0.unix_timestamp() + offset as i64, nanos: self.0.nanosecond(), offset: Some(offset) }
+        }
+
+        fn from_timestamp(ts: Timestamp) -> Result<WithOffset, FromSqlError> {
+            OffsetDateTime::from_timestamp(ts).map(WithOffset)
+        }
+    }
+
+    time_value_sql!(OffsetDateTime, WithOffset);
+}
@@ -0,0 +1,393 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Deserializing rows into Rust types with serde, available with the
+//! `serde` feature.
+//!
+//! A row deserializes as a struct or map keyed by column names, or as a
+//! tuple or sequence of its values. Rows with a single column can also be
+//! deserialized as a plain value. JSON columns are deserialized with the
+//! `json` helper: `#[serde(deserialize_with = "sqlite3::de::json")]`.
+
+use cursor::Cursor;
+use serde::de::{self, Deserialize, DeserializeOwned, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::de::value::BorrowedStrDeserializer;
+use serde_json;
+use std::error;
+use std::fmt;
+use std::marker::PhantomData;
+use types::*;
+use types::BindArg::*;
+use types::ColumnType::*;
+use types::ResultCode::*;
+
+/// Why a row could not be deserialized.
+#[derive(Clone, PartialEq, Debug)]
+pub enum DeserializeError {
+    /// Stepping the statement failed.
+    Sqlite(ResultCode),
+    /// The type has a field with no column of the same name.
+    MissingColumn(String),
+    /// The row has columns matching no field of the type.
+    ExtraColumns(Vec<String>),
+    /// The value of a column does not fit the field.
+    Column { column: String, message: String },
+    Message(String),
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeserializeError::Sqlite(code) => write!(f, "{:?}", code),
+            DeserializeError::MissingColumn(ref column) => write!(f, "missing column {}", column),
+            DeserializeError::ExtraColumns(ref columns) => write!(f, "unexpected columns {}", columns.join(", ")),
+            DeserializeError::Column { ref column, ref message } => write!(f, "column {}: {}", column, message),
+            DeserializeError::Message(ref message) => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for DeserializeError {}
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(msg: T) -> DeserializeError {
+        DeserializeError::Message(msg.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> DeserializeError {
+        DeserializeError::MissingColumn(field.to_string())
+    }
+}
+
+impl From<ResultCode> for DeserializeError {
+    fn from(code: ResultCode) -> DeserializeError {
+        DeserializeError::Sqlite(code)
+    }
+}
+
+/// A row read from a cursor, with its column names.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Row {
+    columns: Vec<String>,
+    values: Vec<BindArg>,
+}
+
+impl Row {
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[BindArg] {
+        &self.values
+    }
+
+    /// The value of the column `name`.
+    pub fn get(&self, name: &str) -> Option<&BindArg> {
+        self.columns.iter().position(|c| c == name).map(|i| &self.values[i])
+    }
+
+    /// Deserializes the row. Text and blobs can be borrowed from it.
+    pub fn deserialize<'de, T: Deserialize<'de>>(&'de self) -> Result<T, DeserializeError> {
+        T::deserialize(RowDeserializer { row: self })
+    }
+}
+
+impl<'db> Cursor<'db> {
+    /// Steps the cursor, copying the next row if there is one.
+    pub fn next_row(&mut self) -> SqliteResult<Option<Row>> {
+        match self.step() {
+            SQLITE_ROW => (),
+            SQLITE_DONE => return Ok(None),
+            r => return Err(r),
+        }
+        let count = self.get_column_count();
+        let values = (0..count).map(|i| match self.get_column_type(i) {
+            SQLITE_INTEGER => Integer64(self.get_i64(i)),
+            SQLITE_FLOAT => Float64(self.get_f64(i)),
+            SQLITE_TEXT => Text(self.get_text(i).unwrap_or("").to_string()),
+            SQLITE_BLOB => Blob(self.get_blob(i).unwrap_or(&[]).to_vec()),
+            SQLITE_NULL => Null,
+        }).collect();
+        Ok(Some(Row { columns: self.get_column_names(), values }))
+    }
+
+    /// Iterates over the remaining rows, deserialized as `T`.
+    pub fn deserialize_rows<T: DeserializeOwned>(&mut self) -> DeserializeRows<'_, 'db, T> {
+        DeserializeRows { cursor: self, done: false, _marker: PhantomData }
+    }
+}
+
+/// The iterator returned by `Cursor::deserialize_rows`.
+pub struct DeserializeRows<'c, 'db: 'c, T> {
+    cursor: &'c mut Cursor<'db>,
+    done: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'c, 'db, T: DeserializeOwned> Iterator for DeserializeRows<'c, 'db, T> {
+    type Item = Result<T, DeserializeError>;
+
+    fn next(&mut self) -> Option<Result<T, DeserializeError>> {
+        if self.done {
+            return None;
+        }
+        match self.cursor.next_row() {
+            Ok(Some(row)) => Some(row.deserialize()),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(code) => {
+                self.done = true;
+                Some(Err(DeserializeError::Sqlite(code)))
+            }
+        }
+    }
+}
+
+/// Deserializes a TEXT column holding JSON, for use with
+/// `#[serde(deserialize_with = "sqlite3::de::json")]`.
+pub fn json<'de, D: Deserializer<'de>, T: DeserializeOwned>(d: D) -> Result<T, D::Error> {
+    let text = String::deserialize(d)?;
+    serde_json::from_str(&text).map_err(de::Error::custom)
+}
+
+/// Like `json`, for nullable columns.
+pub fn json_option<'de, D: Deserializer<'de>, T: DeserializeOwned>(d: D) -> Result<Option<T>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(text) => serde_json::from_str(&text).map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+struct RowDeserializer<'de> {
+    row: &'de Row,
+}
+
+impl<'de> RowDeserializer<'de> {
+    fn single(&self) -> Result<ValueDeserializer<'de>, DeserializeError> {
+        if self.row.values.len() != 1 {
+            return Err(DeserializeError::Message(format!("expected a single column, got {}", self.row.values.len())));
+        }
+        Ok(ValueDeserializer { value: &self.row.values[0] })
+    }
+}
+
+macro_rules! single_column {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    }
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer<'de> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        visitor.visit_map(RowAccess { row: self.row, i: 0 })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V)
+                                           -> Result<V::Value, DeserializeError> {
+        let extra: Vec<String> = self.row.columns.iter().filter(|c| !fields.contains(&&***c)).cloned().collect();
+        if !extra.is_empty() {
+            return Err(DeserializeError::ExtraColumns(extra));
+        }
+        visitor.visit_map(RowAccess { row: self.row, i: 0 })
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        visitor.visit_map(RowAccess { row: self.row, i: 0 })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        visitor.visit_seq(RowAccess { row: self.row, i: 0 })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, DeserializeError> {
+        if len != self.row.values.len() {
+            return Err(de::Error::invalid_length(self.row.values.len(), &&*format!("{} columns", len)));
+        }
+        visitor.visit_seq(RowAccess { row: self.row, i: 0 })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V)
+                                                 -> Result<V::Value, DeserializeError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                   -> Result<V::Value, DeserializeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V)
+                                         -> Result<V::Value, DeserializeError> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                -> Result<V::Value, DeserializeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        visitor.visit_unit()
+    }
+
+    single_column! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32 deserialize_f64
+        deserialize_char deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+        deserialize_option deserialize_unit deserialize_identifier
+    }
+}
+
+/// Iterates over the columns of a row, as a map or sequence.
+struct RowAccess<'de> {
+    row: &'de Row,
+    i: usize,
+}
+
+impl<'de> RowAccess<'de> {
+    fn value<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, DeserializeError> {
+        let i = self.i;
+        self.i += 1;
+        seed.deserialize(ValueDeserializer { value: &self.row.values[i] }).map_err(|e| match e {
+            DeserializeError::Message(message) => DeserializeError::Column {
+                column: self.row.columns[i].clone(),
+                message,
+            },
+            e => e,
+        })
+    }
+}
+
+impl<'de> MapAccess<'de> for RowAccess<'de> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, DeserializeError> {
+        match self.row.columns.get(self.i) {
+            Some(column) => seed.deserialize(BorrowedStrDeserializer::new(column)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, DeserializeError> {
+        self.value(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.row.values.len() - self.i)
+    }
+}
+
+impl<'de> SeqAccess<'de> for RowAccess<'de> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>, DeserializeError> {
+        if self.i == self.row.values.len() {
+            return Ok(None);
+        }
+        self.value(seed).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.row.values.len() - self.i)
+    }
+}
+
+/// Deserializes a single value according to its SQLite type.
+struct ValueDeserializer<'de> {
+    value: &'de BindArg,
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        match *self.value {
+            Text(ref s) => visitor.visit_borrowed_str(s),
+            StaticText(s) => visitor.visit_borrowed_str(s),
+            Float64(f) => visitor.visit_f64(f),
+            Integer(i) => visitor.visit_i64(i as i64),
+            Integer64(i) => visitor.visit_i64(i),
+            Blob(ref b) => visitor.visit_borrowed_bytes(b),
+            Null => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        match *self.value {
+            Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    /// SQLite stores booleans as integers.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        match *self.value {
+            Integer(i) => visitor.visit_bool(i != 0),
+            Integer64(i) => visitor.visit_bool(i != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        match *self.value {
+            Null => visitor.visit_unit(),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                   -> Result<V::Value, DeserializeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are stored as their name.
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V)
+                                         -> Result<V::Value, DeserializeError> {
+        match *self.value {
+            Text(ref s) => visitor.visit_enum(BorrowedStrDeserializer::new(s)),
+            StaticText(s) => visitor.visit_enum(BorrowedStrDeserializer::new(s)),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
@@ -0,0 +1,127 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Loading extensions from shared libraries.
+//! See http://www.sqlite.org/loadext.html
+
+use ffi::*;
+use libc::{c_int, c_void};
+use std::error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::ptr;
+use database::Database;
+use types::*;
+use types::ResultCode::*;
+
+const SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION: c_int = 1005;
+
+/// Sets whether extensions can be loaded through the C API, returning the
+/// setting in effect, or only returns it if `onoff` is -1.
+pub(crate) fn enable_load_extension(db: &Database, onoff: c_int) -> SqliteResult<bool> {
+    let mut enabled: c_int = 0;
+    let r = unsafe {
+        sqlite3_db_config(db.dbh, SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION, onoff, &mut enabled as *mut c_int)
+    };
+    if r == SQLITE_OK { Ok(enabled != 0) } else { Err(r) }
+}
+
+/// Allows loading extensions with `Database::load_extension` for as long
+/// as it lives, restoring the previous setting when dropped.
+///
+/// The `load_extension()` SQL function stays disabled, so SQL run meanwhile
+/// cannot load libraries.
+/// See http://www.sqlite.org/c3ref/c_dbconfig_defensive.html
+pub struct LoadExtensionGuard<'db> {
+    db: &'db Database,
+    was_enabled: bool,
+}
+
+impl<'db> LoadExtensionGuard<'db> {
+    pub fn new(db: &'db Database) -> SqliteResult<LoadExtensionGuard<'db>> {
+        let was_enabled = enable_load_extension(db, -1)?;
+        enable_load_extension(db, 1)?;
+        Ok(LoadExtensionGuard { db, was_enabled })
+    }
+}
+
+impl<'db> Drop for LoadExtensionGuard<'db> {
+    fn drop(&mut self) {
+        let _ = enable_load_extension(self.db, self.was_enabled as c_int);
+    }
+}
+
+/// Why an extension could not be loaded, as reported by SQLite or by the
+/// extension itself.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LoadExtensionError {
+    pub code: ResultCode,
+    pub message: String,
+}
+
+impl fmt::Display for LoadExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl error::Error for LoadExtensionError {}
+
+impl Database {
+
+    /// Loads the extension in the shared library at `path`, calling
+    /// `entry_point` or the default one derived from the file name.
+    /// Loading through the C API is enabled for the call only, as with
+    /// `LoadExtensionGuard`.
+    /// See http://www.sqlite.org/c3ref/load_extension.html
+    pub fn load_extension(&mut self, path: &str, entry_point: Option<&str>) -> Result<(), LoadExtensionError> {
+        let error = |code| LoadExtensionError { code, message: self.get_errmsg() };
+        let _guard = LoadExtensionGuard::new(self).map_err(error)?;
+        let path = CString::new(path).unwrap();
+        let entry_point = entry_point.map(|e| CString::new(e).unwrap());
+        let mut errmsg = ptr::null_mut();
+        let r = unsafe {
+            sqlite3_load_extension(self.dbh, path.as_ptr(), entry_point.as_ref().map_or(ptr::null(), |e| e.as_ptr()),
+                                   &mut errmsg)
+        };
+        if r == SQLITE_OK {
+            return Ok(());
+        }
+        let message = if errmsg.is_null() {
+            self.get_errmsg()
+        } else {
+            let message = unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().into_owned();
+            unsafe { sqlite3_free(errmsg as *mut c_void); }
+            message
+        };
+        Err(LoadExtensionError { code: r, message })
+    }
+}
@@ -32,15 +32,29 @@
 use libc::*;
 use types::*;
 
+pub type xDestroyFn = extern "C" fn(*mut c_void);
+
 #[link(name = "sqlite3")]
-extern {
+extern "C" {
     pub fn sqlite3_open(path: *const c_char, hnd: *mut *mut dbh) -> ResultCode;
+    pub fn sqlite3_open_v2(path: *const c_char, hnd: *mut *mut dbh, flags: c_int, vfs: *const c_char) -> ResultCode;
     pub fn sqlite3_close(dbh: *mut dbh) -> ResultCode;
     pub fn sqlite3_errmsg(dbh: *mut dbh) -> *const c_char;
     pub fn sqlite3_changes(dbh: *mut dbh) -> c_int;
     pub fn sqlite3_last_insert_rowid(dbh: *mut dbh) -> i64;
+    pub fn sqlite3_get_autocommit(dbh: *mut dbh) -> c_int;
+    pub fn sqlite3_db_status(dbh: *mut dbh, op: c_int, cur: *mut c_int, hiwtr: *mut c_int, reset: c_int)
+                             -> ResultCode;
+    pub fn sqlite3_stmt_status(sth: *mut stmt, op: c_int, reset: c_int) -> c_int;
+    pub fn sqlite3_limit(dbh: *mut dbh, id: c_int, value: c_int) -> c_int;
     pub fn sqlite3_complete(sql: *const c_char) -> c_int;
 
+    pub fn sqlite3_libversion() -> *const c_char;
+    pub fn sqlite3_libversion_number() -> c_int;
+    pub fn sqlite3_sourceid() -> *const c_char;
+    pub fn sqlite3_compileoption_used(name: *const c_char) -> c_int;
+    pub fn sqlite3_compileoption_get(n: c_int) -> *const c_char;
+
     pub fn sqlite3_prepare_v2(
         hnd: *mut dbh,
         sql: *const c_char,
@@ -65,6 +79,7 @@ extern {
     pub fn sqlite3_column_name(sth: *mut stmt, icol: c_int) -> *const c_char;
     pub fn sqlite3_column_type(sth: *mut stmt, icol: c_int) -> c_int;
     pub fn sqlite3_data_count(sth: *mut stmt) -> c_int;
+    pub fn sqlite3_column_count(sth: *mut stmt) -> c_int;
     pub fn sqlite3_column_bytes(sth: *mut stmt, icol: c_int) -> c_int;
     pub fn sqlite3_column_blob(sth: *mut stmt, icol: c_int) -> *const u8;
 
@@ -79,7 +94,203 @@ extern {
     pub fn sqlite3_bind_int(sth: *mut stmt, icol: c_int, v: c_int) -> ResultCode;
     pub fn sqlite3_bind_int64(sth: *mut stmt, icol: c_int, v: i64) -> ResultCode;
     pub fn sqlite3_bind_double(sth: *mut stmt, icol: c_int, value: f64) -> ResultCode;
+    pub fn sqlite3_bind_pointer(sth: *mut stmt, icol: c_int, p: *mut c_void, ty: *const c_char,
+                                d: Option<xDestroyFn>) -> ResultCode;
     pub fn sqlite3_bind_parameter_index(sth: *mut stmt, name: *const c_char) -> c_int;
+    pub fn sqlite3_bind_parameter_count(sth: *mut stmt) -> c_int;
+    pub fn sqlite3_bind_parameter_name(sth: *mut stmt, i: c_int) -> *const c_char;
 
     pub fn sqlite3_busy_timeout(dbh: *mut dbh, ms: c_int) -> ResultCode;
+    pub fn sqlite3_busy_handler(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void, c_int) -> c_int>,
+        ctx: *mut c_void
+    ) -> ResultCode;
+
+    pub fn sqlite3_malloc(n: c_int) -> *mut c_void;
+    pub fn sqlite3_free(p: *mut c_void);
+    pub fn sqlite3_malloc64(n: u64) -> *mut c_void;
+
+    pub fn sqlite3_status64(op: c_int, cur: *mut i64, hiwtr: *mut i64, reset: c_int) -> ResultCode;
+    pub fn sqlite3_memory_used() -> i64;
+    pub fn sqlite3_memory_highwater(reset: c_int) -> i64;
+    pub fn sqlite3_soft_heap_limit64(n: i64) -> i64;
+    pub fn sqlite3_hard_heap_limit64(n: i64) -> i64;
+    pub fn sqlite3_release_memory(n: c_int) -> c_int;
+
+    pub fn sqlite3_db_config(db: *mut dbh, op: c_int, ...) -> ResultCode;
+    pub fn sqlite3_load_extension(db: *mut dbh, file: *const c_char, entry: *const c_char,
+                                  errmsg: *mut *mut c_char) -> ResultCode;
+
+    pub fn sqlite3_db_filename(db: *mut dbh, schema: *const c_char) -> *const c_char;
+    pub fn sqlite3_serialize(db: *mut dbh, schema: *const c_char, size: *mut i64, flags: c_uint) -> *mut u8;
+    pub fn sqlite3_deserialize(db: *mut dbh, schema: *const c_char, data: *mut u8, size: i64,
+                               capacity: i64, flags: c_uint) -> ResultCode;
+
+    pub fn sqlite3_config(op: c_int, ...) -> ResultCode;
+
+    pub fn sqlite3_sql(sth: *mut stmt) -> *const c_char;
+    pub fn sqlite3_expanded_sql(sth: *mut stmt) -> *mut c_char;
+
+    pub fn sqlite3_trace_v2(
+        dbh: *mut dbh,
+        mask: c_uint,
+        cb: Option<extern "C" fn(c_uint, *mut c_void, *mut c_void, *mut c_void) -> c_int>,
+        ctx: *mut c_void
+    ) -> ResultCode;
+
+    pub fn sqlite3_value_type(v: *mut value) -> c_int;
+    pub fn sqlite3_value_int64(v: *mut value) -> i64;
+    pub fn sqlite3_value_double(v: *mut value) -> f64;
+    pub fn sqlite3_value_text(v: *mut value) -> *const c_char;
+    pub fn sqlite3_value_blob(v: *mut value) -> *const u8;
+    pub fn sqlite3_value_bytes(v: *mut value) -> c_int;
+    pub fn sqlite3_value_pointer(v: *mut value, ty: *const c_char) -> *mut c_void;
+
+    pub fn sqlite3_result_null(ctx: *mut context);
+    pub fn sqlite3_result_int64(ctx: *mut context, v: i64);
+    pub fn sqlite3_result_double(ctx: *mut context, v: f64);
+    pub fn sqlite3_result_text(ctx: *mut context, buf: *const c_char, buflen: c_int, d: *mut c_void);
+    pub fn sqlite3_result_blob(ctx: *mut context, buf: *const u8, buflen: c_int, d: *mut c_void);
+
+    pub fn sqlite3_create_module_v2(
+        dbh: *mut dbh,
+        name: *const c_char,
+        module: *const sqlite3_module,
+        client_data: *mut c_void,
+        destroy: Option<xDestroyFn>
+    ) -> ResultCode;
+    pub fn sqlite3_declare_vtab(dbh: *mut dbh, sql: *const c_char) -> ResultCode;
+}
+
+#[repr(C)]
+pub struct sqlite3_vtab {
+    pub pModule: *const sqlite3_module,
+    pub nRef: c_int,
+    pub zErrMsg: *mut c_char,
+}
+
+#[repr(C)]
+pub struct sqlite3_vtab_cursor {
+    pub pVtab: *mut sqlite3_vtab,
+}
+
+#[repr(C)]
+pub struct sqlite3_index_constraint {
+    pub iColumn: c_int,
+    pub op: c_uchar,
+    pub usable: c_uchar,
+    pub iTermOffset: c_int,
+}
+
+#[repr(C)]
+pub struct sqlite3_index_orderby {
+    pub iColumn: c_int,
+    pub desc: c_uchar,
+}
+
+#[repr(C)]
+pub struct sqlite3_index_constraint_usage {
+    pub argvIndex: c_int,
+    pub omit: c_uchar,
+}
+
+#[repr(C)]
+pub struct sqlite3_index_info {
+    pub nConstraint: c_int,
+    pub aConstraint: *mut sqlite3_index_constraint,
+    pub nOrderBy: c_int,
+    pub aOrderBy: *mut sqlite3_index_orderby,
+    pub aConstraintUsage: *mut sqlite3_index_constraint_usage,
+    pub idxNum: c_int,
+    pub idxStr: *mut c_char,
+    pub needToFreeIdxStr: c_int,
+    pub orderByConsumed: c_int,
+    pub estimatedCost: f64,
+    pub estimatedRows: i64,
+    pub idxFlags: c_int,
+    pub colUsed: u64,
+}
+
+pub type xConnectFn = extern "C" fn(*mut dbh, *mut c_void, c_int, *const *const c_char,
+                                    *mut *mut sqlite3_vtab, *mut *mut c_char) -> c_int;
+
+#[repr(C)]
+pub struct sqlite3_module {
+    pub iVersion: c_int,
+    pub xCreate: Option<xConnectFn>,
+    pub xConnect: Option<xConnectFn>,
+    pub xBestIndex: Option<extern "C" fn(*mut sqlite3_vtab, *mut sqlite3_index_info) -> c_int>,
+    pub xDisconnect: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub xDestroy: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub xOpen: Option<extern "C" fn(*mut sqlite3_vtab, *mut *mut sqlite3_vtab_cursor) -> c_int>,
+    pub xClose: Option<extern "C" fn(*mut sqlite3_vtab_cursor) -> c_int>,
+    pub xFilter: Option<extern "C" fn(*mut sqlite3_vtab_cursor, c_int, *const c_char,
+                                      c_int, *mut *mut value) -> c_int>,
+    pub xNext: Option<extern "C" fn(*mut sqlite3_vtab_cursor) -> c_int>,
+    pub xEof: Option<extern "C" fn(*mut sqlite3_vtab_cursor) -> c_int>,
+    pub xColumn: Option<extern "C" fn(*mut sqlite3_vtab_cursor, *mut context, c_int) -> c_int>,
+    pub xRowid: Option<extern "C" fn(*mut sqlite3_vtab_cursor, *mut i64) -> c_int>,
+    pub xUpdate: Option<extern "C" fn(*mut sqlite3_vtab, c_int, *mut *mut value, *mut i64) -> c_int>,
+    pub xBegin: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub xSync: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub xCommit: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub xRollback: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub xFindFunction: Option<extern "C" fn(*mut sqlite3_vtab, c_int, *const c_char,
+                                            *mut *mut c_void, *mut *mut c_void) -> c_int>,
+    pub xRename: Option<extern "C" fn(*mut sqlite3_vtab, *const c_char) -> c_int>,
+    pub xSavepoint: Option<extern "C" fn(*mut sqlite3_vtab, c_int) -> c_int>,
+    pub xRelease: Option<extern "C" fn(*mut sqlite3_vtab, c_int) -> c_int>,
+    pub xRollbackTo: Option<extern "C" fn(*mut sqlite3_vtab, c_int) -> c_int>,
+    pub xShadowName: Option<extern "C" fn(*const c_char) -> c_int>,
+    pub xIntegrity: Option<extern "C" fn(*mut sqlite3_vtab, *const c_char, *const c_char,
+                                         c_int, *mut *mut c_char) -> c_int>,
+}
+
+#[cfg(feature = "session")]
+pub enum session {}
+#[cfg(feature = "session")]
+pub enum changeset_iter {}
+
+#[cfg(feature = "session")]
+pub type xOutputFn = extern "C" fn(*mut c_void, *const c_void, c_int) -> c_int;
+
+#[cfg(feature = "session")]
+#[link(name = "sqlite3")]
+extern "C" {
+    pub fn sqlite3session_create(db: *mut dbh, name: *const c_char, s: *mut *mut session) -> ResultCode;
+    pub fn sqlite3session_delete(s: *mut session);
+    pub fn sqlite3session_attach(s: *mut session, table: *const c_char) -> ResultCode;
+    pub fn sqlite3session_enable(s: *mut session, enable: c_int) -> c_int;
+    pub fn sqlite3session_indirect(s: *mut session, indirect: c_int) -> c_int;
+    pub fn sqlite3session_isempty(s: *mut session) -> c_int;
+    pub fn sqlite3session_changeset(s: *mut session, n: *mut c_int, p: *mut *mut c_void) -> ResultCode;
+    pub fn sqlite3session_patchset(s: *mut session, n: *mut c_int, p: *mut *mut c_void) -> ResultCode;
+    pub fn sqlite3session_changeset_strm(s: *mut session, out: xOutputFn, ctx: *mut c_void) -> ResultCode;
+    pub fn sqlite3session_patchset_strm(s: *mut session, out: xOutputFn, ctx: *mut c_void) -> ResultCode;
+
+    pub fn sqlite3changeset_start(it: *mut *mut changeset_iter, n: c_int, p: *mut c_void) -> ResultCode;
+    pub fn sqlite3changeset_next(it: *mut changeset_iter) -> ResultCode;
+    pub fn sqlite3changeset_op(it: *mut changeset_iter, table: *mut *const c_char, ncol: *mut c_int,
+                               op: *mut c_int, indirect: *mut c_int) -> ResultCode;
+    pub fn sqlite3changeset_pk(it: *mut changeset_iter, pk: *mut *mut u8, ncol: *mut c_int) -> ResultCode;
+    pub fn sqlite3changeset_old(it: *mut changeset_iter, i: c_int, v: *mut *mut value) -> ResultCode;
+    pub fn sqlite3changeset_new(it: *mut changeset_iter, i: c_int, v: *mut *mut value) -> ResultCode;
+    pub fn sqlite3changeset_finalize(it: *mut changeset_iter) -> ResultCode;
+    pub fn sqlite3changeset_invert(n: c_int, p: *const c_void, nout: *mut c_int, pout: *mut *mut c_void)
+                                   -> ResultCode;
+    pub fn sqlite3changeset_concat(na: c_int, pa: *mut c_void, nb: c_int, pb: *mut c_void,
+                                   nout: *mut c_int, pout: *mut *mut c_void) -> ResultCode;
+}
+
+#[cfg(feature = "session")]
+#[link(name = "sqlite3")]
+extern "C" {
+    pub fn sqlite3changeset_conflict(it: *mut changeset_iter, i: c_int, v: *mut *mut value) -> ResultCode;
+    pub fn sqlite3changeset_fk_conflicts(it: *mut changeset_iter, n: *mut c_int) -> ResultCode;
+    pub fn sqlite3changeset_apply_v2(
+        db: *mut dbh, n: c_int, p: *mut c_void,
+        filter: Option<extern "C" fn(*mut c_void, *const c_char) -> c_int>,
+        conflict: Option<extern "C" fn(*mut c_void, c_int, *mut changeset_iter) -> c_int>,
+        ctx: *mut c_void, rebase: *mut *mut c_void, nrebase: *mut c_int, flags: c_int) -> ResultCode;
 }
@@ -0,0 +1,477 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! JSON values and JSON1 helpers, available with the `serde_json` feature.
+//!
+//! `serde_json::Value` is stored as JSON text. `Json<T>` stores a value as
+//! JSON text and `Jsonb<T>` as a JSONB blob, the binary format of SQLite
+//! 3.45 and later; both read either. Binding
+//! `Json(vec)` to `x IN (SELECT value FROM json_each(?))` matches `x`
+//! against the elements of a Rust `Vec`.
+//! See http://www.sqlite.org/json1.html
+
+use convert::*;
+use cursor::Cursor;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::{Map, Number, Value};
+use std::borrow::Borrow;
+use std::fmt;
+use std::str;
+use types::*;
+use types::BindArg::*;
+use types::ColumnType::*;
+use types::ResultCode::*;
+
+impl FromSql for Value {
+    /// Reads JSON text or a JSONB blob; numbers read as JSON numbers and
+    /// NULL as `Value::Null`.
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<Value, FromSqlError> {
+        match cursor.get_column_type(i) {
+            SQLITE_TEXT => serde_json::from_str(cursor.get_text(i).unwrap_or(""))
+                .map_err(|e| FromSqlError::Other(e.to_string())),
+            SQLITE_BLOB => from_jsonb(cursor.get_blob(i).unwrap_or(&[])),
+            SQLITE_INTEGER => Ok(Value::from(cursor.get_i64(i))),
+            SQLITE_FLOAT => Ok(Value::from(cursor.get_f64(i))),
+            SQLITE_NULL => Ok(Value::Null),
+        }
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> BindArg {
+        Text(self.to_string())
+    }
+}
+
+/// Stores the wrapped value as JSON text.
+///
+/// Any deserializable type can be read, but only a `Value` can be bound
+/// since serializing other types can fail; `try_new` makes one.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Json<T>(pub T);
+
+impl Json<Value> {
+    /// Serializes `value`, which fails e.g. for a map with non-string keys.
+    pub fn try_new<T: Serialize + ?Sized>(value: &T) -> Result<Json<Value>, serde_json::Error> {
+        serde_json::to_value(value).map(Json)
+    }
+}
+
+impl<T: DeserializeOwned> FromSql for Json<T> {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<Json<T>, FromSqlError> {
+        from_value(Value::from_sql(cursor, i)?).map(Json)
+    }
+}
+
+impl<T: Borrow<Value>> ToSql for Json<T> {
+    fn to_sql(&self) -> BindArg {
+        Text(self.0.borrow().to_string())
+    }
+}
+
+/// Stores the wrapped value as a JSONB blob.
+///
+/// As with `Json`, only a `Value` can be bound; `try_new` makes one.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Jsonb<T>(pub T);
+
+impl Jsonb<Value> {
+    /// Serializes `value`, which fails e.g. for a map with non-string keys.
+    pub fn try_new<T: Serialize + ?Sized>(value: &T) -> Result<Jsonb<Value>, serde_json::Error> {
+        serde_json::to_value(value).map(Jsonb)
+    }
+}
+
+impl<T: DeserializeOwned> FromSql for Jsonb<T> {
+    fn from_sql(cursor: &mut Cursor, i: isize) -> Result<Jsonb<T>, FromSqlError> {
+        from_value(Value::from_sql(cursor, i)?).map(Jsonb)
+    }
+}
+
+impl<T: Borrow<Value>> ToSql for Jsonb<T> {
+    fn to_sql(&self) -> BindArg {
+        Blob(to_jsonb(self.0.borrow()))
+    }
+}
+
+fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, FromSqlError> {
+    serde_json::from_value(value).map_err(|e| FromSqlError::Other(e.to_string()))
+}
+
+/// A JSON path, e.g. `$.tags[0]`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct JsonPath(String);
+
+impl JsonPath {
+    /// The path of the whole value, `$`.
+    pub fn root() -> JsonPath {
+        JsonPath("$".to_string())
+    }
+
+    /// Appends an object key, quoted unless it is a plain identifier.
+    ///
+    /// Panics if `key` contains a double quote; see `try_key`.
+    pub fn key(self, key: &str) -> JsonPath {
+        match self.try_key(key) {
+            Some(path) => path,
+            None => panic!("JSON path key {:?} contains a double quote", key),
+        }
+    }
+
+    /// Appends an object key like `key`, or returns `None` if it contains a
+    /// double quote, which SQLite paths cannot express.
+    pub fn try_key(mut self, key: &str) -> Option<JsonPath> {
+        if key.contains('"') {
+            return None;
+        }
+        if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            self.0.push('.');
+            self.0.push_str(key);
+        } else {
+            self.0.push_str(&format!(".\"{}\"", key));
+        }
+        Some(self)
+    }
+
+    /// Appends an array index.
+    pub fn index(mut self, i: usize) -> JsonPath {
+        self.0.push_str(&format!("[{}]", i));
+        self
+    }
+
+    /// Appends an array index counted from the end, `[#-n]`.
+    pub fn from_end(mut self, n: usize) -> JsonPath {
+        self.0.push_str(&format!("[#-{}]", n));
+        self
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the path as an SQL string literal.
+    pub fn to_sql_literal(&self) -> String {
+        format!("'{}'", self.0.replace('\'', "''"))
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Returns `json_extract(expr, path)`.
+pub fn extract(expr: &str, path: &JsonPath) -> String {
+    format!("json_extract({}, {})", expr, path.to_sql_literal())
+}
+
+/// Returns `json_each(expr)`, or `json_each(expr, path)` to walk part of
+/// the value.
+pub fn each(expr: &str, path: Option<&JsonPath>) -> String {
+    match path {
+        Some(path) => format!("json_each({}, {})", expr, path.to_sql_literal()),
+        None => format!("json_each({})", expr),
+    }
+}
+
+/// Returns `expr IN (SELECT value FROM json_each(array))`, where `array` is
+/// typically a parameter bound to `Json(vec)`.
+pub fn in_array(expr: &str, array: &str) -> String {
+    format!("{} IN (SELECT value FROM {})", expr, each(array, None))
+}
+
+impl<'db> Cursor<'db> {
+    /// Binds `values` to parameter `i` as a JSON array. Fails with
+    /// `SQLITE_MISMATCH` if they do not serialize.
+    pub fn bind_json_array<T: Serialize>(&mut self, i: isize, values: &[T]) -> SqliteResult<()> {
+        let array = Json::try_new(values).map_err(|_| SQLITE_MISMATCH)?;
+        match self.bind_param(i, &array.to_sql()) {
+            SQLITE_OK => Ok(()),
+            r => Err(r),
+        }
+    }
+}
+
+// JSONB element types.
+// See http://www.sqlite.org/jsonb.html
+const JSONB_NULL: u8 = 0;
+const JSONB_TRUE: u8 = 1;
+const JSONB_FALSE: u8 = 2;
+const JSONB_INT: u8 = 3;
+const JSONB_INT5: u8 = 4;
+const JSONB_FLOAT: u8 = 5;
+const JSONB_FLOAT5: u8 = 6;
+const JSONB_TEXT: u8 = 7;
+const JSONB_TEXTJ: u8 = 8;
+const JSONB_TEXT5: u8 = 9;
+const JSONB_TEXTRAW: u8 = 10;
+const JSONB_ARRAY: u8 = 11;
+const JSONB_OBJECT: u8 = 12;
+
+/// The deepest nesting of arrays and objects decoded, as in SQLite.
+const JSONB_MAX_DEPTH: usize = 1000;
+
+/// Encodes `value` as JSONB.
+pub fn to_jsonb(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode(value, &mut out);
+    out
+}
+
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    match *value {
+        Value::Null => out.push(JSONB_NULL),
+        Value::Bool(true) => out.push(JSONB_TRUE),
+        Value::Bool(false) => out.push(JSONB_FALSE),
+        Value::Number(ref n) => {
+            let kind = if n.is_f64() { JSONB_FLOAT } else { JSONB_INT };
+            encode_element(kind, n.to_string().as_bytes(), out);
+        }
+        Value::String(ref s) => encode_text(s, out),
+        Value::Array(ref values) => {
+            let mut payload = Vec::new();
+            for v in values {
+                encode(v, &mut payload);
+            }
+            encode_element(JSONB_ARRAY, &payload, out);
+        }
+        Value::Object(ref map) => {
+            let mut payload = Vec::new();
+            for (k, v) in map {
+                encode_text(k, &mut payload);
+                encode(v, &mut payload);
+            }
+            encode_element(JSONB_OBJECT, &payload, out);
+        }
+    }
+}
+
+/// Text needing no escapes is stored as TEXT, anything else as TEXTRAW.
+fn encode_text(s: &str, out: &mut Vec<u8>) {
+    let plain = s.bytes().all(|b| b >= 0x20 && b != b'"' && b != b'\\');
+    encode_element(if plain { JSONB_TEXT } else { JSONB_TEXTRAW }, s.as_bytes(), out);
+}
+
+fn encode_element(kind: u8, payload: &[u8], out: &mut Vec<u8>) {
+    let len = payload.len();
+    if len <= 11 {
+        out.push((len as u8) << 4 | kind);
+    } else if len <= 0xff {
+        out.push(0xc0 | kind);
+        out.push(len as u8);
+    } else if len <= 0xffff {
+        out.push(0xd0 | kind);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else if len as u64 <= 0xffff_ffff {
+        out.push(0xe0 | kind);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0xf0 | kind);
+        out.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    out.extend_from_slice(payload);
+}
+
+fn malformed() -> FromSqlError {
+    FromSqlError::Other("malformed JSONB".to_string())
+}
+
+/// Decodes a JSONB blob.
+pub fn from_jsonb(bytes: &[u8]) -> Result<Value, FromSqlError> {
+    let (value, len) = decode(bytes, 0)?;
+    if len == bytes.len() { Ok(value) } else { Err(malformed()) }
+}
+
+/// Decodes the element at the start of `bytes`, nested in `depth` arrays
+/// or objects, returning it and its length.
+fn decode(bytes: &[u8], depth: usize) -> Result<(Value, usize), FromSqlError> {
+    let first = *bytes.first().ok_or_else(malformed)?;
+    let (header, size) = match first >> 4 {
+        n @ 0..=11 => (1, n as usize),
+        n => {
+            let width = 1 << (n - 12);
+            let size = bytes.get(1..1 + width).ok_or_else(malformed)?
+                .iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+            (1 + width, size as usize)
+        }
+    };
+    let end = header.checked_add(size).ok_or_else(malformed)?;
+    let payload = bytes.get(header..end).ok_or_else(malformed)?;
+    let value = match first & 0x0f {
+        JSONB_ARRAY | JSONB_OBJECT if depth >= JSONB_MAX_DEPTH => return Err(malformed()),
+        JSONB_ARRAY => decode_array(payload, depth + 1),
+        JSONB_OBJECT => decode_object(payload, depth + 1),
+        kind => decode_scalar(kind, payload),
+    };
+    Ok((value?, end))
+}
+
+// The elements are decoded in functions of their own so that the frames
+// of the recursion through nested values stay small.
+fn decode_scalar(kind: u8, payload: &[u8]) -> Result<Value, FromSqlError> {
+    let text = || str::from_utf8(payload).map_err(|_| malformed());
+    Ok(match kind {
+        JSONB_NULL => Value::Null,
+        JSONB_TRUE => Value::Bool(true),
+        JSONB_FALSE => Value::Bool(false),
+        JSONB_INT | JSONB_FLOAT => Value::Number(parse_number(text()?)?),
+        JSONB_INT5 => Value::Number(parse_hex(text()?)?),
+        JSONB_FLOAT5 => Value::Number(parse_number(&normalize_float5(text()?))?),
+        JSONB_TEXT | JSONB_TEXTRAW => Value::String(text()?.to_string()),
+        JSONB_TEXTJ | JSONB_TEXT5 => Value::String(unescape(text()?)?),
+        _ => return Err(malformed()),
+    })
+}
+
+fn decode_array(mut rest: &[u8], depth: usize) -> Result<Value, FromSqlError> {
+    let mut values = Vec::new();
+    while !rest.is_empty() {
+        let (v, len) = decode(rest, depth)?;
+        values.push(v);
+        rest = &rest[len..];
+    }
+    Ok(Value::Array(values))
+}
+
+fn decode_object(mut rest: &[u8], depth: usize) -> Result<Value, FromSqlError> {
+    let mut map = Map::new();
+    while !rest.is_empty() {
+        let (k, len) = decode(rest, depth)?;
+        let (v, vlen) = decode(rest.get(len..).ok_or_else(malformed)?, depth)?;
+        match k {
+            Value::String(k) => map.insert(k, v),
+            _ => return Err(malformed()),
+        };
+        rest = &rest[len + vlen..];
+    }
+    Ok(Value::Object(map))
+}
+
+fn parse_number(text: &str) -> Result<Number, FromSqlError> {
+    text.parse().map_err(|_| FromSqlError::Other(format!("invalid JSONB number {:?}", text)))
+}
+
+/// Parses a JSON5 hexadecimal integer, e.g. `-0x1F`.
+fn parse_hex(text: &str) -> Result<Number, FromSqlError> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let digits = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")).ok_or_else(malformed)?;
+    let v = u64::from_str_radix(digits, 16).map_err(|_| malformed())?;
+    if !negative {
+        Ok(Number::from(v))
+    } else if v <= i64::MAX as u64 + 1 {
+        Ok(Number::from((v as i64).wrapping_neg()))
+    } else {
+        Err(FromSqlError::Other(format!("invalid JSONB number {:?}", text)))
+    }
+}
+
+/// Rewrites a JSON5 float, e.g. `+.5` or `5.`, as JSON.
+fn normalize_float5(text: &str) -> String {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text.strip_prefix('+').unwrap_or(text)),
+    };
+    let mut s = sign.to_string();
+    if digits.starts_with('.') {
+        s.push('0');
+    }
+    s.push_str(digits);
+    if let Some(i) = s.find('.') {
+        if !s[i + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+            s.insert(i + 1, '0');
+        }
+    }
+    s
+}
+
+/// Resolves the JSON and JSON5 escapes of TEXTJ and TEXT5 payloads.
+fn unescape(text: &str) -> Result<String, FromSqlError> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next().ok_or_else(malformed)? {
+            'b' => out.push('\u{8}'),
+            'f' => out.push('\u{c}'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'v' => out.push('\u{b}'),
+            '0' => out.push('\0'),
+            'x' => out.push(hex_char(&mut chars, 2)?),
+            'u' => {
+                let c = hex_code(&mut chars, 4)?;
+                if (0xd800..0xdc00).contains(&c) {
+                    if chars.next() != Some('\\') || chars.next() != Some('u') {
+                        return Err(malformed());
+                    }
+                    let low = hex_code(&mut chars, 4)?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(malformed());
+                    }
+                    out.push(char::from_u32(0x10000 + ((c - 0xd800) << 10) + (low - 0xdc00)).ok_or_else(malformed)?);
+                } else {
+                    out.push(char::from_u32(c).ok_or_else(malformed)?);
+                }
+            }
+            // JSON5 line continuations.
+            '\r' => {
+                if chars.as_str().starts_with('\n') {
+                    chars.next();
+                }
+            }
+            '\n' | '\u{2028}' | '\u{2029}' => {}
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn hex_code(chars: &mut str::Chars, n: usize) -> Result<u32, FromSqlError> {
+    let mut v = 0;
+    for _ in 0..n {
+        v = v * 16 + chars.next().and_then(|c| c.to_digit(16)).ok_or_else(malformed)?;
+    }
+    Ok(v)
+}
+
+fn hex_char(chars: &mut str::Chars, n: usize) -> Result<char, FromSqlError> {
+    hex_code(chars, n).and_then(|c| char::from_u32(c).ok_or_else(malformed))
+}
@@ -0,0 +1,136 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Run-time limits on the size of things a connection handles, to contain
+//! the resources used by untrusted SQL.
+//! See http://www.sqlite.org/limits.html
+
+use ffi::*;
+use libc::c_int;
+use database::Database;
+use self::Limit::*;
+
+/// See http://www.sqlite.org/c3ref/c_limit_attached.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub enum Limit {
+    /// The maximum size of a string, blob or row.
+    SQLITE_LIMIT_LENGTH              =  0,
+    /// The maximum length of an SQL statement, in bytes.
+    SQLITE_LIMIT_SQL_LENGTH          =  1,
+    /// The maximum number of columns of a table, index, result or clause.
+    SQLITE_LIMIT_COLUMN              =  2,
+    /// The maximum depth of the parse tree of an expression.
+    SQLITE_LIMIT_EXPR_DEPTH          =  3,
+    /// The maximum number of terms in a compound SELECT.
+    SQLITE_LIMIT_COMPOUND_SELECT     =  4,
+    /// The maximum number of instructions of a statement's program.
+    SQLITE_LIMIT_VDBE_OP             =  5,
+    /// The maximum number of arguments to a function.
+    SQLITE_LIMIT_FUNCTION_ARG        =  6,
+    /// The maximum number of attached databases.
+    SQLITE_LIMIT_ATTACHED            =  7,
+    /// The maximum length of the pattern of LIKE or GLOB.
+    SQLITE_LIMIT_LIKE_PATTERN_LENGTH =  8,
+    /// The maximum index of a parameter.
+    SQLITE_LIMIT_VARIABLE_NUMBER     =  9,
+    /// The maximum depth of recursive triggers.
+    SQLITE_LIMIT_TRIGGER_DEPTH       = 10,
+    /// The maximum number of auxiliary worker threads of a statement.
+    SQLITE_LIMIT_WORKER_THREADS      = 11,
+}
+
+/// A set of limits to apply at once to a connection running untrusted SQL.
+///
+/// The defaults follow the recommendations of
+/// http://www.sqlite.org/security.html and can be adjusted with `limit`.
+#[derive(Clone, Debug)]
+pub struct SandboxProfile {
+    limits: Vec<(Limit, i32)>,
+}
+
+impl Default for SandboxProfile {
+    fn default() -> SandboxProfile {
+        SandboxProfile {
+            limits: vec![
+                (SQLITE_LIMIT_LENGTH, 1000000),
+                (SQLITE_LIMIT_SQL_LENGTH, 100000),
+                (SQLITE_LIMIT_COLUMN, 100),
+                (SQLITE_LIMIT_EXPR_DEPTH, 10),
+                (SQLITE_LIMIT_COMPOUND_SELECT, 3),
+                (SQLITE_LIMIT_VDBE_OP, 25000),
+                (SQLITE_LIMIT_FUNCTION_ARG, 8),
+                (SQLITE_LIMIT_ATTACHED, 0),
+                (SQLITE_LIMIT_LIKE_PATTERN_LENGTH, 50),
+                (SQLITE_LIMIT_VARIABLE_NUMBER, 10),
+                (SQLITE_LIMIT_TRIGGER_DEPTH, 10),
+            ],
+        }
+    }
+}
+
+impl SandboxProfile {
+    /// Sets the value of `limit` in the profile.
+    pub fn limit(mut self, limit: Limit, value: i32) -> SandboxProfile {
+        match self.limits.iter_mut().find(|l| l.0 == limit) {
+            Some(l) => l.1 = value,
+            None => self.limits.push((limit, value)),
+        }
+        self
+    }
+
+    /// The value of `limit` in the profile, if it sets one.
+    pub fn get(&self, limit: Limit) -> Option<i32> {
+        self.limits.iter().find(|l| l.0 == limit).map(|l| l.1)
+    }
+}
+
+impl Database {
+
+    /// Sets `limit` to `value`, returning its previous value. Values above
+    /// the maximum SQLite was compiled with are reduced to it.
+    /// See http://www.sqlite.org/c3ref/limit.html
+    pub fn set_limit(&mut self, limit: Limit, value: i32) -> i32 {
+        unsafe { sqlite3_limit(self.dbh, limit as c_int, value.max(0)) }
+    }
+
+    /// See http://www.sqlite.org/c3ref/limit.html
+    pub fn get_limit(&self, limit: Limit) -> i32 {
+        unsafe { sqlite3_limit(self.dbh, limit as c_int, -1) }
+    }
+
+    /// Sets all the limits of `profile`, leaving the others unchanged.
+    pub fn apply_sandbox(&mut self, profile: &SandboxProfile) {
+        for &(limit, value) in &profile.limits {
+            self.set_limit(limit, value);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Memory used by SQLite across all connections of the process, and limits
+//! on it.
+//! See http://www.sqlite.org/malloc.html
+
+use ffi::*;
+use libc::c_int;
+use status::StatusValue;
+use types::*;
+use types::ResultCode::*;
+use self::Status::*;
+
+/// See http://www.sqlite.org/c3ref/c_status_malloc_count.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub enum Status {
+    SQLITE_STATUS_MEMORY_USED        = 0,
+    SQLITE_STATUS_PAGECACHE_USED     = 1,
+    SQLITE_STATUS_PAGECACHE_OVERFLOW = 2,
+    SQLITE_STATUS_MALLOC_SIZE        = 5,
+    SQLITE_STATUS_PARSER_STACK       = 6,
+    SQLITE_STATUS_PAGECACHE_SIZE     = 7,
+    SQLITE_STATUS_MALLOC_COUNT       = 9,
+}
+
+/// The memory use of the library at some point, in bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MemoryStats {
+    pub memory_used: i64,
+    pub memory_highwater: i64,
+    /// The number of allocations currently outstanding.
+    pub malloc_count: i64,
+    /// Page cache memory that did not fit in the memory set up for it.
+    pub pagecache_overflow: i64,
+    /// The soft heap limit, or 0 for none.
+    pub soft_heap_limit: i64,
+    /// The hard heap limit, or 0 for none.
+    pub hard_heap_limit: i64,
+}
+
+/// Reads a counter of the library, resetting its highest value when `reset`
+/// is set.
+/// See http://www.sqlite.org/c3ref/status.html
+pub fn status(op: Status, reset: bool) -> SqliteResult<StatusValue> {
+    let mut current = 0;
+    let mut highwater = 0;
+    let r = unsafe { sqlite3_status64(op as c_int, &mut current, &mut highwater, reset as c_int) };
+    if r != SQLITE_OK {
+        return Err(r);
+    }
+    Ok(StatusValue { current, highwater })
+}
+
+/// Reads the main memory counters and limits at once.
+pub fn stats() -> SqliteResult<MemoryStats> {
+    let memory = status(SQLITE_STATUS_MEMORY_USED, false)?;
+    Ok(MemoryStats {
+        memory_used: memory.current,
+        memory_highwater: memory.highwater,
+        malloc_count: status(SQLITE_STATUS_MALLOC_COUNT, false)?.current,
+        pagecache_overflow: status(SQLITE_STATUS_PAGECACHE_OVERFLOW, false)?.current,
+        soft_heap_limit: soft_heap_limit(),
+        hard_heap_limit: hard_heap_limit(),
+    })
+}
+
+/// The number of bytes currently allocated.
+/// See http://www.sqlite.org/c3ref/memory_highwater.html
+pub fn memory_used() -> i64 {
+    unsafe { sqlite3_memory_used() }
+}
+
+/// The highest number of bytes allocated, since the last reset.
+/// See http://www.sqlite.org/c3ref/memory_highwater.html
+pub fn memory_highwater(reset: bool) -> i64 {
+    unsafe { sqlite3_memory_highwater(reset as c_int) }
+}
+
+/// Sets the amount of memory above which SQLite tries to free cache
+/// memory, or removes it with 0. Returns the previous limit.
+/// See http://www.sqlite.org/c3ref/hard_heap_limit64.html
+pub fn set_soft_heap_limit(limit: i64) -> i64 {
+    unsafe { sqlite3_soft_heap_limit64(limit.max(0)) }
+}
+
+pub fn soft_heap_limit() -> i64 {
+    unsafe { sqlite3_soft_heap_limit64(-1) }
+}
+
+/// Sets the amount of memory above which allocations fail with
+/// `SQLITE_NOMEM`, or removes it with 0. Returns the previous limit.
+/// See http://www.sqlite.org/c3ref/hard_heap_limit64.html
+pub fn set_hard_heap_limit(limit: i64) -> i64 {
+    unsafe { sqlite3_hard_heap_limit64(limit.max(0)) }
+}
+
+pub fn hard_heap_limit() -> i64 {
+    unsafe { sqlite3_hard_heap_limit64(-1) }
+}
+
+/// Tries to free `bytes` of memory held by caches, returning the amount
+/// actually freed. This only does something when SQLite is compiled with
+/// SQLITE_ENABLE_MEMORY_MANAGEMENT.
+/// See http://www.sqlite.org/c3ref/release_memory.html
+pub fn release_memory(bytes: i32) -> i32 {
+    unsafe { sqlite3_release_memory(bytes) }
+}
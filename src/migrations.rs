@@ -0,0 +1,332 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Schema migrations tracked through `PRAGMA user_version`.
+//!
+//! Migration N (starting from 1) brings the database from version N - 1 to
+//! N, and its optional down step back. Each step runs in a transaction,
+//! together with the update of `user_version` and of the `_migrations`
+//! table, which records a checksum of every applied migration so that
+//! later edits to them are detected.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use database::Database;
+use types::*;
+use types::BindArg::*;
+use types::ResultCode::*;
+
+type MigrationFn = dyn Fn(&mut Database) -> SqliteResult<()>;
+
+enum Step {
+    Sql(String),
+    Fn(Box<MigrationFn>),
+}
+
+struct Migration {
+    name: String,
+    up: Step,
+    down: Option<Step>,
+}
+
+impl Migration {
+    /// FNV-1a of the up script, or of the name for closures.
+    fn checksum(&self) -> String {
+        let bytes = match self.up {
+            Step::Sql(ref sql) => sql.as_bytes(),
+            Step::Fn(_) => self.name.as_bytes(),
+        };
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for &b in bytes {
+            hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+        format!("{:016x}", hash)
+    }
+}
+
+/// Why migrating failed.
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Reading or updating the migration state failed.
+    Sqlite(ResultCode),
+    /// A migration step failed, and was rolled back.
+    Failed { version: i32, code: ResultCode, message: String },
+    /// An applied migration is not the same as when it was applied.
+    Changed { version: i32 },
+    /// The database is at a version later than the last migration.
+    TooNew { version: i32, latest: i32 },
+    /// Going back requires a migration without a down step.
+    Irreversible { version: i32 },
+    /// `_migrations` records a version no migration has.
+    Unknown { version: i32 },
+    Io(io::Error),
+    /// A file of a migration directory is not named as expected, or the
+    /// versions do not follow each other.
+    InvalidFile(PathBuf),
+}
+
+impl From<ResultCode> for MigrationError {
+    fn from(code: ResultCode) -> MigrationError {
+        MigrationError::Sqlite(code)
+    }
+}
+
+impl From<io::Error> for MigrationError {
+    fn from(err: io::Error) -> MigrationError {
+        MigrationError::Io(err)
+    }
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrationError::Sqlite(code) => write!(f, "{:?}", code),
+            MigrationError::Failed { version, code, ref message } =>
+                write!(f, "migration {} failed: {:?}: {}", version, code, message),
+            MigrationError::Changed { version } => write!(f, "migration {} changed since it was applied", version),
+            MigrationError::TooNew { version, latest } =>
+                write!(f, "database version {} is later than the last migration {}", version, latest),
+            MigrationError::Irreversible { version } => write!(f, "migration {} cannot be reverted", version),
+            MigrationError::Unknown { version } => write!(f, "unknown migration {} was applied", version),
+            MigrationError::Io(ref err) => write!(f, "{}", err),
+            MigrationError::InvalidFile(ref path) => write!(f, "invalid migration file {}", path.display()),
+        }
+    }
+}
+
+impl error::Error for MigrationError {}
+
+/// An ordered list of migrations.
+#[derive(Default)]
+pub struct Migrations {
+    migrations: Vec<Migration>,
+    dry_run: bool,
+}
+
+impl Migrations {
+    pub fn new() -> Migrations {
+        Migrations::default()
+    }
+
+    /// Adds a migration running `sql`, which cannot be reverted.
+    pub fn up(self, name: &str, sql: &str) -> Migrations {
+        self.push(name, Step::Sql(sql.to_string()), None)
+    }
+
+    /// Adds a migration running `up`, and `down` to revert it.
+    pub fn up_down(self, name: &str, up: &str, down: &str) -> Migrations {
+        self.push(name, Step::Sql(up.to_string()), Some(Step::Sql(down.to_string())))
+    }
+
+    /// Adds a migration running `up`, and optionally `down` to revert it.
+    /// Its checksum only covers `name`.
+    pub fn up_fn<U>(self, name: &str, up: U, down: Option<Box<MigrationFn>>) -> Migrations
+        where U: Fn(&mut Database) -> SqliteResult<()> + 'static
+    {
+        self.push(name, Step::Fn(Box::new(up)), down.map(Step::Fn))
+    }
+
+    fn push(mut self, name: &str, up: Step, down: Option<Step>) -> Migrations {
+        self.migrations.push(Migration { name: name.to_string(), up, down });
+        self
+    }
+
+    /// Runs migrations in a single transaction rolled back at the end,
+    /// leaving the database unchanged.
+    pub fn dry_run(mut self, dry_run: bool) -> Migrations {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Loads the migrations of directory `dir`, named `<version>_<name>.sql`
+    /// or `<version>_<name>.up.sql` with an optional `<version>_<name>.down.sql`.
+    pub fn from_directory<P: AsRef<Path>>(dir: P) -> Result<Migrations, MigrationError> {
+        let mut ups = Vec::new();
+        let mut downs = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let file = match path.file_name().and_then(|f| f.to_str()) {
+                Some(f) if f.ends_with(".sql") => f.trim_end_matches(".sql").to_string(),
+                _ => continue,
+            };
+            let (stem, down) = if let Some(stem) = file.strip_suffix(".down") {
+                (stem, true)
+            } else {
+                (file.trim_end_matches(".up"), false)
+            };
+            let (version, name) = match stem.split_once('_').map(|(v, n)| (v.parse::<i32>(), n)) {
+                Some((Ok(version), name)) => (version, name.to_string()),
+                _ => return Err(MigrationError::InvalidFile(path)),
+            };
+            let sql = fs::read_to_string(&path)?;
+            if down { downs.push((version, sql, path)) } else { ups.push((version, name, sql, path)) }
+        }
+        ups.sort_by_key(|u| u.0);
+        let mut migrations = Migrations::new();
+        for (i, (version, name, sql, path)) in ups.into_iter().enumerate() {
+            if version != i as i32 + 1 {
+                return Err(MigrationError::InvalidFile(path));
+            }
+            let down = downs.iter().position(|d| d.0 == version).map(|d| Step::Sql(downs.remove(d).1));
+            migrations = migrations.push(&name, Step::Sql(sql), down);
+        }
+        if let Some((_, _, path)) = downs.pop() {
+            return Err(MigrationError::InvalidFile(path));
+        }
+        Ok(migrations)
+    }
+
+    /// The version of the last migration.
+    pub fn latest(&self) -> i32 {
+        self.migrations.len() as i32
+    }
+
+    /// Checks the migrations already applied to `db` did not change,
+    /// without modifying it. Returns the current version of `db`.
+    pub fn validate(&self, db: &mut Database) -> Result<i32, MigrationError> {
+        let current = db.user_version()?;
+        if current > self.latest() {
+            return Err(MigrationError::TooNew { version: current, latest: self.latest() });
+        }
+        let mut exists = db.prepare("SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = '_migrations'", &None)?;
+        if exists.step_row()?.is_none() {
+            return Ok(current);
+        }
+        let mut cursor = db.prepare("SELECT version, checksum FROM _migrations WHERE version <= ?", &None)?;
+        let r = cursor.bind_param(1, &Integer(current as isize));
+        if r != SQLITE_OK {
+            return Err(r.into());
+        }
+        while let Some(row) = cursor.step_row()? {
+            let version = match row.get("version") {
+                Some(&Integer(v)) => v as i32,
+                _ => continue,
+            };
+            if version < 1 {
+                return Err(MigrationError::Unknown { version });
+            }
+            let checksum = self.migrations[version as usize - 1].checksum();
+            if row.get("checksum") != Some(&Text(checksum)) {
+                return Err(MigrationError::Changed { version });
+            }
+        }
+        Ok(current)
+    }
+
+    /// Applies all the migrations not applied yet. See `to_version`.
+    pub fn to_latest(&self, db: &mut Database) -> Result<Vec<i32>, MigrationError> {
+        self.to_version(db, self.latest())
+    }
+
+    /// Migrates `db` up or down to `target`, after validating it. Returns the
+    /// versions of the migrations run, in order.
+    pub fn to_version(&self, db: &mut Database, target: i32) -> Result<Vec<i32>, MigrationError> {
+        if target < 0 {
+            return Err(MigrationError::Sqlite(SQLITE_RANGE));
+        }
+        if target > self.latest() {
+            return Err(MigrationError::TooNew { version: target, latest: self.latest() });
+        }
+        if self.dry_run {
+            db.exec("BEGIN")?;
+            let r = self.validate(db).and_then(|current| self.migrate(db, current, target));
+            let rolled_back = db.exec("ROLLBACK");
+            let run = r?;
+            rolled_back?;
+            return Ok(run);
+        }
+        let current = self.validate(db)?;
+        self.migrate(db, current, target)
+    }
+
+    fn migrate(&self, db: &mut Database, current: i32, target: i32) -> Result<Vec<i32>, MigrationError> {
+        let mut run = Vec::new();
+        let mut version = current;
+        while version != target {
+            let (migration, up) = if version < target {
+                (version + 1, true)
+            } else {
+                (version, false)
+            };
+            let m = &self.migrations[migration as usize - 1];
+            let step = if up {
+                &m.up
+            } else {
+                m.down.as_ref().ok_or(MigrationError::Irreversible { version: migration })?
+            };
+            version = if up { migration } else { migration - 1 };
+            self.transaction(db, |db| {
+                match *step {
+                    Step::Sql(ref sql) => db.exec(sql).map(|_| ()),
+                    Step::Fn(ref f) => f(db),
+                }?;
+                db.exec("CREATE TABLE IF NOT EXISTS _migrations \
+                         (version INTEGER PRIMARY KEY, name TEXT NOT NULL, checksum TEXT NOT NULL)")?;
+                if up {
+                    let mut c = db.prepare("INSERT OR REPLACE INTO _migrations VALUES (?, ?, ?)", &None)?;
+                    let r = c.bind_params(&[Integer(migration as isize), Text(m.name.clone()), Text(m.checksum())]);
+                    if r != SQLITE_OK {
+                        return Err(r);
+                    }
+                    c.step_row()?;
+                } else {
+                    db.exec(&format!("DELETE FROM _migrations WHERE version = {}", migration))?;
+                }
+                db.set_user_version(version)
+            }).map_err(|(code, message)| MigrationError::Failed { version: migration, code, message })?;
+            run.push(migration);
+        }
+        Ok(run)
+    }
+
+    fn transaction<F>(&self, db: &mut Database, f: F) -> Result<(), (ResultCode, String)>
+        where F: FnOnce(&mut Database) -> SqliteResult<()>
+    {
+        // dry runs are already in a transaction.
+        let (begin, commit, rollback) = if self.dry_run {
+            ("SAVEPOINT migration", "RELEASE migration", "ROLLBACK TO migration; RELEASE migration")
+        } else {
+            ("BEGIN", "COMMIT", "ROLLBACK")
+        };
+        let r = db.exec(begin).and_then(|_| f(db)).and_then(|_| db.exec(commit));
+        match r {
+            Ok(_) => Ok(()),
+            Err(code) => {
+                let message = db.get_errmsg();
+                db.exec(rollback).ok();
+                Err((code, message))
+            }
+        }
+    }
+}
@@ -0,0 +1,339 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A connection pool for a database file in WAL mode.
+//!
+//! A `Pool` opens a single writer connection, which switches the database
+//! to WAL mode, and a number of read-only connections. In WAL mode, readers
+//! do not block the writer nor each other, so read-only queries need not be
+//! serialized behind the writer.
+
+use ffi::*;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use database::Database;
+use pragma::JournalMode;
+use types::*;
+use types::ResultCode::*;
+
+type Customizer = dyn Fn(&mut Database) -> SqliteResult<()> + Send + Sync;
+
+/// Configures and opens a `Pool`.
+pub struct PoolBuilder {
+    path: String,
+    readers: usize,
+    checkout_timeout: Duration,
+    on_connect: Option<Arc<Customizer>>,
+}
+
+impl PoolBuilder {
+    /// Sets the number of read-only connections (4 by default).
+    pub fn readers(mut self, readers: usize) -> PoolBuilder {
+        self.readers = readers;
+        self
+    }
+
+    /// Sets how long a checkout waits for a free connection before failing
+    /// with `SQLITE_BUSY` (30 seconds by default).
+    pub fn checkout_timeout(mut self, timeout: Duration) -> PoolBuilder {
+        self.checkout_timeout = timeout;
+        self
+    }
+
+    /// Sets a function run on every new connection, e.g. to set pragmas or
+    /// register functions. It runs after the writer is switched to WAL mode;
+    /// keep in mind that readers are opened read-only.
+    pub fn on_connect<F>(mut self, f: F) -> PoolBuilder
+        where F: Fn(&mut Database) -> SqliteResult<()> + Send + Sync + 'static
+    {
+        self.on_connect = Some(Arc::new(f));
+        self
+    }
+
+    /// Opens the writer, then the readers.
+    pub fn build(self) -> SqliteResult<Pool> {
+        let pool = Pool {
+            path: self.path,
+            on_connect: self.on_connect,
+            checkout_timeout: self.checkout_timeout,
+            writer: Slots::new(1),
+            readers: Slots::new(self.readers),
+            metrics: Mutex::new(PoolMetrics::default()),
+        };
+        // the writer has to come first so that the WAL exists for the readers.
+        let writer = pool.connect(Kind::Writer)?;
+        pool.writer.put(Some(writer));
+        for _ in 0..self.readers {
+            let reader = pool.connect(Kind::Reader)?;
+            pool.readers.put(Some(reader));
+        }
+        pool.metrics.lock().unwrap().readers = self.readers;
+        Ok(pool)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+    Reader,
+    Writer,
+}
+
+/// An idle connection, or `None` for one to be reopened on checkout.
+type Slot = Option<Database>;
+
+struct Slots {
+    idle: Mutex<Vec<Slot>>,
+    available: Condvar,
+}
+
+impl Slots {
+    fn new(capacity: usize) -> Slots {
+        Slots { idle: Mutex::new(Vec::with_capacity(capacity)), available: Condvar::new() }
+    }
+
+    fn put(&self, db: Slot) {
+        self.idle.lock().unwrap().push(db);
+        self.available.notify_one();
+    }
+
+    fn take(&self, timeout: Duration) -> Option<Slot> {
+        let deadline = Instant::now() + timeout;
+        let mut idle = self.idle.lock().unwrap();
+        loop {
+            if let Some(db) = idle.pop() {
+                return Some(db);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            idle = self.available.wait_timeout(idle, deadline - now).unwrap().0;
+        }
+    }
+}
+
+/// Counters describing the usage of a `Pool`.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct PoolMetrics {
+    /// The number of read-only connections.
+    pub readers: usize,
+    pub readers_in_use: usize,
+    pub writer_in_use: bool,
+    pub reader_checkouts: u64,
+    pub writer_checkouts: u64,
+    /// The number of checkouts which failed after waiting `checkout_timeout`.
+    pub timeouts: u64,
+    /// The number of connections found broken on checkout and reopened.
+    pub reconnects: u64,
+    /// The total and longest time spent waiting for a connection.
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl PoolMetrics {
+    /// Returns the fraction of read-only connections currently checked out.
+    pub fn reader_utilization(&self) -> f64 {
+        if self.readers == 0 {
+            0.0
+        } else {
+            self.readers_in_use as f64 / self.readers as f64
+        }
+    }
+}
+
+/// A pool of one writer and any number of read-only connections to the same
+/// database file.
+///
+/// `Pool` is `Sync` and is meant to be shared, e.g. through `Arc`.
+pub struct Pool {
+    path: String,
+    on_connect: Option<Arc<Customizer>>,
+    checkout_timeout: Duration,
+    writer: Slots,
+    readers: Slots,
+    metrics: Mutex<PoolMetrics>,
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Pool path={:?} metrics={:?}>", self.path, self.metrics())
+    }
+}
+
+impl Pool {
+    /// Starts configuring a pool for the database file at `path`.
+    pub fn builder(path: &str) -> PoolBuilder {
+        PoolBuilder {
+            path: path.to_string(),
+            readers: 4,
+            checkout_timeout: Duration::from_secs(30),
+            on_connect: None,
+        }
+    }
+
+    fn connect(&self, kind: Kind) -> SqliteResult<Database> {
+        let mut db = match kind {
+            Kind::Writer => {
+                let mut db = ::open_with_flags(&self.path, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)?;
+                if db.set_journal_mode(JournalMode::Wal)? != JournalMode::Wal {
+                    return Err(SQLITE_CANTOPEN);
+                }
+                db
+            }
+            Kind::Reader => ::open_with_flags(&self.path, SQLITE_OPEN_READONLY)?,
+        };
+        if let Some(ref f) = self.on_connect {
+            f(&mut db)?;
+        }
+        debug!("`Pool.connect()`: kind={:?} db={:?}", kind, db);
+        Ok(db)
+    }
+
+    fn checkout(&self, kind: Kind) -> SqliteResult<PooledConnection<'_>> {
+        let slots = match kind { Kind::Reader => &self.readers, Kind::Writer => &self.writer };
+        let started = Instant::now();
+        let db = slots.take(self.checkout_timeout);
+        let waited = started.elapsed();
+
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.total_wait += waited;
+        if waited > metrics.max_wait {
+            metrics.max_wait = waited;
+        }
+        let db = match db {
+            Some(db) => db,
+            None => {
+                metrics.timeouts += 1;
+                return Err(SQLITE_BUSY);
+            }
+        };
+        match kind {
+            Kind::Reader => { metrics.reader_checkouts += 1; metrics.readers_in_use += 1; }
+            Kind::Writer => { metrics.writer_checkouts += 1; metrics.writer_in_use = true; }
+        }
+        drop(metrics);
+
+        let mut conn = PooledConnection { pool: self, kind, db };
+        if !conn.db.as_mut().is_some_and(is_valid) {
+            debug!("`Pool.checkout()`: reopening connection {:?}", conn);
+            // on failure, dropping `conn` puts the empty slot back to be retried later.
+            conn.db = None;
+            conn.db = Some(self.connect(kind)?);
+            self.metrics.lock().unwrap().reconnects += 1;
+        }
+        Ok(conn)
+    }
+
+    /// Checks out a read-only connection, waiting for one to be returned if
+    /// all are in use.
+    pub fn reader(&self) -> SqliteResult<PooledConnection<'_>> {
+        self.checkout(Kind::Reader)
+    }
+
+    /// Checks out the writer connection, waiting for it to be returned if
+    /// it is in use.
+    pub fn writer(&self) -> SqliteResult<PooledConnection<'_>> {
+        self.checkout(Kind::Writer)
+    }
+
+    /// Returns a snapshot of the pool counters.
+    pub fn metrics(&self) -> PoolMetrics {
+        *self.metrics.lock().unwrap()
+    }
+}
+
+/// Checks that a connection still works and is not left inside a transaction.
+fn is_valid(db: &mut Database) -> bool {
+    if unsafe { sqlite3_get_autocommit(db.dbh) } == 0 && db.exec("ROLLBACK").is_err() {
+        return false;
+    }
+    match db.prepare("SELECT 1", &None) {
+        Ok(mut c) => c.step() == SQLITE_ROW,
+        Err(..) => false,
+    }
+}
+
+/// A connection checked out of a `Pool`, returned to it when dropped.
+///
+/// It derefs to `Database`.
+pub struct PooledConnection<'pool> {
+    pool: &'pool Pool,
+    kind: Kind,
+    db: Option<Database>,
+}
+
+impl<'pool> fmt::Debug for PooledConnection<'pool> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<PooledConnection kind={:?} db={:?}>", self.kind, self.db)
+    }
+}
+
+impl<'pool> Deref for PooledConnection<'pool> {
+    type Target = Database;
+    fn deref(&self) -> &Database {
+        self.db.as_ref().unwrap()
+    }
+}
+
+impl<'pool> DerefMut for PooledConnection<'pool> {
+    fn deref_mut(&mut self) -> &mut Database {
+        self.db.as_mut().unwrap()
+    }
+}
+
+impl<'pool> Drop for PooledConnection<'pool> {
+    fn drop(&mut self) {
+        {
+            let mut metrics = self.pool.metrics.lock().unwrap();
+            match self.kind {
+                Kind::Reader => metrics.readers_in_use -= 1,
+                Kind::Writer => metrics.writer_in_use = false,
+            }
+        }
+        // a transaction left open would hold its locks while the connection
+        // is idle; if it cannot be rolled back, the connection is closed
+        // instead and reopened by a later checkout.
+        let db = self.db.take().and_then(|db| {
+            if unsafe { sqlite3_get_autocommit(db.dbh) } == 0 && db.exec("ROLLBACK").is_err() {
+                debug!("`PooledConnection.drop()`: closing {:?}, which failed to roll back", db);
+                None
+            } else {
+                Some(db)
+            }
+        });
+        match self.kind {
+            Kind::Reader => self.pool.readers.put(db),
+            Kind::Writer => self.pool.writer.put(db),
+        }
+    }
+}
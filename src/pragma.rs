@@ -0,0 +1,274 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Reading and setting PRAGMAs without building SQL by hand.
+//! See http://www.sqlite.org/pragma.html
+
+use std::fmt::Write;
+use std::time::Duration;
+use database::Database;
+use types::*;
+use types::BindArg::*;
+use types::ResultCode::*;
+
+/// See http://www.sqlite.org/pragma.html#pragma_journal_mode
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+
+    fn parse(s: &str) -> SqliteResult<JournalMode> {
+        match &*s.to_uppercase() {
+            "DELETE" => Ok(JournalMode::Delete),
+            "TRUNCATE" => Ok(JournalMode::Truncate),
+            "PERSIST" => Ok(JournalMode::Persist),
+            "MEMORY" => Ok(JournalMode::Memory),
+            "WAL" => Ok(JournalMode::Wal),
+            "OFF" => Ok(JournalMode::Off),
+            _ => Err(SQLITE_MISMATCH),
+        }
+    }
+}
+
+/// See http://www.sqlite.org/pragma.html#pragma_synchronous
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Synchronous {
+    Off = 0,
+    Normal = 1,
+    Full = 2,
+    Extra = 3,
+}
+
+/// See http://www.sqlite.org/pragma.html#pragma_temp_store
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TempStore {
+    Default = 0,
+    File = 1,
+    Memory = 2,
+}
+
+/// Formats `value` as an SQL literal, since PRAGMAs do not take parameters.
+/// Fails with `SQLITE_MISUSE` for NaN and infinities, which have none.
+fn literal(value: &BindArg) -> SqliteResult<String> {
+    Ok(match *value {
+        Text(ref s) => format!("'{}'", s.replace('\'', "''")),
+        StaticText(s) => format!("'{}'", s.replace('\'', "''")),
+        Float64(f) if !f.is_finite() => return Err(SQLITE_MISUSE),
+        Float64(f) => format!("{:?}", f),
+        Integer(i) => i.to_string(),
+        Integer64(i) => i.to_string(),
+        Blob(ref b) => {
+            let mut s = "X'".to_string();
+            for byte in b {
+                write!(s, "{:02X}", byte).unwrap();
+            }
+            s + "'"
+        }
+        Null => "NULL".to_string(),
+    })
+}
+
+fn pragma_sql(schema: Option<&str>, name: &str) -> SqliteResult<String> {
+    // names cannot be quoted.
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(SQLITE_MISUSE);
+    }
+    Ok(match schema {
+        Some(schema) => format!("PRAGMA {}.{}", quote_identifier(schema), name),
+        None => format!("PRAGMA {}", name),
+    })
+}
+
+fn single_value(rows: Vec<RowMap>) -> SqliteResult<BindArg> {
+    rows.into_iter().next().and_then(|row| row.into_iter().next()).map(|(_, v)| v).ok_or(SQLITE_EMPTY)
+}
+
+fn int_value(value: BindArg) -> SqliteResult<i64> {
+    match value {
+        Integer(i) => Ok(i as i64),
+        Integer64(i) => Ok(i),
+        _ => Err(SQLITE_MISMATCH),
+    }
+}
+
+fn text_value(value: BindArg) -> SqliteResult<String> {
+    match value {
+        Text(s) => Ok(s),
+        _ => Err(SQLITE_MISMATCH),
+    }
+}
+
+impl Database {
+
+    /// Runs the PRAGMA `name` on `schema` (or all databases for `None`),
+    /// returning its rows.
+    pub fn pragma_query(&self, schema: Option<&str>, name: &str) -> SqliteResult<Vec<RowMap>> {
+        self.pragma_rows(&pragma_sql(schema, name)?)
+    }
+
+    /// Sets the PRAGMA `name` of `schema` to `value`, returning the rows some
+    /// PRAGMAs produce when set.
+    pub fn pragma_update(&mut self, schema: Option<&str>, name: &str, value: &BindArg)
+                         -> SqliteResult<Vec<RowMap>> {
+        let sql = format!("{} = {}", pragma_sql(schema, name)?, literal(value)?);
+        self.pragma_rows(&sql)
+    }
+
+    fn pragma_rows(&self, sql: &str) -> SqliteResult<Vec<RowMap>> {
+        let mut cursor = self.prepare(sql, &None)?;
+        let mut rows = Vec::new();
+        while let Some(row) = cursor.step_row()? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    fn pragma_int(&self, name: &str) -> SqliteResult<i64> {
+        int_value(single_value(self.pragma_query(None, name)?)?)
+    }
+
+    fn set_pragma_int(&mut self, name: &str, value: i64) -> SqliteResult<()> {
+        self.pragma_update(None, name, &Integer64(value)).map(|_| ())
+    }
+
+    pub fn journal_mode(&self) -> SqliteResult<JournalMode> {
+        JournalMode::parse(&text_value(single_value(self.pragma_query(Some("main"), "journal_mode")?)?)?)
+    }
+
+    /// Sets the journal mode of the main database, returning the mode
+    /// actually in use, e.g. `JournalMode::Memory` for in-memory databases.
+    pub fn set_journal_mode(&mut self, mode: JournalMode) -> SqliteResult<JournalMode> {
+        let rows = self.pragma_update(Some("main"), "journal_mode", &StaticText(mode.as_str()))?;
+        JournalMode::parse(&text_value(single_value(rows)?)?)
+    }
+
+    pub fn synchronous(&self) -> SqliteResult<Synchronous> {
+        match self.pragma_int("synchronous")? {
+            0 => Ok(Synchronous::Off),
+            1 => Ok(Synchronous::Normal),
+            2 => Ok(Synchronous::Full),
+            3 => Ok(Synchronous::Extra),
+            _ => Err(SQLITE_MISMATCH),
+        }
+    }
+
+    pub fn set_synchronous(&mut self, synchronous: Synchronous) -> SqliteResult<()> {
+        self.set_pragma_int("synchronous", synchronous as i64)
+    }
+
+    pub fn foreign_keys(&self) -> SqliteResult<bool> {
+        self.pragma_int("foreign_keys").map(|v| v != 0)
+    }
+
+    /// Has no effect within a transaction.
+    pub fn set_foreign_keys(&mut self, enabled: bool) -> SqliteResult<()> {
+        self.set_pragma_int("foreign_keys", enabled as i64)
+    }
+
+    pub fn user_version(&self) -> SqliteResult<i32> {
+        self.pragma_int("user_version").map(|v| v as i32)
+    }
+
+    pub fn set_user_version(&mut self, version: i32) -> SqliteResult<()> {
+        self.set_pragma_int("user_version", version as i64)
+    }
+
+    pub fn application_id(&self) -> SqliteResult<i32> {
+        self.pragma_int("application_id").map(|v| v as i32)
+    }
+
+    pub fn set_application_id(&mut self, id: i32) -> SqliteResult<()> {
+        self.set_pragma_int("application_id", id as i64)
+    }
+
+    /// The suggested number of cache pages, or of KiB when negative.
+    pub fn cache_size(&self) -> SqliteResult<i64> {
+        self.pragma_int("cache_size")
+    }
+
+    pub fn set_cache_size(&mut self, size: i64) -> SqliteResult<()> {
+        self.set_pragma_int("cache_size", size)
+    }
+
+    pub fn mmap_size(&self) -> SqliteResult<i64> {
+        self.pragma_int("mmap_size")
+    }
+
+    /// Sets the maximum number of bytes of the database accessed through
+    /// memory mapping, returning the size actually used.
+    pub fn set_mmap_size(&mut self, size: i64) -> SqliteResult<i64> {
+        int_value(single_value(self.pragma_update(None, "mmap_size", &Integer64(size))?)?)
+    }
+
+    /// The busy timeout set with `set_busy_timeout`, if any.
+    pub fn busy_timeout(&self) -> SqliteResult<Duration> {
+        self.pragma_int("busy_timeout").map(|ms| Duration::from_millis(ms as u64))
+    }
+
+    pub fn temp_store(&self) -> SqliteResult<TempStore> {
+        match self.pragma_int("temp_store")? {
+            0 => Ok(TempStore::Default),
+            1 => Ok(TempStore::File),
+            2 => Ok(TempStore::Memory),
+            _ => Err(SQLITE_MISMATCH),
+        }
+    }
+
+    pub fn set_temp_store(&mut self, store: TempStore) -> SqliteResult<()> {
+        self.set_pragma_int("temp_store", store as i64)
+    }
+
+    pub fn page_size(&self) -> SqliteResult<i64> {
+        self.pragma_int("page_size")
+    }
+
+    /// Only takes effect on a new database, or after a `VACUUM`.
+    pub fn set_page_size(&mut self, size: i64) -> SqliteResult<()> {
+        self.set_pragma_int("page_size", size)
+    }
+}
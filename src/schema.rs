@@ -0,0 +1,305 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Describing the tables, views, indexes and triggers of a database.
+//!
+//! Objects are listed from `sqlite_schema`, and described with the
+//! `pragma_table_list`, `pragma_table_xinfo`, `pragma_index_list`,
+//! `pragma_index_xinfo` and `pragma_foreign_key_list` table-valued functions,
+//! which require SQLite 3.37.0 or later.
+//! See http://www.sqlite.org/schematab.html
+
+use database::Database;
+use types::*;
+use types::BindArg::*;
+
+/// The kind of a `Table`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TableKind {
+    Table,
+    View,
+    Virtual,
+    /// A table holding the data of a virtual table, e.g. for FTS5.
+    Shadow,
+}
+
+/// Whether a column is generated, and how.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Generated {
+    Virtual,
+    Stored,
+}
+
+/// See http://www.sqlite.org/pragma.html#pragma_table_xinfo
+#[derive(Clone, PartialEq, Debug)]
+pub struct Column {
+    pub name: String,
+    /// The declared type, possibly empty.
+    pub decl_type: String,
+    pub not_null: bool,
+    /// The SQL text of the default value.
+    pub default: Option<String>,
+    /// The position of the column in the primary key, starting from 1, or 0.
+    pub primary_key: i32,
+    /// Hidden columns of virtual tables, e.g. arguments of table-valued functions.
+    pub hidden: bool,
+    pub generated: Option<Generated>,
+}
+
+/// What created an index.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndexOrigin {
+    /// A CREATE INDEX statement.
+    CreateIndex,
+    /// A UNIQUE constraint.
+    Unique,
+    /// A PRIMARY KEY constraint.
+    PrimaryKey,
+}
+
+/// A key column of an index.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IndexColumn {
+    /// The name of the column, or `None` for an expression or the rowid.
+    pub name: Option<String>,
+    pub descending: bool,
+    pub collation: String,
+}
+
+/// See http://www.sqlite.org/pragma.html#pragma_index_list
+#[derive(Clone, PartialEq, Debug)]
+pub struct Index {
+    pub name: String,
+    pub unique: bool,
+    pub origin: IndexOrigin,
+    /// Whether the index has a WHERE clause.
+    pub partial: bool,
+    pub columns: Vec<IndexColumn>,
+}
+
+/// See http://www.sqlite.org/pragma.html#pragma_foreign_key_list
+#[derive(Clone, PartialEq, Debug)]
+pub struct ForeignKey {
+    /// The referenced table.
+    pub table: String,
+    pub from: Vec<String>,
+    /// The referenced columns, or `None` for its primary key.
+    pub to: Vec<Option<String>>,
+    pub on_update: String,
+    pub on_delete: String,
+}
+
+/// A table or view, with its columns, and for tables its indexes and foreign keys.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Table {
+    pub name: String,
+    pub kind: TableKind,
+    pub without_rowid: bool,
+    pub strict: bool,
+    /// The CREATE statement, if any.
+    pub sql: Option<String>,
+    pub columns: Vec<Column>,
+    pub indexes: Vec<Index>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+impl Table {
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// See http://www.sqlite.org/lang_createtrigger.html
+#[derive(Clone, PartialEq, Debug)]
+pub struct Trigger {
+    pub name: String,
+    pub table: String,
+    pub sql: String,
+}
+
+fn text(row: &RowMap, key: &str) -> String {
+    opt_text(row, key).unwrap_or_default()
+}
+
+fn opt_text(row: &RowMap, key: &str) -> Option<String> {
+    match row.get(key) {
+        Some(Text(s)) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+fn int(row: &RowMap, key: &str) -> i64 {
+    match row.get(key) {
+        Some(&Integer(i)) => i as i64,
+        Some(&Integer64(i)) => i,
+        _ => 0,
+    }
+}
+
+impl Database {
+
+    fn schema_rows(&self, sql: &str, params: &[BindArg]) -> SqliteResult<Vec<RowMap>> {
+        let mut cursor = self.prepare(sql, &None)?;
+        let r = cursor.bind_params(params);
+        if r != ResultCode::SQLITE_OK {
+            return Err(r);
+        }
+        let mut rows = Vec::new();
+        while let Some(row) = cursor.step_row()? {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    fn select_tables(&self, schema: &str, name: Option<&str>) -> SqliteResult<Vec<Table>> {
+        let sql = format!("SELECT s.name, s.type, s.sql, l.type AS kind, l.wr, l.strict \
+                           FROM {}.sqlite_schema AS s JOIN pragma_table_list(s.name) AS l ON l.schema = ?1 \
+                           WHERE s.type IN ('table', 'view') AND s.name NOT LIKE 'sqlite\\_%' ESCAPE '\\' \
+                           {}ORDER BY s.name", quote_identifier(schema),
+                          if name.is_some() { "AND s.name = ?2 COLLATE NOCASE " } else { "" });
+        let mut params = vec![Text(schema.to_string())];
+        params.extend(name.map(|n| Text(n.to_string())));
+        let rows = self.schema_rows(&sql, &params)?;
+        rows.iter().map(|row| self.describe(schema, row)).collect()
+    }
+
+    /// The tables and views of the database `schema` ("main", "temp" or an
+    /// attached database), except internal ones like `sqlite_sequence`.
+    pub fn tables(&self, schema: &str) -> SqliteResult<Vec<Table>> {
+        self.select_tables(schema, None)
+    }
+
+    /// The table or view `name` of the database `schema`, if it exists.
+    pub fn table(&self, schema: &str, name: &str) -> SqliteResult<Option<Table>> {
+        Ok(self.select_tables(schema, Some(name))?.pop())
+    }
+
+    fn describe(&self, schema: &str, row: &RowMap) -> SqliteResult<Table> {
+        let name = text(row, "name");
+        let kind = match &*text(row, "kind") {
+            "view" => TableKind::View,
+            "virtual" => TableKind::Virtual,
+            "shadow" => TableKind::Shadow,
+            _ => TableKind::Table,
+        };
+        let args = [Text(name.clone()), Text(schema.to_string())];
+        let columns = self.schema_rows("SELECT * FROM pragma_table_xinfo(?1, ?2) ORDER BY cid", &args)?
+            .iter().map(|c| Column {
+                name: text(c, "name"),
+                decl_type: text(c, "type"),
+                not_null: int(c, "notnull") != 0,
+                default: opt_text(c, "dflt_value"),
+                primary_key: int(c, "pk") as i32,
+                hidden: int(c, "hidden") == 1,
+                generated: match int(c, "hidden") {
+                    2 => Some(Generated::Virtual),
+                    3 => Some(Generated::Stored),
+                    _ => None,
+                },
+            }).collect();
+        let (indexes, foreign_keys) = if kind == TableKind::View {
+            (Vec::new(), Vec::new())
+        } else {
+            (self.table_indexes(schema, &name)?, self.table_foreign_keys(schema, &name)?)
+        };
+        Ok(Table {
+            name,
+            kind,
+            without_rowid: int(row, "wr") != 0,
+            strict: int(row, "strict") != 0,
+            sql: opt_text(row, "sql"),
+            columns,
+            indexes,
+            foreign_keys,
+        })
+    }
+
+    /// The indexes of `table`, including those created for constraints.
+    pub fn table_indexes(&self, schema: &str, table: &str) -> SqliteResult<Vec<Index>> {
+        let args = [Text(table.to_string()), Text(schema.to_string())];
+        let list = self.schema_rows("SELECT * FROM pragma_index_list(?1, ?2) ORDER BY name", &args)?;
+        list.iter().map(|i| {
+            let name = text(i, "name");
+            let args = [Text(name.clone()), Text(schema.to_string())];
+            let columns = self.schema_rows("SELECT * FROM pragma_index_xinfo(?1, ?2) WHERE key ORDER BY seqno", &args)?
+                .iter().map(|c| IndexColumn {
+                    name: opt_text(c, "name"),
+                    descending: int(c, "desc") != 0,
+                    collation: text(c, "coll"),
+                }).collect();
+            Ok(Index {
+                name,
+                unique: int(i, "unique") != 0,
+                origin: match &*text(i, "origin") {
+                    "u" => IndexOrigin::Unique,
+                    "pk" => IndexOrigin::PrimaryKey,
+                    _ => IndexOrigin::CreateIndex,
+                },
+                partial: int(i, "partial") != 0,
+                columns,
+            })
+        }).collect()
+    }
+
+    /// The foreign keys of `table`.
+    pub fn table_foreign_keys(&self, schema: &str, table: &str) -> SqliteResult<Vec<ForeignKey>> {
+        let args = [Text(table.to_string()), Text(schema.to_string())];
+        let rows = self.schema_rows("SELECT * FROM pragma_foreign_key_list(?1, ?2) ORDER BY id, seq", &args)?;
+        let mut keys: Vec<(i64, ForeignKey)> = Vec::new();
+        for row in &rows {
+            let id = int(row, "id");
+            if keys.last().map(|k| k.0) != Some(id) {
+                keys.push((id, ForeignKey {
+                    table: text(row, "table"),
+                    from: Vec::new(),
+                    to: Vec::new(),
+                    on_update: text(row, "on_update"),
+                    on_delete: text(row, "on_delete"),
+                }));
+            }
+            let key = &mut keys.last_mut().unwrap().1;
+            key.from.push(text(row, "from"));
+            key.to.push(opt_text(row, "to"));
+        }
+        Ok(keys.into_iter().map(|k| k.1).collect())
+    }
+
+    /// The triggers of the database `schema`.
+    pub fn triggers(&self, schema: &str) -> SqliteResult<Vec<Trigger>> {
+        let sql = format!("SELECT name, tbl_name, sql FROM {}.sqlite_schema WHERE type = 'trigger' ORDER BY name",
+                          quote_identifier(schema));
+        Ok(self.schema_rows(&sql, &[])?.iter().map(|row| Trigger {
+            name: text(row, "name"),
+            table: text(row, "tbl_name"),
+            sql: text(row, "sql"),
+        }).collect())
+    }
+}
@@ -0,0 +1,411 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Binding Rust values to named parameters with serde, available with the
+//! `serde` feature.
+//!
+//! The fields of a struct (or entries of a map) bind to the parameters of
+//! the same name, prefixed with ':', '@' or '$'. Booleans bind as integers,
+//! unit enum variants as their name, and sequences, maps, structs and other
+//! variants as JSON text. Byte strings bind as blobs, but serde serializes
+//! `Vec<u8>` and `[u8]` as sequences, so such fields need
+//! `#[serde(with = "serde_bytes")]` to bind as blobs rather than JSON arrays.
+
+use cursor::Cursor;
+use serde::ser::{self, Impossible, Serialize, Serializer};
+use serde_json;
+use serde_json::value::Serializer as JsonSerializer;
+use std::error;
+use std::fmt;
+use types::*;
+use types::BindArg::*;
+use types::ResultCode::*;
+
+/// Why a value could not be bound.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SerializeError {
+    /// Binding a parameter failed.
+    Sqlite(ResultCode),
+    /// The statement has parameters matching no field of the value; positional
+    /// parameters are listed as "?N".
+    UnboundParameters(Vec<String>),
+    Message(String),
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SerializeError::Sqlite(code) => write!(f, "{:?}", code),
+            SerializeError::UnboundParameters(ref params) => write!(f, "unbound parameters {}", params.join(", ")),
+            SerializeError::Message(ref message) => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for SerializeError {}
+
+impl ser::Error for SerializeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerializeError {
+        SerializeError::Message(msg.to_string())
+    }
+}
+
+impl From<serde_json::Error> for SerializeError {
+    fn from(err: serde_json::Error) -> SerializeError {
+        SerializeError::Message(err.to_string())
+    }
+}
+
+/// Converts a value to the `BindArg` it binds as.
+pub fn to_bind_arg<T: Serialize + ?Sized>(value: &T) -> Result<BindArg, SerializeError> {
+    value.serialize(ValueSerializer)
+}
+
+impl<'db> Cursor<'db> {
+    /// Binds the fields of `value`, a struct or a map with string keys, to
+    /// the parameters of the same name. Fields without a parameter are
+    /// ignored, but all the parameters must be bound.
+    ///
+    /// A `Vec<u8>` field binds as JSON text, e.g. `[1,2]`, unless it is
+    /// marked `#[serde(with = "serde_bytes")]`.
+    pub fn bind_serialized<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        let fields = value.serialize(FieldsSerializer)?;
+        let count = self.get_bind_count();
+        let mut bound = vec![false; count as usize];
+        for (name, value) in &fields {
+            for prefix in &[":", "@", "$"] {
+                let i = self.get_bind_index(&format!("{}{}", prefix, name));
+                if i == 0 {
+                    continue;
+                }
+                let r = self.bind_param(i, value);
+                if r != SQLITE_OK {
+                    return Err(SerializeError::Sqlite(r));
+                }
+                bound[i as usize - 1] = true;
+            }
+        }
+        let unbound: Vec<String> = (1..count + 1).filter(|&i| !bound[i as usize - 1])
+            .map(|i| self.get_bind_name(i).unwrap_or_else(|| format!("?{}", i)))
+            .collect();
+        if !unbound.is_empty() {
+            return Err(SerializeError::UnboundParameters(unbound));
+        }
+        Ok(())
+    }
+}
+
+fn expected_fields<T>() -> Result<T, SerializeError> {
+    Err(SerializeError::Message("expected a struct or a map".to_string()))
+}
+
+/// Collects the fields of a struct or map.
+struct FieldsSerializer;
+
+struct Fields {
+    fields: Vec<(String, BindArg)>,
+    key: Option<String>,
+}
+
+impl Serializer for FieldsSerializer {
+    type Ok = Vec<(String, BindArg)>;
+    type Error = SerializeError;
+    type SerializeSeq = Impossible<Self::Ok, SerializeError>;
+    type SerializeTuple = Impossible<Self::Ok, SerializeError>;
+    type SerializeTupleStruct = Impossible<Self::Ok, SerializeError>;
+    type SerializeTupleVariant = Impossible<Self::Ok, SerializeError>;
+    type SerializeMap = Fields;
+    type SerializeStruct = Fields;
+    type SerializeStructVariant = Impossible<Self::Ok, SerializeError>;
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Fields, SerializeError> {
+        Ok(Fields { fields: Vec::with_capacity(len), key: None })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Fields, SerializeError> {
+        Ok(Fields { fields: Vec::with_capacity(len.unwrap_or(0)), key: None })
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T)
+                                                       -> Result<Self::Ok, SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_str(self, _v: &str) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_none(self) -> Result<Self::Ok, SerializeError> { expected_fields() }
+    fn serialize_unit(self) -> Result<Self::Ok, SerializeError> { expected_fields() }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, SerializeError> {
+        Ok(Vec::new())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str)
+                              -> Result<Self::Ok, SerializeError> {
+        expected_fields()
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32,
+                                                        _variant: &'static str, _value: &T)
+                                                        -> Result<Self::Ok, SerializeError> {
+        expected_fields()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, SerializeError> {
+        expected_fields()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, SerializeError> {
+        expected_fields()
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize)
+                              -> Result<Self::SerializeTupleStruct, SerializeError> {
+        expected_fields()
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+                               -> Result<Self::SerializeTupleVariant, SerializeError> {
+        expected_fields()
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+                                -> Result<Self::SerializeStructVariant, SerializeError> {
+        expected_fields()
+    }
+}
+
+impl ser::SerializeStruct for Fields {
+    type Ok = Vec<(String, BindArg)>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T)
+                                              -> Result<(), SerializeError> {
+        self.fields.push((key.to_string(), to_bind_arg(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, SerializeError> {
+        Ok(self.fields)
+    }
+}
+
+impl ser::SerializeMap for Fields {
+    type Ok = Vec<(String, BindArg)>;
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerializeError> {
+        match to_bind_arg(key)? {
+            Text(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(SerializeError::Message("map keys must be strings".to_string())),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        let key = self.key.take().unwrap();
+        self.fields.push((key, to_bind_arg(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, SerializeError> {
+        Ok(self.fields)
+    }
+}
+
+/// Converts a value to a `BindArg`, going through JSON for compound values.
+struct ValueSerializer;
+
+/// A compound value being serialized as JSON.
+struct Json<S>(S);
+
+fn json_text(value: serde_json::Value) -> BindArg {
+    Text(value.to_string())
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = BindArg;
+    type Error = SerializeError;
+    type SerializeSeq = Json<<JsonSerializer as Serializer>::SerializeSeq>;
+    type SerializeTuple = Json<<JsonSerializer as Serializer>::SerializeTuple>;
+    type SerializeTupleStruct = Json<<JsonSerializer as Serializer>::SerializeTupleStruct>;
+    type SerializeTupleVariant = Json<<JsonSerializer as Serializer>::SerializeTupleVariant>;
+    type SerializeMap = Json<<JsonSerializer as Serializer>::SerializeMap>;
+    type SerializeStruct = Json<<JsonSerializer as Serializer>::SerializeStruct>;
+    type SerializeStructVariant = Json<<JsonSerializer as Serializer>::SerializeStructVariant>;
+
+    fn serialize_bool(self, v: bool) -> Result<BindArg, SerializeError> { Ok(Integer64(v as i64)) }
+    fn serialize_i8(self, v: i8) -> Result<BindArg, SerializeError> { Ok(Integer64(v as i64)) }
+    fn serialize_i16(self, v: i16) -> Result<BindArg, SerializeError> { Ok(Integer64(v as i64)) }
+    fn serialize_i32(self, v: i32) -> Result<BindArg, SerializeError> { Ok(Integer64(v as i64)) }
+    fn serialize_i64(self, v: i64) -> Result<BindArg, SerializeError> { Ok(Integer64(v)) }
+    fn serialize_u8(self, v: u8) -> Result<BindArg, SerializeError> { Ok(Integer64(v as i64)) }
+    fn serialize_u16(self, v: u16) -> Result<BindArg, SerializeError> { Ok(Integer64(v as i64)) }
+    fn serialize_u32(self, v: u32) -> Result<BindArg, SerializeError> { Ok(Integer64(v as i64)) }
+
+    fn serialize_u64(self, v: u64) -> Result<BindArg, SerializeError> {
+        if v > i64::MAX as u64 {
+            return Err(SerializeError::Message(format!("{} does not fit in an INTEGER", v)));
+        }
+        Ok(Integer64(v as i64))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<BindArg, SerializeError> { Ok(Float64(v as f64)) }
+    fn serialize_f64(self, v: f64) -> Result<BindArg, SerializeError> { Ok(Float64(v)) }
+    fn serialize_char(self, v: char) -> Result<BindArg, SerializeError> { Ok(Text(v.to_string())) }
+    fn serialize_str(self, v: &str) -> Result<BindArg, SerializeError> { Ok(Text(v.to_string())) }
+    fn serialize_bytes(self, v: &[u8]) -> Result<BindArg, SerializeError> { Ok(Blob(v.to_vec())) }
+    fn serialize_none(self) -> Result<BindArg, SerializeError> { Ok(Null) }
+    fn serialize_unit(self) -> Result<BindArg, SerializeError> { Ok(Null) }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<BindArg, SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<BindArg, SerializeError> {
+        Ok(Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str)
+                              -> Result<BindArg, SerializeError> {
+        Ok(Text(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T)
+                                                       -> Result<BindArg, SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, name: &'static str, index: u32,
+                                                        variant: &'static str, value: &T)
+                                                        -> Result<BindArg, SerializeError> {
+        Ok(json_text(JsonSerializer.serialize_newtype_variant(name, index, variant, value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, SerializeError> {
+        Ok(Json(JsonSerializer.serialize_seq(len)?))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, SerializeError> {
+        Ok(Json(JsonSerializer.serialize_tuple(len)?))
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, len: usize)
+                              -> Result<Self::SerializeTupleStruct, SerializeError> {
+        Ok(Json(JsonSerializer.serialize_tuple_struct(name, len)?))
+    }
+
+    fn serialize_tuple_variant(self, name: &'static str, index: u32, variant: &'static str, len: usize)
+                               -> Result<Self::SerializeTupleVariant, SerializeError> {
+        Ok(Json(JsonSerializer.serialize_tuple_variant(name, index, variant, len)?))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, SerializeError> {
+        Ok(Json(JsonSerializer.serialize_map(len)?))
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct, SerializeError> {
+        Ok(Json(JsonSerializer.serialize_struct(name, len)?))
+    }
+
+    fn serialize_struct_variant(self, name: &'static str, index: u32, variant: &'static str, len: usize)
+                                -> Result<Self::SerializeStructVariant, SerializeError> {
+        Ok(Json(JsonSerializer.serialize_struct_variant(name, index, variant, len)?))
+    }
+}
+
+macro_rules! json_compound {
+    ($($trait_:ident :: $method:ident ($($arg:ident : $ty:ty),*);)*) => {
+        $(
+            impl<S> ser::$trait_ for Json<S>
+                where S: ser::$trait_<Ok = serde_json::Value, Error = serde_json::Error>
+            {
+                type Ok = BindArg;
+                type Error = SerializeError;
+
+                fn $method<T: Serialize + ?Sized>(&mut self, $($arg: $ty,)* value: &T)
+                                                  -> Result<(), SerializeError> {
+                    Ok(self.0.$method($($arg,)* value)?)
+                }
+
+                fn end(self) -> Result<BindArg, SerializeError> {
+                    Ok(json_text(self.0.end()?))
+                }
+            }
+        )*
+    }
+}
+
+json_compound! {
+    SerializeSeq::serialize_element();
+    SerializeTuple::serialize_element();
+    SerializeTupleStruct::serialize_field();
+    SerializeTupleVariant::serialize_field();
+    SerializeStruct::serialize_field(key: &'static str);
+    SerializeStructVariant::serialize_field(key: &'static str);
+}
+
+impl<S> ser::SerializeMap for Json<S>
+    where S: ser::SerializeMap<Ok = serde_json::Value, Error = serde_json::Error>
+{
+    type Ok = BindArg;
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerializeError> {
+        Ok(self.0.serialize_key(key)?)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        Ok(self.0.serialize_value(value)?)
+    }
+
+    fn end(self) -> Result<BindArg, SerializeError> {
+        Ok(json_text(self.0.end()?))
+    }
+}
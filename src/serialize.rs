@@ -0,0 +1,93 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Copying a database to and from memory.
+//! See http://www.sqlite.org/c3ref/serialize.html
+
+use ffi::*;
+use libc::c_void;
+use std::ffi::CString;
+use std::ptr;
+use std::slice;
+use database::Database;
+use types::*;
+use types::ResultCode::*;
+
+const SQLITE_DESERIALIZE_FREEONCLOSE: u32 = 1;
+const SQLITE_DESERIALIZE_RESIZEABLE: u32 = 2;
+const SQLITE_DESERIALIZE_READONLY: u32 = 4;
+
+impl Database {
+
+    /// Copies the content of the database `schema` ("main", "temp" or an
+    /// attached database), as it would be stored on disk.
+    /// See http://www.sqlite.org/c3ref/serialize.html
+    pub fn serialize(&self, schema: &str) -> SqliteResult<Vec<u8>> {
+        let schema = CString::new(schema).unwrap();
+        let mut size = 0;
+        let data = unsafe { sqlite3_serialize(self.dbh, schema.as_ptr(), &mut size, 0) };
+        if data.is_null() {
+            if unsafe { sqlite3_db_filename(self.dbh, schema.as_ptr()) }.is_null() {
+                return Err(SQLITE_ERROR);
+            }
+            // an empty database has no pages to copy.
+            return if size == 0 { Ok(Vec::new()) } else { Err(SQLITE_NOMEM) };
+        }
+        let v = unsafe { slice::from_raw_parts(data, size as usize).to_vec() };
+        unsafe { sqlite3_free(data as *mut c_void); }
+        Ok(v)
+    }
+
+    /// Replaces the database `schema` with an in-memory copy of `data`.
+    /// Unless `read_only`, the copy can be written to and grow; changes are
+    /// lost when the connection closes.
+    /// See http://www.sqlite.org/c3ref/deserialize.html
+    pub fn deserialize(&mut self, schema: &str, data: &[u8], read_only: bool) -> SqliteResult<()> {
+        let schema = CString::new(schema).unwrap();
+        // SQLite takes ownership of the buffer, which must come from its
+        // allocator to be freed or resized.
+        let buf = unsafe { sqlite3_malloc64(data.len().max(1) as u64) } as *mut u8;
+        if buf.is_null() {
+            return Err(SQLITE_NOMEM);
+        }
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len()); }
+        let flags = SQLITE_DESERIALIZE_FREEONCLOSE | if read_only {
+            SQLITE_DESERIALIZE_READONLY
+        } else {
+            SQLITE_DESERIALIZE_RESIZEABLE
+        };
+        // the buffer is freed even on failure.
+        let r = unsafe {
+            sqlite3_deserialize(self.dbh, schema.as_ptr(), buf, data.len() as i64, data.len() as i64, flags)
+        };
+        if r == SQLITE_OK { Ok(()) } else { Err(r) }
+    }
+}
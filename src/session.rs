@@ -0,0 +1,510 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Recording changes with the session extension, available with the
+//! `session` feature.
+//!
+//! A `Session` records the changes made to the tables attached to it, and
+//! produces them as a `Changeset` (or a smaller patchset) which can be
+//! inspected, inverted, concatenated with others and stored or sent elsewhere,
+//! to be applied to another database with `Database::apply_changeset`.
+//! See http://www.sqlite.org/sessionintro.html
+
+use ffi::*;
+use libc::{c_int, c_void, c_char};
+use std::ffi::{CStr, CString};
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use database::Database;
+use types::*;
+use types::ResultCode::*;
+use vtab::Value;
+
+/// The kind of change made to a row.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A change to a row of a table, as recorded in a changeset.
+///
+/// `old` holds the row before the change and `new` the row after it, with
+/// `None` for values which are not recorded: all of `old` for an insert, all
+/// of `new` for a delete, and for an update the columns left unchanged
+/// (except for the primary key, in `old`). Patchsets omit old values other
+/// than the primary key.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Change {
+    pub table: String,
+    pub op: Operation,
+    pub indirect: bool,
+    /// Whether each column is part of the primary key.
+    pub primary_key: Vec<bool>,
+    pub old: Vec<Option<BindArg>>,
+    pub new: Vec<Option<BindArg>>,
+}
+
+type ValueFn = unsafe extern "C" fn(*mut changeset_iter, c_int, *mut *mut value) -> ResultCode;
+
+unsafe fn column_values(it: *mut changeset_iter, ncol: c_int, f: ValueFn) -> Vec<Option<BindArg>> {
+    (0..ncol).map(|i| {
+        let mut v = ptr::null_mut();
+        if f(it, i, &mut v) != SQLITE_OK || v.is_null() {
+            None
+        } else {
+            Some(Value::from_raw(v).to_bind_arg())
+        }
+    }).collect()
+}
+
+impl Change {
+    /// Copies the change `it` points to.
+    pub(crate) unsafe fn from_iter(it: *mut changeset_iter) -> SqliteResult<Change> {
+        let mut table = ptr::null();
+        let mut ncol = 0;
+        let mut op = 0;
+        let mut indirect = 0;
+        let r = sqlite3changeset_op(it, &mut table, &mut ncol, &mut op, &mut indirect);
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        let mut pk = ptr::null_mut();
+        let r = sqlite3changeset_pk(it, &mut pk, ptr::null_mut());
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        let op = match op {
+            18 => Operation::Insert,
+            23 => Operation::Update,
+            _ => Operation::Delete,
+        };
+        Ok(Change {
+            table: CStr::from_ptr(table).to_string_lossy().into_owned(),
+            op,
+            indirect: indirect != 0,
+            primary_key: slice::from_raw_parts(pk, ncol as usize).iter().map(|&b| b != 0).collect(),
+            old: if op == Operation::Insert {
+                vec![None; ncol as usize]
+            } else {
+                column_values(it, ncol, sqlite3changeset_old)
+            },
+            new: if op == Operation::Delete {
+                vec![None; ncol as usize]
+            } else {
+                column_values(it, ncol, sqlite3changeset_new)
+            },
+        })
+    }
+}
+
+/// Takes ownership of a buffer allocated by SQLite.
+unsafe fn take_buffer(n: c_int, p: *mut c_void) -> Vec<u8> {
+    let v = if p.is_null() {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(p as *const u8, n as usize).to_vec()
+    };
+    sqlite3_free(p);
+    v
+}
+
+/// A changeset or patchset, in SQLite's binary format.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Changeset {
+    data: Vec<u8>,
+}
+
+impl Changeset {
+    /// Wraps a changeset received elsewhere. It is only checked when used.
+    pub fn from_bytes(data: Vec<u8>) -> Changeset {
+        Changeset { data }
+    }
+
+    /// Reads a changeset from `r`, until its end.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Changeset> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        Ok(Changeset { data })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Writes the changeset to `w`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.data)
+    }
+
+    /// Iterates over the changes, grouped by table.
+    /// See http://www.sqlite.org/session/sqlite3changeset_start.html
+    pub fn iter(&self) -> SqliteResult<ChangesetIter<'_>> {
+        let mut it = ptr::null_mut();
+        let r = unsafe {
+            sqlite3changeset_start(&mut it, self.data.len() as c_int, self.data.as_ptr() as *mut c_void)
+        };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        Ok(ChangesetIter { it, done: false, _marker: PhantomData })
+    }
+
+    /// The changeset undoing this one. Patchsets cannot be inverted.
+    /// See http://www.sqlite.org/session/sqlite3changeset_invert.html
+    pub fn invert(&self) -> SqliteResult<Changeset> {
+        let mut n = 0;
+        let mut p = ptr::null_mut();
+        let r = unsafe {
+            sqlite3changeset_invert(self.data.len() as c_int, self.data.as_ptr() as *const c_void, &mut n, &mut p)
+        };
+        let data = unsafe { take_buffer(n, p) };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        Ok(Changeset { data })
+    }
+
+    /// The changeset with the effect of this one followed by `other`. Both
+    /// must be changesets, or both patchsets.
+    /// See http://www.sqlite.org/session/sqlite3changeset_concat.html
+    pub fn concat(&self, other: &Changeset) -> SqliteResult<Changeset> {
+        let mut n = 0;
+        let mut p = ptr::null_mut();
+        let r = unsafe {
+            sqlite3changeset_concat(self.data.len() as c_int, self.data.as_ptr() as *mut c_void,
+                                    other.data.len() as c_int, other.data.as_ptr() as *mut c_void,
+                                    &mut n, &mut p)
+        };
+        let data = unsafe { take_buffer(n, p) };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        Ok(Changeset { data })
+    }
+}
+
+/// An iterator over the changes of a `Changeset`.
+pub struct ChangesetIter<'a> {
+    it: *mut changeset_iter,
+    done: bool,
+    _marker: PhantomData<&'a Changeset>,
+}
+
+impl<'a> Iterator for ChangesetIter<'a> {
+    type Item = SqliteResult<Change>;
+
+    fn next(&mut self) -> Option<SqliteResult<Change>> {
+        if self.done {
+            return None;
+        }
+        match unsafe { sqlite3changeset_next(self.it) } {
+            SQLITE_ROW => Some(unsafe { Change::from_iter(self.it) }),
+            SQLITE_DONE => {
+                self.done = true;
+                None
+            }
+            r => {
+                self.done = true;
+                Some(Err(r))
+            }
+        }
+    }
+}
+
+impl<'a> Drop for ChangesetIter<'a> {
+    fn drop(&mut self) {
+        unsafe { sqlite3changeset_finalize(self.it); }
+    }
+}
+
+struct Output<'w> {
+    writer: &'w mut dyn Write,
+    error: Option<io::Error>,
+}
+
+extern "C" fn write_output(ctx: *mut c_void, data: *const c_void, n: c_int) -> c_int {
+    let out = unsafe { &mut *(ctx as *mut Output) };
+    let data = unsafe { slice::from_raw_parts(data as *const u8, n as usize) };
+    let r = panic::catch_unwind(AssertUnwindSafe(|| out.writer.write_all(data)));
+    match r {
+        Ok(Ok(())) => SQLITE_OK as c_int,
+        Ok(Err(e)) => {
+            out.error = Some(e);
+            SQLITE_IOERR as c_int
+        }
+        Err(_) => SQLITE_IOERR as c_int,
+    }
+}
+
+/// Why a change could not be applied as is.
+/// See http://www.sqlite.org/session/c_changeset_abort.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictType {
+    /// The row to update or delete exists, but with other values than the
+    /// old ones of the change.
+    Data,
+    /// The row to update or delete does not exist.
+    NotFound,
+    /// The row to insert already exists.
+    Conflict,
+    /// Applying the change violates a NOT NULL, CHECK or UNIQUE constraint.
+    Constraint,
+    /// Foreign keys are violated once all changes are applied.
+    ForeignKey,
+}
+
+/// How to resolve a conflict.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictAction {
+    /// Skips the change.
+    Omit,
+    /// Overwrites the conflicting row with the change. Only valid for
+    /// `ConflictType::Data` and `ConflictType::Conflict`.
+    Replace,
+    /// Rolls back all the changes applied, making `apply_changeset` fail with
+    /// `SQLITE_ABORT`.
+    Abort,
+}
+
+/// The change which could not be applied, passed to the conflict handler of
+/// `Database::apply_changeset`.
+pub struct ConflictItem<'a> {
+    it: *mut changeset_iter,
+    kind: ConflictType,
+    _marker: PhantomData<&'a Changeset>,
+}
+
+impl<'a> ConflictItem<'a> {
+    /// The change, except for `ConflictType::ForeignKey`.
+    pub fn change(&self) -> SqliteResult<Change> {
+        if self.kind == ConflictType::ForeignKey {
+            return Err(SQLITE_MISUSE);
+        }
+        unsafe { Change::from_iter(self.it) }
+    }
+
+    /// The row currently in the table, for `ConflictType::Data` and
+    /// `ConflictType::Conflict`.
+    pub fn conflicting(&self) -> Option<Vec<BindArg>> {
+        if self.kind != ConflictType::Data && self.kind != ConflictType::Conflict {
+            return None;
+        }
+        let mut ncol = 0;
+        unsafe {
+            sqlite3changeset_op(self.it, &mut ptr::null(), &mut ncol, &mut 0, &mut 0);
+            column_values(self.it, ncol, sqlite3changeset_conflict).into_iter().collect()
+        }
+    }
+
+    /// The number of foreign key violations, for `ConflictType::ForeignKey`.
+    pub fn foreign_key_conflicts(&self) -> Option<i32> {
+        let mut n = 0;
+        if self.kind != ConflictType::ForeignKey || unsafe { sqlite3changeset_fk_conflicts(self.it, &mut n) } != SQLITE_OK {
+            return None;
+        }
+        Some(n)
+    }
+}
+
+struct Handlers<'f> {
+    filter: &'f mut dyn FnMut(&str) -> bool,
+    conflict: &'f mut dyn FnMut(ConflictType, &ConflictItem) -> ConflictAction,
+}
+
+extern "C" fn apply_filter(ctx: *mut c_void, table: *const c_char) -> c_int {
+    let handlers = unsafe { &mut *(ctx as *mut Handlers) };
+    let table = unsafe { CStr::from_ptr(table) }.to_string_lossy();
+    let r = panic::catch_unwind(AssertUnwindSafe(|| (handlers.filter)(&table)));
+    r.unwrap_or(false) as c_int
+}
+
+extern "C" fn apply_conflict(ctx: *mut c_void, kind: c_int, it: *mut changeset_iter) -> c_int {
+    let handlers = unsafe { &mut *(ctx as *mut Handlers) };
+    let kind = match kind {
+        1 => ConflictType::Data,
+        2 => ConflictType::NotFound,
+        3 => ConflictType::Conflict,
+        4 => ConflictType::Constraint,
+        _ => ConflictType::ForeignKey,
+    };
+    let item = ConflictItem { it, kind, _marker: PhantomData };
+    let r = panic::catch_unwind(AssertUnwindSafe(|| (handlers.conflict)(kind, &item)));
+    match r.unwrap_or(ConflictAction::Abort) {
+        ConflictAction::Omit => 0,
+        ConflictAction::Replace => 1,
+        ConflictAction::Abort => 2,
+    }
+}
+
+/// Records the changes made through a connection.
+/// See http://www.sqlite.org/session/sqlite3session_create.html
+pub struct Session<'db> {
+    session: *mut session,
+    _marker: PhantomData<&'db Database>,
+}
+
+impl<'db> Session<'db> {
+    /// Records changes to `table`, or to all tables when `None`. Only tables
+    /// with a primary key are recorded.
+    /// See http://www.sqlite.org/session/sqlite3session_attach.html
+    pub fn attach(&mut self, table: Option<&str>) -> SqliteResult<()> {
+        let table = table.map(|t| CString::new(t).unwrap());
+        let r = unsafe {
+            sqlite3session_attach(self.session, table.as_ref().map_or(ptr::null(), |t| t.as_ptr()))
+        };
+        if r == SQLITE_OK { Ok(()) } else { Err(r) }
+    }
+
+    /// Pauses or resumes recording.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        unsafe { sqlite3session_enable(self.session, enabled as c_int); }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        unsafe { sqlite3session_enable(self.session, -1) != 0 }
+    }
+
+    /// Marks the changes recorded from now on as indirect, e.g. made by
+    /// triggers or foreign key actions on behalf of the application.
+    pub fn set_indirect(&mut self, indirect: bool) {
+        unsafe { sqlite3session_indirect(self.session, indirect as c_int); }
+    }
+
+    /// Whether no changes have been recorded.
+    pub fn is_empty(&self) -> bool {
+        unsafe { sqlite3session_isempty(self.session) != 0 }
+    }
+
+    /// See http://www.sqlite.org/session/sqlite3session_changeset.html
+    pub fn changeset(&self) -> SqliteResult<Changeset> {
+        self.collect(sqlite3session_changeset)
+    }
+
+    /// A patchset is a smaller changeset, without the old values of updated
+    /// or deleted rows besides their primary key.
+    /// See http://www.sqlite.org/session/sqlite3session_patchset.html
+    pub fn patchset(&self) -> SqliteResult<Changeset> {
+        self.collect(sqlite3session_patchset)
+    }
+
+    /// Streams the changeset to `w` without building it in memory. A failure
+    /// of `w` is reported as `SQLITE_IOERR`.
+    /// See http://www.sqlite.org/session/sqlite3session_changeset_strm.html
+    pub fn write_changeset<W: Write>(&self, w: &mut W) -> SqliteResult<()> {
+        self.stream(w, sqlite3session_changeset_strm)
+    }
+
+    /// Streams the patchset to `w`, as with `write_changeset`.
+    pub fn write_patchset<W: Write>(&self, w: &mut W) -> SqliteResult<()> {
+        self.stream(w, sqlite3session_patchset_strm)
+    }
+
+    fn collect(&self, f: unsafe extern "C" fn(*mut session, *mut c_int, *mut *mut c_void) -> ResultCode)
+               -> SqliteResult<Changeset> {
+        let mut n = 0;
+        let mut p = ptr::null_mut();
+        let r = unsafe { f(self.session, &mut n, &mut p) };
+        let data = unsafe { take_buffer(n, p) };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        Ok(Changeset { data })
+    }
+
+    fn stream(&self, w: &mut dyn Write,
+              f: unsafe extern "C" fn(*mut session, xOutputFn, *mut c_void) -> ResultCode)
+              -> SqliteResult<()> {
+        let mut out = Output { writer: w, error: None };
+        let r = unsafe { f(self.session, write_output, &mut out as *mut Output as *mut c_void) };
+        if out.error.is_some() {
+            return Err(SQLITE_IOERR);
+        }
+        if r == SQLITE_OK { Ok(()) } else { Err(r) }
+    }
+}
+
+impl<'db> Drop for Session<'db> {
+    fn drop(&mut self) {
+        unsafe { sqlite3session_delete(self.session); }
+    }
+}
+
+impl Database {
+
+    /// Starts a session recording changes to the database `name` ("main",
+    /// "temp" or an attached database). No table is recorded until attached.
+    ///
+    /// The session borrows the connection, through which the changes to
+    /// record are made with `exec` or `prepare`, as both take `&self`.
+    /// See http://www.sqlite.org/session/sqlite3session_create.html
+    pub fn session<'db>(&'db self, name: &str) -> SqliteResult<Session<'db>> {
+        let name = CString::new(name).unwrap();
+        let mut s = ptr::null_mut();
+        let r = unsafe { sqlite3session_create(self.dbh, name.as_ptr() as *const c_char, &mut s) };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        Ok(Session { session: s, _marker: PhantomData })
+    }
+
+    /// Applies the changes of `changeset` to the tables for which `filter`
+    /// returns true, in a single savepoint. When a change conflicts with the
+    /// database, `conflict` decides what to do; a panic in it aborts.
+    /// See http://www.sqlite.org/session/sqlite3changeset_apply.html
+    pub fn apply_changeset<F, C>(&mut self, changeset: &Changeset, mut filter: F, mut conflict: C)
+                                 -> SqliteResult<()>
+        where F: FnMut(&str) -> bool,
+              C: FnMut(ConflictType, &ConflictItem) -> ConflictAction
+    {
+        let mut handlers = Handlers { filter: &mut filter, conflict: &mut conflict };
+        let r = unsafe {
+            sqlite3changeset_apply_v2(self.dbh, changeset.data.len() as c_int,
+                                      changeset.data.as_ptr() as *mut c_void,
+                                      Some(apply_filter), Some(apply_conflict),
+                                      &mut handlers as *mut Handlers as *mut c_void,
+                                      ptr::null_mut(), ptr::null_mut(), 0)
+        };
+        if r == SQLITE_OK { Ok(()) } else { Err(r) }
+    }
+}
@@ -0,0 +1,173 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Reference counted connections and statements which keep them alive.
+//!
+//! A `Cursor` borrows its `Database`, so the two cannot be stored side by side
+//! in one struct. A `Statement` instead holds a `SharedDatabase`, the
+//! connection being closed only once the last of them is dropped.
+
+use cursor::*;
+use database::Database;
+use std::cell::{Cell, Ref, RefCell};
+use std::fmt;
+use std::rc::Rc;
+use types::*;
+use types::ResultCode::*;
+
+struct Shared {
+    db: RefCell<Database>,
+    // the number of live `Statement`s.
+    statements: Cell<usize>,
+}
+
+/// A reference counted database connection.
+///
+/// Cloning it yields another handle to the same connection.
+/// Like `Cursor`, it cannot be sent across tasks.
+#[derive(Clone)]
+pub struct SharedDatabase {
+    inner: Rc<Shared>,
+}
+
+impl fmt::Debug for SharedDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inner.db.try_borrow() {
+            Ok(db) => write!(f, "<SharedDatabase dbh={:?}>", db.dbh),
+            Err(_) => f.write_str("<SharedDatabase borrowed>"),
+        }
+    }
+}
+
+impl SharedDatabase {
+    pub fn new(database: Database) -> SharedDatabase {
+        SharedDatabase { inner: Rc::new(Shared { db: RefCell::new(database), statements: Cell::new(0) }) }
+    }
+
+    /// Borrows the connection.
+    /// Panics if it is currently borrowed mutably.
+    pub fn borrow(&self) -> Ref<'_, Database> {
+        self.inner.db.borrow()
+    }
+
+    /// Calls `f` with the connection borrowed mutably, e.g. to run
+    /// `Migrations`.
+    ///
+    /// Fails with `SQLITE_BUSY` while any `Statement` of the connection is
+    /// alive, as `f` could replace the `Database` under them; `exec`,
+    /// `set_busy_timeout` and `pragma_update` below work regardless. Also
+    /// fails if the connection is already borrowed.
+    pub fn with_mut<R, F: FnOnce(&mut Database) -> R>(&self, f: F) -> SqliteResult<R> {
+        if self.inner.statements.get() != 0 {
+            return Err(SQLITE_BUSY);
+        }
+        let mut db = self.inner.db.try_borrow_mut().map_err(|_| SQLITE_BUSY)?;
+        Ok(f(&mut db))
+    }
+
+    fn try_borrow(&self) -> SqliteResult<Ref<'_, Database>> {
+        self.inner.db.try_borrow().map_err(|_| SQLITE_BUSY)
+    }
+
+    /// Like `Database::exec`. Fails with `SQLITE_BUSY` inside `with_mut`.
+    pub fn exec(&self, sql: &str) -> SqliteResult<bool> {
+        self.try_borrow()?.exec(sql)
+    }
+
+    /// Like `Database::set_busy_timeout`. Fails with `SQLITE_BUSY` if the
+    /// connection is borrowed.
+    pub fn set_busy_timeout(&self, ms: isize) -> SqliteResult<()> {
+        let mut db = self.inner.db.try_borrow_mut().map_err(|_| SQLITE_BUSY)?;
+        match db.set_busy_timeout(ms) {
+            SQLITE_OK => Ok(()),
+            r => Err(r),
+        }
+    }
+
+    /// Like `Database::pragma_update`. Fails with `SQLITE_BUSY` if the
+    /// connection is borrowed.
+    pub fn pragma_update(&self, schema: Option<&str>, name: &str, value: &BindArg) -> SqliteResult<Vec<RowMap>> {
+        let mut db = self.inner.db.try_borrow_mut().map_err(|_| SQLITE_BUSY)?;
+        db.pragma_update(schema, name, value)
+    }
+
+    /// Prepares/compiles an SQL statement owning a handle to this connection.
+    /// Fails with `SQLITE_BUSY` inside `with_mut`.
+    /// See http://www.sqlite.org/c3ref/prepare.html
+    pub fn prepare(&self, sql: &str) -> SqliteResult<Statement> {
+        let db = self.try_borrow()?;
+        let stmt = db.prepare(sql, &None)?.into_raw();
+        self.inner.statements.set(self.inner.statements.get() + 1);
+        Ok(Statement {
+            // safe as `db` below keeps the connection open.
+            cursor: unsafe { cursor_from_raw(stmt, db.dbh) },
+            db: self.clone(),
+        })
+    }
+}
+
+/// A prepared statement which keeps its connection alive.
+///
+/// Its `Cursor` is reached through `with_cursor`, for stepping, binding and
+/// reading columns.
+pub struct Statement {
+    // dropped (finalized) before the connection.
+    cursor: Cursor<'static>,
+    db: SharedDatabase,
+}
+
+impl fmt::Debug for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Statement cursor={:?}>", self.cursor)
+    }
+}
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        let statements = &self.db.inner.statements;
+        statements.set(statements.get() - 1);
+    }
+}
+
+impl Statement {
+    /// Returns the connection this statement belongs to.
+    pub fn database(&self) -> &SharedDatabase {
+        &self.db
+    }
+
+    /// Calls `f` with the cursor of this statement.
+    ///
+    /// The cursor's lifetime is local to `f`, so it can neither escape nor
+    /// be swapped with the cursor of another connection.
+    pub fn with_cursor<R, F>(&mut self, f: F) -> R where F: for<'c> FnOnce(&mut Cursor<'c>) -> R {
+        f(&mut self.cursor)
+    }
+}
@@ -4,6 +4,15 @@
 #![allow(missing_copy_implementations)]
 
 #[macro_use] extern crate log;
+#[cfg(any(feature = "serde", feature = "serde_json"))]
+#[cfg_attr(feature = "serde", macro_use)]
+extern crate serde;
+#[cfg(any(feature = "serde", feature = "serde_json"))]
+extern crate serde_json;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "time")]
+extern crate time;
 
 /*
 ** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
@@ -38,25 +47,60 @@
 
 extern crate libc;
 
+pub use convert::*;
 pub use cursor::*;
 pub use database::*;
 use ffi::*;
 pub use types::*;
 use types::ResultCode::*;
+use std::error;
+use std::fmt;
 use std::ptr;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 
+pub mod busy;
+pub mod cache;
+pub mod config;
+pub mod convert;
+pub mod csv;
 pub mod cursor;
 pub mod database;
+pub mod datetime;
+#[cfg(feature = "serde")]
+pub mod de;
+pub mod extension;
+#[allow(non_camel_case_types, non_snake_case)]
 mod ffi;
+#[cfg(feature = "serde_json")]
+pub mod json;
+#[allow(non_camel_case_types)]
+pub mod limits;
+#[allow(non_camel_case_types)]
+pub mod memory;
+pub mod migrations;
+pub mod pool;
+pub mod pragma;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod serialize;
+#[cfg(feature = "session")]
+pub mod session;
+pub mod shared;
+#[allow(non_camel_case_types)]
+pub mod status;
+pub mod tablefn;
+pub mod trace;
 
 #[allow(non_camel_case_types)]
 pub mod types;
+pub mod vtab;
 
 
 
 /// Determines whether an SQL statement is complete.
 /// See http://www.sqlite.org/c3ref/complete.html
+#[allow(clippy::needless_return)]
 pub fn sqlite_complete(sql: &str) -> SqliteResult<bool> {
     let sql = CString::new(sql.as_bytes()).unwrap();
     let r = unsafe {
@@ -73,6 +117,78 @@ pub fn sqlite_complete(sql: &str) -> SqliteResult<bool> {
     }
 }
 
+/// The version of the SQLite library in use, e.g. "3.40.1".
+/// See http://www.sqlite.org/c3ref/libversion.html
+pub fn version() -> &'static str {
+    unsafe { CStr::from_ptr(sqlite3_libversion()) }.to_str().unwrap()
+}
+
+/// The version of the SQLite library in use, as a number: 3040001 for 3.40.1.
+pub fn version_number() -> i32 {
+    unsafe { sqlite3_libversion_number() }
+}
+
+/// The date, time and hash of the check-in the library was built from.
+pub fn source_id() -> &'static str {
+    unsafe { CStr::from_ptr(sqlite3_sourceid()) }.to_str().unwrap()
+}
+
+/// The options the library was compiled with, without their "SQLITE_"
+/// prefix, e.g. "ENABLE_FTS5" or "MAX_ATTACHED=10".
+/// See http://www.sqlite.org/c3ref/compileoption_get.html
+pub fn compile_options() -> Vec<String> {
+    let mut options = Vec::new();
+    loop {
+        let option = unsafe { sqlite3_compileoption_get(options.len() as i32) };
+        if option.is_null() {
+            return options;
+        }
+        options.push(unsafe { CStr::from_ptr(option) }.to_string_lossy().into_owned());
+    }
+}
+
+/// Whether the library was compiled with `option`, with or without its
+/// "SQLITE_" prefix.
+pub fn compile_option_used(option: &str) -> bool {
+    let option = CString::new(option).unwrap();
+    unsafe { sqlite3_compileoption_used(option.as_ptr()) != 0 }
+}
+
+/// The features `require_features` found missing from the library.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MissingFeatures {
+    pub version: String,
+    pub missing: Vec<String>,
+}
+
+impl fmt::Display for MissingFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SQLite {} was compiled without {}", self.version, self.missing.join(", "))
+    }
+}
+
+impl error::Error for MissingFeatures {}
+
+/// Checks the library was compiled with all the `features`, as in
+/// `require_features(&["ENABLE_FTS5", "ENABLE_JSON1"])`.
+///
+/// JSON functions are built in from 3.38.0, so "ENABLE_JSON1" is also
+/// satisfied by these versions unless compiled with "OMIT_JSON".
+pub fn require_features(features: &[&str]) -> Result<(), MissingFeatures> {
+    let missing: Vec<String> = features.iter().filter(|&&feature| {
+        let name = feature.trim_start_matches("SQLITE_");
+        if name == "ENABLE_JSON1" && version_number() >= 3038000 {
+            return compile_option_used("OMIT_JSON");
+        }
+        !compile_option_used(name)
+    }).map(|f| f.to_string()).collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(MissingFeatures { version: version().to_string(), missing })
+    }
+}
+
 
 /// Opens a new database connection.
 /// `path` can either be a filesystem path or ":memory:".
@@ -94,34 +210,99 @@ pub fn open(path: &str) -> SqliteResult<Database> {
     }
 }
 
+/// Opens a new database connection with `flags`, a combination of the
+/// `SQLITE_OPEN_*` constants. One of `SQLITE_OPEN_READONLY` or
+/// `SQLITE_OPEN_READWRITE` (optionally with `SQLITE_OPEN_CREATE`) is required.
+/// See http://www.sqlite.org/c3ref/open.html
+pub fn open_with_flags(path: &str, flags: i32) -> SqliteResult<Database> {
+    let path = CString::new(path.as_bytes()).unwrap();
+    let mut dbh = ptr::null_mut();
+    let r = unsafe {
+        sqlite3_open_v2(path.as_ptr(), &mut dbh, flags, ptr::null())
+    };
+    if r != SQLITE_OK {
+        unsafe {
+            sqlite3_close(dbh);
+        }
+        Err(r)
+    } else {
+        debug!("`open_with_flags()`: dbh={:?}", dbh);
+        Ok(database_with_handle(dbh))
+    }
+}
+
+/// Opens an in-memory database holding a copy of `bytes`, as returned by
+/// `Database::serialize`. See `Database::deserialize`.
+pub fn open_from_bytes(bytes: &[u8], read_only: bool) -> SqliteResult<Database> {
+    let mut db = open(":memory:")?;
+    db.deserialize("main", bytes, read_only)?;
+    Ok(db)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use types::BindArg::*;
-    use types::ResultCode::*;
+    use std::{env, fs, process};
+    use std::sync::{Mutex, Once};
     use std::thread;
 
     fn checked_prepare<'db>(database: &'db Database, sql: &str) -> Cursor<'db> {
         match database.prepare(sql, &None) {
             Ok(s)  => s,
-            Err(x) => panic!(format!("sqlite error: \"{}\" ({:?})", database.get_errmsg(), x)),
+            Err(x) => panic!("sqlite error: \"{}\" ({:?})", database.get_errmsg(), x),
         }
     }
 
     fn checked_open() -> Database {
         match open(":memory:") {
             Ok(database) => database,
-            Err(ref e) => panic!(format!("{:?}", *e)),
+            Err(ref e) => panic!("{:?}", *e),
         }
     }
 
+    fn temp_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("rustsqlite-{}-{}", process::id(), name));
+        let path = path.to_str().unwrap().to_string();
+        for suffix in &["", "-wal", "-shm", "-journal"] {
+            let _ = fs::remove_file(format!("{}{}", path, suffix));
+        }
+        path
+    }
+
     fn checked_exec(database: &mut Database, sql: &str) {
         match database.exec(sql) {
             Ok(..) => {}
-            Err(x) => panic!(format!("sqlite error: \"{}\" ({:?})", database.get_errmsg(), x)),
+            Err(x) => panic!("sqlite error: \"{}\" ({:?})", database.get_errmsg(), x),
+        }
+    }
+
+    static LOGGED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    struct CapturingLogger;
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _: &log::LogMetadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::LogRecord) {
+            LOGGED.lock().unwrap().push((record.target().to_string(), record.args().to_string()));
         }
     }
 
+    /// Installs a logger recording every log record, returning the records.
+    fn captured_logs() -> &'static Mutex<Vec<(String, String)>> {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(|max| {
+                max.set(log::LogLevelFilter::Trace);
+                Box::new(CapturingLogger)
+            }).unwrap();
+        });
+        &LOGGED
+    }
+
     #[test]
     fn open_db() {
         checked_open();
@@ -361,9 +542,9 @@ mod tests {
         match possible_row {
             Some(x) => {
                 let mut x = x;
-                assert!(x.remove(&"id".to_string()) == Some(Integer(2)));
-                assert!(x.remove(&"k".to_string())  == Some(Text("e".to_string())));
-                assert!(x.remove(&"v".to_string())  == Some(Float64(2.17)));
+                assert!(x.remove("id") == Some(Integer(2)));
+                assert!(x.remove("k") == Some(Text("e".to_string())));
+                assert!(x.remove("v") == Some(Float64(2.17)));
             }
             None => {
                 panic!("didnt get even one row back.");
@@ -372,6 +553,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_return)]
     fn check_complete_sql() {
         let r1 = sqlite_complete("SELECT * FROM");
         let r2 = sqlite_complete("SELECT * FROM bob;");
@@ -399,14 +581,1482 @@ mod tests {
         assert_eq!(c.get_text(1), None);
     }
 
+    #[test]
+    fn trace_statements() {
+        use std::sync::{Arc, Mutex};
+        use trace::*;
+
+        let mut db = checked_open();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen2 = seen.clone();
+        db.trace(SQLITE_TRACE_STMT | SQLITE_TRACE_PROFILE | SQLITE_TRACE_ROW, move |event| {
+            let msg = match event {
+                TraceEvent::Statement(ref stmt, _) => format!("stmt {}", stmt.expanded_sql().unwrap()),
+                TraceEvent::Profile(ref stmt, _) => format!("profile {}", stmt.sql().unwrap()),
+                TraceEvent::Row(..) => "row".to_string(),
+                TraceEvent::Close => "close".to_string(),
+            };
+            seen2.lock().unwrap().push(msg);
+        }).unwrap();
+
+        {
+            let mut c = checked_prepare(&db, "SELECT ?");
+            assert_eq!(c.bind_param(1, &Integer(42)), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.step(), SQLITE_DONE);
+        }
+        assert_eq!(*seen.lock().unwrap(),
+                   vec!["stmt SELECT 42".to_string(), "row".to_string(), "profile SELECT ?".to_string()]);
+
+        db.untrace().unwrap();
+        checked_exec(&mut db, "SELECT 1");
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn trace_to_log() {
+        use log::LogLevel;
+        use trace::LogTracer;
+
+        let logs = captured_logs();
+        let traced = || -> Vec<String> {
+            logs.lock().unwrap().iter()
+                .filter(|(target, msg)| target == "sqlite3::trace" && msg.contains("trace_log_test"))
+                .map(|(_, msg)| msg.clone())
+                .collect()
+        };
+        let mut db = checked_open();
+        checked_exec(&mut db, "CREATE TABLE trace_log_test (secret TEXT)");
+        db.trace_to_log(LogTracer::new().statement_level(Some(LogLevel::Info)).redact_params(true)).unwrap();
+        {
+            let mut c = checked_prepare(&db, "INSERT INTO trace_log_test VALUES (?)");
+            assert_eq!(c.bind_param(1, &Text("hunter2".to_string())), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_DONE);
+        }
+        db.trace_to_log(LogTracer::new().statement_level(Some(LogLevel::Info))).unwrap();
+        {
+            let mut c = checked_prepare(&db, "SELECT count(*) FROM trace_log_test WHERE secret = ?");
+            assert_eq!(c.bind_param(1, &Text("visible".to_string())), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+        }
+        db.trace_to_log(LogTracer::new().statement_level(None).profile_level(None)).unwrap();
+        assert!(db.trace_hook.is_none());
+        checked_exec(&mut db, "DELETE FROM trace_log_test");
+
+        let traced = traced();
+        assert!(traced.contains(&"INSERT INTO trace_log_test VALUES (?)".to_string()), "{:?}", traced);
+        assert!(traced.contains(&"SELECT count(*) FROM trace_log_test WHERE secret = 'visible'".to_string()), "{:?}", traced);
+        assert!(!traced.iter().any(|m| m.contains("hunter2") || m.starts_with("DELETE")), "{:?}", traced);
+    }
+
+    #[test]
+    fn busy_handler_gives_up() {
+        use std::sync::{Arc, Mutex};
+
+        let path = temp_path("busy_handler_gives_up.db");
+        let mut locker = open(&path).unwrap();
+        let mut db = open(&path).unwrap();
+        checked_exec(&mut locker, "BEGIN EXCLUSIVE");
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls2 = calls.clone();
+        db.busy_handler(move |attempt| {
+            calls2.lock().unwrap().push(attempt);
+            attempt < 2
+        }).unwrap();
+        assert_eq!(db.exec("BEGIN IMMEDIATE"), Err(SQLITE_BUSY));
+        assert_eq!(*calls.lock().unwrap(), vec![0, 1, 2]);
+
+        assert_eq!(db.set_busy_timeout(0), SQLITE_OK);
+        assert!(db.busy_hook.is_none());
+    }
+
+    #[test]
+    fn retry_policy_transaction() {
+        use busy::RetryPolicy;
+        use std::time::Duration;
+
+        let path = temp_path("retry_policy_transaction.db");
+        let mut db = open(&path).unwrap();
+        checked_exec(&mut db, "CREATE TABLE test (id INTEGER)");
+
+        let mut locker = open(&path).unwrap();
+        checked_exec(&mut locker, "BEGIN EXCLUSIVE");
+
+        let policy = RetryPolicy::new().max_wait(Duration::from_millis(20));
+        let mut runs = 0;
+        let r = policy.transaction(&mut db, |db| { runs += 1; db.exec("INSERT INTO test VALUES (1)") });
+        assert_eq!(r, Err(SQLITE_BUSY));
+        assert_eq!(runs, 0);
+
+        let unlocker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            checked_exec(&mut locker, "ROLLBACK");
+        });
+        let policy = RetryPolicy::new().max_delay(Duration::from_millis(10));
+        let r = policy.transaction(&mut db, |db| { runs += 1; db.exec("INSERT INTO test VALUES (1)") });
+        unlocker.join().unwrap();
+        assert_eq!(r, Ok(true));
+        assert_eq!(runs, 1);
+
+        let mut c = checked_prepare(&db, "SELECT count(*) FROM test");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 1);
+    }
+
+    #[test]
+    fn retry_policy_delays() {
+        use busy::RetryPolicy;
+        use std::time::Duration;
+
+        let policy = RetryPolicy::new().jitter(false)
+            .initial_delay(Duration::from_millis(2)).max_delay(Duration::from_millis(10));
+        assert_eq!(policy.delay(0), Duration::from_millis(2));
+        assert_eq!(policy.delay(2), Duration::from_millis(8));
+        assert_eq!(policy.delay(3), Duration::from_millis(10));
+        assert_eq!(policy.delay(40), Duration::from_millis(10));
+
+        let jittered = policy.jitter(true).delay(1);
+        assert!(jittered >= Duration::from_millis(2) && jittered <= Duration::from_millis(4));
+    }
+
+    #[test]
+    fn prepare_cached_reuses_statements() {
+        let mut db = checked_open();
+        checked_exec(&mut db, "CREATE TABLE test (id INTEGER)");
+
+        {
+            let mut c = db.prepare_cached("SELECT ?").unwrap();
+            assert_eq!(c.bind_param(1, &Integer(1)), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_int(0), 1);
+        }
+        {
+            // returned to the cache with its bindings cleared.
+            let mut c = db.prepare_cached("SELECT ?").unwrap();
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), None);
+        }
+        let stats = db.prepared_statement_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.size), (1, 1, 1));
+
+        // two statements checked out at once are distinct.
+        {
+            let _c1 = db.prepare_cached("SELECT ?").unwrap();
+            let _c2 = db.prepare_cached("SELECT ?").unwrap();
+        }
+        assert_eq!(db.prepared_statement_cache_stats().size, 2);
+
+        db.flush_cache();
+        assert_eq!(db.prepared_statement_cache_stats().size, 0);
+    }
+
+    #[test]
+    fn prepare_cached_eviction_and_schema_changes() {
+        let mut db = checked_open();
+        db.set_prepared_statement_cache_capacity(1);
+        drop(db.prepare_cached("SELECT 1").unwrap());
+        drop(db.prepare_cached("SELECT 2").unwrap());
+        let stats = db.prepared_statement_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.size, stats.capacity), (0, 2, 1, 1));
+        drop(db.prepare_cached("SELECT 2").unwrap());
+        assert_eq!(db.prepared_statement_cache_stats().hits, 1);
+
+        checked_exec(&mut db, "CREATE TABLE test (id INTEGER)");
+        drop(db.prepare_cached("SELECT 2").unwrap());
+        let stats = db.prepared_statement_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.size), (1, 3, 1));
+
+        // writing data leaves the schema, and so the cache, alone.
+        db.set_prepared_statement_cache_capacity(2);
+        assert_eq!(db.prepare_cached("DELETE FROM test").unwrap().step(), SQLITE_DONE);
+        checked_exec(&mut db, "INSERT INTO test VALUES (1)");
+        drop(db.prepare_cached("SELECT 2").unwrap());
+        let stats = db.prepared_statement_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.size), (2, 4, 2));
+
+        // a schema change outside `exec` keeps the cache, whose statements
+        // prepare themselves again.
+        drop(db.prepare_cached("SELECT * FROM test").unwrap());
+        assert_eq!(checked_prepare(&db, "ALTER TABLE test ADD COLUMN name TEXT").step(), SQLITE_DONE);
+        {
+            let mut c = db.prepare_cached("SELECT * FROM test").unwrap();
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_column_count(), 2);
+        }
+        assert_eq!(db.prepared_statement_cache_stats().hits, 3);
+
+        db.set_prepared_statement_cache_capacity(0);
+        drop(db.prepare_cached("SELECT 2").unwrap());
+        assert_eq!(db.prepared_statement_cache_stats().size, 0);
+    }
+
+    #[test]
+    fn owned_statements() {
+        use shared::*;
+
+        struct Service {
+            db: SharedDatabase,
+            insert: Statement,
+            count: Statement,
+        }
+
+        let db = SharedDatabase::new(checked_open());
+        db.with_mut(|db| checked_exec(db, "CREATE TABLE test (id INTEGER)")).unwrap();
+        db.with_mut(|_| {
+            assert_eq!(db.prepare("SELECT 1").err(), Some(SQLITE_BUSY));
+            assert_eq!(db.exec("SELECT 1"), Err(SQLITE_BUSY));
+            assert_eq!(format!("{:?}", db), "<SharedDatabase borrowed>");
+        }).unwrap();
+        let mut service = Service {
+            insert: db.prepare("INSERT INTO test (id) VALUES (?)").unwrap(),
+            count: db.prepare("SELECT count(*) FROM test").unwrap(),
+            db,
+        };
+
+        for i in 0..3 {
+            service.insert.with_cursor(|c| {
+                assert_eq!(c.bind_param(1, &Integer(i)), SQLITE_OK);
+                assert_eq!(c.step(), SQLITE_DONE);
+                assert_eq!(c.reset(), SQLITE_OK);
+            });
+        }
+        assert_eq!(service.db.borrow().get_last_insert_rowid(), 3);
+        // the connection cannot be replaced under live statements, but can
+        // still be used.
+        assert_eq!(service.db.with_mut(|_| ()), Err(SQLITE_BUSY));
+        assert_eq!(service.db.exec("INSERT INTO test (id) VALUES (10)"), Ok(true));
+        assert_eq!(service.db.set_busy_timeout(100), Ok(()));
+        assert!(service.db.pragma_update(None, "cache_size", &Integer(-1000)).is_ok());
+
+        // the statement keeps the connection open on its own.
+        let Service { db, insert, mut count } = service;
+        drop(db);
+        drop(insert);
+        assert_eq!(count.with_cursor(|c| (c.step(), c.get_int(0))), (SQLITE_ROW, 4));
+        assert!(count.database().prepare("SELECT q FRO test").is_err());
+        let db = count.database().clone();
+        drop(count);
+        assert_eq!(db.with_mut(|db| db.exec("SELECT 1")), Ok(Ok(true)));
+    }
+
+    #[test]
+    fn open_read_only() {
+        let path = temp_path("open_read_only.db");
+        assert_eq!(open_with_flags(&path, SQLITE_OPEN_READONLY).err(), Some(SQLITE_CANTOPEN));
+        let mut db = open_with_flags(&path, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE).unwrap();
+        checked_exec(&mut db, "CREATE TABLE test (id INTEGER)");
+        let ro = open_with_flags(&path, SQLITE_OPEN_READONLY).unwrap();
+        assert_eq!(ro.exec("INSERT INTO test VALUES (1)"), Err(SQLITE_READONLY));
+    }
+
+    #[test]
+    fn pool_readers_and_writer() {
+        use pool::Pool;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let connects = Arc::new(AtomicUsize::new(0));
+        let connects2 = connects.clone();
+        let path = temp_path("pool_readers_and_writer.db");
+        let pool = Pool::builder(&path)
+            .readers(2)
+            .checkout_timeout(Duration::from_millis(10))
+            .on_connect(move |db| {
+                connects2.fetch_add(1, Ordering::SeqCst);
+                db.exec("PRAGMA foreign_keys = ON").map(|_| ())
+            })
+            .build().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 3);
+
+        let mut w = pool.writer().unwrap();
+        checked_exec(&mut w, "CREATE TABLE test (id INTEGER); INSERT INTO test VALUES (1);");
+        assert_eq!(pool.writer().err(), Some(SQLITE_BUSY));
+
+        // readers see the last commit while the writer is in a transaction.
+        checked_exec(&mut w, "BEGIN; INSERT INTO test VALUES (2);");
+        {
+            let r1 = pool.reader().unwrap();
+            let r2 = pool.reader().unwrap();
+            assert_eq!(pool.reader().err(), Some(SQLITE_BUSY));
+            assert_eq!(pool.metrics().reader_utilization(), 1.0);
+
+            let mut c = checked_prepare(&r2, "SELECT count(*) FROM test");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_int(0), 1);
+            assert_eq!(r1.exec("INSERT INTO test VALUES (3)"), Err(SQLITE_READONLY));
+        }
+        drop(w);
+
+        // a transaction left open is rolled back when the connection is
+        // returned, releasing its write lock.
+        let mut other = open(&path).unwrap();
+        checked_exec(&mut other, "BEGIN IMMEDIATE; ROLLBACK;");
+        let w = pool.writer().unwrap();
+        let mut c = checked_prepare(&w, "SELECT count(*) FROM test");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 1);
+
+        let metrics = pool.metrics();
+        assert_eq!((metrics.reader_checkouts, metrics.writer_checkouts), (2, 2));
+        assert_eq!((metrics.readers_in_use, metrics.writer_in_use), (0, true));
+        assert_eq!(metrics.timeouts, 2);
+        assert!(metrics.max_wait >= Duration::from_millis(10));
+    }
+
+    mod rows_vtab {
+        use std::sync::{Arc, Mutex};
+        use types::*;
+        use types::BindArg::*;
+        use vtab::*;
+
+        pub type Rows = Arc<Mutex<Vec<(i64, String)>>>;
+
+        pub struct RowsTable { rows: Rows }
+        pub struct RowsCursor { all: Rows, rows: Vec<(usize, i64, String)>, pos: usize }
+
+        impl VTab for RowsTable {
+            type Aux = Rows;
+            type Cursor = RowsCursor;
+
+            fn connect(aux: &Rows, args: &[&str]) -> VTabResult<(String, RowsTable)> {
+                if args.len() > 3 {
+                    return Err(VTabError::message(format!("unexpected argument {}", args[3])));
+                }
+                Ok(("CREATE TABLE x (value INTEGER, name TEXT)".to_string(), RowsTable { rows: aux.clone() }))
+            }
+
+            fn best_index(&self, info: &mut IndexInfo) -> VTabResult<()> {
+                let eq = info.constraints().iter()
+                    .position(|c| c.column == 0 && c.op == ConstraintOp::Eq && c.usable);
+                match eq {
+                    Some(i) => {
+                        info.set_argv_index(i, 1);
+                        info.set_omit(i, true);
+                        info.set_idx_num(1);
+                        info.set_idx_str("by value");
+                        info.set_estimated_cost(1.0);
+                    }
+                    None => info.set_estimated_cost(1000.0),
+                }
+                Ok(())
+            }
+
+            fn open(&self) -> VTabResult<RowsCursor> {
+                Ok(RowsCursor { all: self.rows.clone(), rows: Vec::new(), pos: 0 })
+            }
+
+            fn update(&mut self, change: VTabUpdate) -> VTabResult<i64> {
+                let mut rows = self.rows.lock().unwrap();
+                match change {
+                    VTabUpdate::Insert(_, values) => match (&values[0], &values[1]) {
+                        (&Integer64(v), Text(name)) => rows.push((v, name.clone())),
+                        _ => return Err(VTabError::from(ResultCode::SQLITE_MISMATCH)),
+                    },
+                    VTabUpdate::Delete(rowid) => { rows.remove(rowid as usize - 1); }
+                    VTabUpdate::Update(..) => return Err(VTabError::message("no updates")),
+                }
+                Ok(rows.len() as i64)
+            }
+        }
+
+        impl VTabCursor for RowsCursor {
+            fn filter(&mut self, idx_num: i32, idx_str: Option<&str>, args: &[Value]) -> VTabResult<()> {
+                let all = self.all.lock().unwrap();
+                self.rows = all.iter().enumerate().map(|(i, r)| (i, r.0, r.1.clone())).collect();
+                if idx_num == 1 {
+                    assert_eq!(idx_str, Some("by value"));
+                    let value = args[0].get_i64();
+                    self.rows.retain(|r| r.1 == value);
+                }
+                self.pos = 0;
+                Ok(())
+            }
+            fn next(&mut self) -> VTabResult<()> { self.pos += 1; Ok(()) }
+            fn eof(&self) -> bool { self.pos >= self.rows.len() }
+            fn column(&self, i: i32) -> VTabResult<BindArg> {
+                let row = &self.rows[self.pos];
+                match i {
+                    0 => Ok(Integer64(row.1)),
+                    _ if row.2 == "boom" => panic!("boom"),
+                    _ => Ok(Text(row.2.clone())),
+                }
+            }
+            fn rowid(&self) -> VTabResult<i64> { Ok(self.rows[self.pos].0 as i64 + 1) }
+        }
+    }
+
+    #[test]
+    fn virtual_table_module() {
+        use std::sync::{Arc, Mutex};
+        use self::rows_vtab::RowsTable;
+
+        let rows = Arc::new(Mutex::new(vec![(1, "one".to_string()), (2, "two".to_string())]));
+        let mut db = checked_open();
+        db.create_module::<RowsTable>("rows", rows.clone()).unwrap();
+        checked_exec(&mut db, "CREATE VIRTUAL TABLE temp.t USING rows");
+
+        {
+            let mut c = checked_prepare(&db, "SELECT rowid, name FROM t WHERE value = 2");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_i64(0), 2);
+            assert_eq!(c.get_text(1), Some("two"));
+            assert_eq!(c.step(), SQLITE_DONE);
+        }
+
+        checked_exec(&mut db, "INSERT INTO t VALUES (3, 'three'); DELETE FROM t WHERE value = 1;");
+        assert_eq!(*rows.lock().unwrap(), vec![(2, "two".to_string()), (3, "three".to_string())]);
+        assert_eq!(db.exec("UPDATE t SET name = 'deux' WHERE value = 2"), Err(SQLITE_ERROR));
+        assert_eq!(db.get_errmsg(), "no updates");
+
+        {
+            let mut c = checked_prepare(&db, "SELECT group_concat(name) FROM t ORDER BY value");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), Some("two,three"));
+        }
+
+        rows.lock().unwrap().push((4, "boom".to_string()));
+        {
+            let mut c = checked_prepare(&db, "SELECT name FROM t");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.step(), SQLITE_ERROR);
+        }
+        assert_eq!(db.get_errmsg(), "panic in virtual table");
+
+        assert_eq!(db.exec("CREATE VIRTUAL TABLE temp.u USING rows(bogus)"), Err(SQLITE_ERROR));
+        assert_eq!(db.get_errmsg(), "unexpected argument bogus");
+    }
+
+    #[test]
+    fn csv_inline_data() {
+        let mut db = checked_open();
+        db.load_csv_module().unwrap();
+        checked_exec(&mut db, "CREATE VIRTUAL TABLE temp.t USING csv(
+            data='id,name,score
+1,\"Smith, John\",2.5
+2,\"multi
+line\",
+3,\"say \"\"hi\"\"\",1e3
+', header=yes, infer_types=yes, empty_as_null=yes)");
+
+        let mut c = checked_prepare(&db, "SELECT id, typeof(id), name, score FROM t ORDER BY rowid");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!((c.get_int(0), c.get_text(1).unwrap().to_string()), (1, "integer".to_string()));
+        assert_eq!(c.get_text(2), Some("Smith, John"));
+        assert_eq!(c.get_f64(3), 2.5);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(2), Some("multi\nline"));
+        assert_eq!(c.get_column_type(3), ColumnType::SQLITE_NULL);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(2), Some("say \"hi\""));
+        assert_eq!(c.get_f64(3), 1000.0);
+        assert_eq!(c.step(), SQLITE_DONE);
+    }
+
+    #[test]
+    fn csv_file_with_schema() {
+        let path = temp_path("csv_file_with_schema.csv");
+        fs::write(&path, "a;1\r\n\r\nb;2\r\nc\r\n\r\n\n").unwrap();
+
+        let mut db = checked_open();
+        db.load_csv_module().unwrap();
+        checked_exec(&mut db, &format!(
+            "CREATE VIRTUAL TABLE temp.t USING csv(filename='{}', delimiter=';',
+                                                  schema='CREATE TABLE x (k TEXT, v INTEGER)')", path));
+        {
+            let mut c = checked_prepare(&db, "SELECT group_concat(k || '=' || ifnull(v, '-'), ' ') FROM t");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), Some("a=1 b=2 c=-"));
+        }
+
+        // without a schema, columns are numbered.
+        checked_exec(&mut db, &format!("CREATE VIRTUAL TABLE temp.u USING csv(filename='{}', delimiter=';')", path));
+        {
+            let mut c = checked_prepare(&db, "SELECT c1 FROM u WHERE c0 = 'b'");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), Some("2"));
+        }
+
+        assert_eq!(db.exec("CREATE VIRTUAL TABLE temp.v USING csv(filename='/nonexistent.csv')"), Err(SQLITE_ERROR));
+        assert!(db.get_errmsg().starts_with("csv: cannot open /nonexistent.csv"));
+        assert_eq!(db.exec("CREATE VIRTUAL TABLE temp.w USING csv(data='', bogus=1)"), Err(SQLITE_ERROR));
+        assert_eq!(db.get_errmsg(), "csv: unknown parameter bogus");
+    }
+
+    #[test]
+    fn table_function() {
+        let mut db = checked_open();
+        db.create_table_function("split", &["part"], &["text", "sep"], |args| {
+            let sep = match args[1] { Text(ref s) => s.clone(), _ => ",".to_string() };
+            let parts: Vec<Vec<BindArg>> = match args[0] {
+                Text(ref s) => s.split(&*sep).map(|p| vec![Text(p.to_string())]).collect(),
+                _ => return Err(vtab::VTabError::message("split: text expected")),
+            };
+            Ok(parts)
+        }).unwrap();
+
+        {
+            let mut c = checked_prepare(&db, "SELECT group_concat(part, '|'), sep FROM split('a;b;c', ';')");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), Some("a|b|c"));
+            assert_eq!(c.get_text(1), Some(";"));
+        }
+        {
+            let mut c = checked_prepare(&db, "SELECT count(*) FROM split(?)");
+            assert_eq!(c.bind_param(1, &Text("x,y".to_string())), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_int(0), 2);
+        }
+        {
+            let mut c = checked_prepare(&db, "SELECT * FROM split(1)");
+            assert_eq!(c.step(), SQLITE_ERROR);
+        }
+        assert_eq!(db.get_errmsg(), "split: text expected");
+    }
+
+    #[test]
+    fn generate_series() {
+        let mut db = checked_open();
+        db.load_generate_series().unwrap();
+        let mut c = checked_prepare(&db, "SELECT group_concat(value) FROM generate_series(10, 0, -3)");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(0), Some("10,7,4,1"));
+
+        let mut c = checked_prepare(&db, "SELECT sum(value) FROM generate_series(1, 100)");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 5050);
+
+        let mut c = checked_prepare(&db, "SELECT value FROM generate_series(9223372036854775806) LIMIT 5");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_i64(0), i64::MAX);
+        assert_eq!(c.step(), SQLITE_DONE);
+    }
+
+    #[test]
+    fn bind_array() {
+        use tablefn::*;
+
+        let mut db = checked_open();
+        db.load_array_module().unwrap();
+        db.load_generate_series().unwrap();
+        {
+            let mut c = checked_prepare(&db, "SELECT group_concat(value) FROM generate_series(1, 10) \
+                                              WHERE value IN rarray(?)");
+            assert_eq!(c.bind_array(1, int_array(vec![2, 3, 5, 7, 11])), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), Some("2,3,5,7"));
+
+            // rebinding the parameter releases the previous array.
+            c.reset();
+            assert_eq!(c.bind_array(1, int_array(vec![])), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), None);
+        }
+        {
+            let names = text_array(vec!["b".to_string(), "a".to_string()]);
+            let mut c = checked_prepare(&db, "SELECT value FROM rarray(?) ORDER BY value");
+            assert_eq!(c.bind_array(1, names.clone()), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), Some("a"));
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_text(0), Some("b"));
+            assert_eq!(c.step(), SQLITE_DONE);
+            assert_eq!(::std::rc::Rc::strong_count(&names), 2);
+        }
+        {
+            // not an array.
+            let mut c = checked_prepare(&db, "SELECT count(*) FROM rarray(1)");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_int(0), 0);
+        }
+    }
+
+    #[cfg(feature = "session")]
+    #[test]
+    fn session_changesets() {
+        use session::*;
+
+        let db = checked_open();
+        db.exec("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, n INTEGER); \
+                 CREATE TABLE untracked (id INTEGER PRIMARY KEY); \
+                 INSERT INTO t VALUES (1, 'one', 1), (2, 'two', 2);").unwrap();
+
+        let first = {
+            let mut session = db.session("main").unwrap();
+            session.attach(Some("t")).unwrap();
+            assert!(session.is_empty());
+            db.exec("INSERT INTO t VALUES (3, 'three', 3); UPDATE t SET n = 20 WHERE id = 2; \
+                     DELETE FROM t WHERE id = 1; INSERT INTO untracked VALUES (1);").unwrap();
+            assert!(!session.is_empty());
+
+            let mut streamed = Vec::new();
+            session.write_changeset(&mut streamed).unwrap();
+            let changeset = session.changeset().unwrap();
+            assert_eq!(Changeset::read_from(&mut &streamed[..]).unwrap().iter().unwrap().count(), 3);
+            assert!(session.patchset().unwrap().as_bytes().len() < changeset.as_bytes().len());
+            changeset
+        };
+
+        let mut changes: Vec<Change> = first.iter().unwrap().map(|c| c.unwrap()).collect();
+        changes.sort_by_key(|c| match c.op { Operation::Insert => 0, Operation::Update => 1, Operation::Delete => 2 });
+        assert!(changes.iter().all(|c| c.table == "t" && !c.indirect));
+        assert_eq!(changes[0].primary_key, vec![true, false, false]);
+        assert_eq!(changes[0].old, vec![None, None, None]);
+        assert_eq!(changes[0].new, vec![Some(Integer64(3)), Some(Text("three".to_string())), Some(Integer64(3))]);
+        assert_eq!(changes[1].op, Operation::Update);
+        assert_eq!(changes[1].old, vec![Some(Integer64(2)), None, Some(Integer64(2))]);
+        assert_eq!(changes[1].new, vec![None, None, Some(Integer64(20))]);
+        assert_eq!(changes[2].old[1], Some(Text("one".to_string())));
+
+        let second = {
+            let mut session = db.session("main").unwrap();
+            session.attach(None).unwrap();
+            assert_eq!(checked_prepare(&db, "UPDATE t SET n = 30 WHERE id = 3").step(), SQLITE_DONE);
+            session.changeset().unwrap()
+        };
+        let both = first.concat(&second).unwrap();
+        let inserted: Vec<Change> = both.iter().unwrap().map(|c| c.unwrap())
+            .filter(|c| c.op == Operation::Insert).collect();
+        assert_eq!(inserted[0].new[2], Some(Integer64(30)));
+
+        let inverted = both.invert().unwrap();
+        let ops: Vec<Operation> = inverted.iter().unwrap().map(|c| c.unwrap().op).collect();
+        assert_eq!(ops.iter().filter(|&&op| op == Operation::Delete).count(), 1);
+        assert_eq!(ops.iter().filter(|&&op| op == Operation::Insert).count(), 1);
+        assert!(Changeset::from_bytes(vec![1, 2, 3]).invert().is_err());
+    }
+
+    #[cfg(feature = "session")]
+    #[test]
+    fn apply_changeset() {
+        use session::*;
+
+        let schema = "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT); \
+                      CREATE TABLE other (id INTEGER PRIMARY KEY);";
+        let source = checked_open();
+        source.exec(schema).unwrap();
+        source.exec("INSERT INTO t VALUES (1, 'one'), (2, 'two');").unwrap();
+        let changeset = {
+            let mut session = source.session("main").unwrap();
+            session.attach(None).unwrap();
+            for sql in &["INSERT INTO t VALUES (3, 'three')", "UPDATE t SET name = 'TWO' WHERE id = 2",
+                         "DELETE FROM t WHERE id = 1", "INSERT INTO other VALUES (1)"] {
+                assert_eq!(checked_prepare(&source, sql).step(), SQLITE_DONE);
+            }
+            session.changeset().unwrap()
+        };
+
+        let mut target = checked_open();
+        target.exec(schema).unwrap();
+        target.exec("INSERT INTO t VALUES (2, 'deux'), (3, 'trois');").unwrap();
+
+        // aborting leaves the database unchanged.
+        let r = target.apply_changeset(&changeset, |_| true, |_, _| ConflictAction::Abort);
+        assert_eq!(r, Err(SQLITE_ABORT));
+
+        let mut conflicts = Vec::new();
+        let mut tables = Vec::new();
+        target.apply_changeset(&changeset, |table| {
+            tables.push(table.to_string());
+            table == "t"
+        }, |kind, item| {
+            let change = item.change().unwrap();
+            conflicts.push((kind, change.op, item.conflicting()));
+            match kind {
+                ConflictType::Data | ConflictType::Conflict => ConflictAction::Replace,
+                _ => ConflictAction::Omit,
+            }
+        }).unwrap();
+        tables.sort();
+        assert_eq!(tables, vec!["other", "t"]);
+        conflicts.sort_by_key(|c| c.1 as u8);
+        assert_eq!(conflicts, vec![
+            (ConflictType::Conflict, Operation::Insert, Some(vec![Integer64(3), Text("trois".to_string())])),
+            (ConflictType::Data, Operation::Update, Some(vec![Integer64(2), Text("deux".to_string())])),
+            (ConflictType::NotFound, Operation::Delete, None),
+        ]);
+
+        let mut c = checked_prepare(&target, "SELECT group_concat(name) FROM (SELECT name FROM t ORDER BY id)");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(0), Some("TWO,three"));
+        let mut c = checked_prepare(&target, "SELECT count(*) FROM other");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 0);
+    }
+
+    #[test]
+    fn serialize_and_open_from_bytes() {
+        let db = checked_open();
+        assert_eq!(db.serialize("main"), Ok(Vec::new()));
+        assert_eq!(db.serialize("nope"), Err(SQLITE_ERROR));
+        db.exec("CREATE TABLE t (x); INSERT INTO t VALUES (1), (2);").unwrap();
+        let bytes = db.serialize("main").unwrap();
+        assert!(bytes.starts_with(b"SQLite format 3\0"));
+
+        let copy = open_from_bytes(&bytes, false).unwrap();
+        copy.exec("INSERT INTO t SELECT x + 2 FROM t; INSERT INTO t SELECT x + 4 FROM t;").unwrap();
+        copy.exec("CREATE TABLE big AS SELECT randomblob(10000) FROM t;").unwrap();
+        {
+            let mut c = checked_prepare(&copy, "SELECT sum(x) FROM t");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_int(0), 36);
+        }
+        assert!(copy.serialize("main").unwrap().len() > bytes.len());
+
+        let read_only = open_from_bytes(&bytes, true).unwrap();
+        assert_eq!(read_only.exec("INSERT INTO t VALUES (3)"), Err(SQLITE_READONLY));
+        let mut c = checked_prepare(&read_only, "SELECT count(*) FROM t");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 2);
+
+        assert!(open_from_bytes(b"not a database", false).and_then(|db| db.exec("SELECT * FROM t")).is_err());
+    }
+
+    #[test]
+    fn load_extension() {
+        use extension::LoadExtensionGuard;
+        use std::process::Command;
+
+        let source = temp_path("extension.c");
+        let library = temp_path("extension.so");
+        fs::write(&source, r#"
+            #include <sqlite3ext.h>
+            SQLITE_EXTENSION_INIT1
+
+            static void half(sqlite3_context *ctx, int argc, sqlite3_value **argv) {
+                sqlite3_result_double(ctx, sqlite3_value_double(argv[0]) / 2);
+            }
+
+            int sqlite3_extension_init(sqlite3 *db, char **err, const sqlite3_api_routines *api) {
+                SQLITE_EXTENSION_INIT2(api);
+                return sqlite3_create_function(db, "half", 1, SQLITE_UTF8, 0, half, 0, 0);
+            }
+
+            int broken_init(sqlite3 *db, char **err, const sqlite3_api_routines *api) {
+                SQLITE_EXTENSION_INIT2(api);
+                *err = sqlite3_mprintf("broken on purpose");
+                return SQLITE_ERROR;
+            }
+        "#).unwrap();
+        let status = Command::new("cc").args(["-shared", "-fPIC", "-o"]).arg(&library).arg(&source).status();
+        assert!(status.as_ref().is_ok_and(|s| s.success()), "cannot compile the test extension: {:?}", status);
+        let library = &library[..];
+        let load_sql = format!("SELECT load_extension('{}')", library);
+
+        let mut db = checked_open();
+        let err = db.load_extension(library, Some("broken_init")).unwrap_err();
+        assert_eq!(err.code, SQLITE_ERROR);
+        assert!(err.message.ends_with("broken on purpose"));
+        assert!(db.load_extension("/nonexistent/extension.so", None).is_err());
+
+        db.load_extension(library, Some("sqlite3_extension_init")).unwrap();
+        {
+            let mut c = checked_prepare(&db, "SELECT half(5)");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_f64(0), 2.5);
+        }
+        // loading is disabled again.
+        assert!(db.exec(&load_sql).is_err());
+
+        // the guard enables the C API only, and restores the previous setting.
+        assert!(extension::enable_load_extension(&db, 0).is_ok());
+        {
+            let _guard = LoadExtensionGuard::new(&db).unwrap();
+            assert_ne!(checked_prepare(&db, &load_sql).step(), SQLITE_ROW);
+            drop(LoadExtensionGuard::new(&db).unwrap());
+            assert_eq!(extension::enable_load_extension(&db, -1), Ok(true));
+        }
+        assert_eq!(extension::enable_load_extension(&db, -1), Ok(false));
+    }
+
+    #[test]
+    fn connection_limits() {
+        use limits::*;
+        use limits::Limit::*;
+
+        let mut db = checked_open();
+        let default = db.get_limit(SQLITE_LIMIT_SQL_LENGTH);
+        assert_eq!(db.set_limit(SQLITE_LIMIT_SQL_LENGTH, 20), default);
+        assert_eq!(db.get_limit(SQLITE_LIMIT_SQL_LENGTH), 20);
+        assert_eq!(db.exec("SELECT 1 + 1 + 1 + 1 + 1 + 1"), Err(SQLITE_TOOBIG));
+        assert!(db.exec("SELECT 1").is_ok());
+        db.set_limit(SQLITE_LIMIT_SQL_LENGTH, i32::MAX);
+        assert_eq!(db.get_limit(SQLITE_LIMIT_SQL_LENGTH), default);
+
+        let profile = SandboxProfile::default().limit(SQLITE_LIMIT_VARIABLE_NUMBER, 2);
+        assert_eq!(profile.get(SQLITE_LIMIT_ATTACHED), Some(0));
+        assert_eq!(profile.get(SQLITE_LIMIT_WORKER_THREADS), None);
+        db.apply_sandbox(&profile);
+        assert_eq!(db.get_limit(SQLITE_LIMIT_VARIABLE_NUMBER), 2);
+        assert_eq!(db.get_limit(SQLITE_LIMIT_EXPR_DEPTH), 10);
+        assert!(db.exec("ATTACH ':memory:' AS other").is_err());
+        assert!(db.exec("SELECT ?3").is_err());
+        assert!(db.exec("SELECT 'abc' LIKE '%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%%'").is_err());
+        assert!(db.exec("SELECT 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1").is_err());
+        assert!(db.exec("SELECT ?2").is_ok());
+    }
+
+    #[test]
+    fn status_counters() {
+        use status::DbStatus::*;
+        use status::StmtStatus::*;
+
+        let db = checked_open();
+        db.exec("CREATE TABLE t (x, y); \
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100) \
+                 INSERT INTO t SELECT i, i % 7 FROM n;").unwrap();
+        let before = db.status_snapshot().unwrap();
+        assert!(before.schema_used > 0);
+        {
+            let mut c = checked_prepare(&db, "SELECT x FROM t ORDER BY y");
+            let start = c.status_snapshot();
+            assert!(start.memory_used > 0);
+            while c.step() == SQLITE_ROW {}
+            let used = c.status_snapshot() - start;
+            assert!(used.fullscan_steps > 0);
+            assert_eq!(used.sorts, 1);
+            assert_eq!(used.runs, 1);
+            assert!(used.vm_steps > 100);
+            assert_eq!(c.status(SQLITE_STMTSTATUS_SORT, true), 1);
+            assert_eq!(c.status(SQLITE_STMTSTATUS_SORT, false), 0);
+
+            let during = db.status_snapshot().unwrap() - before;
+            assert!(during.stmt_used > 0);
+        }
+        assert_eq!((db.status_snapshot().unwrap() - before).stmt_used, 0);
+
+        let stats = db.status(SQLITE_DBSTATUS_CACHE_USED, false).unwrap();
+        assert!(stats.current > 0);
+        assert!(db.status_snapshot().unwrap().cache_hit_ratio().is_some());
+    }
+
+    #[test]
+    fn memory_status() {
+        use memory::Status::*;
+
+        let db = checked_open();
+        assert!(memory::memory_used() > 0);
+        assert!(memory::memory_highwater(false) >= memory::memory_used());
+        let stats = memory::stats().unwrap();
+        assert!(stats.malloc_count > 0);
+        assert!(stats.memory_highwater >= stats.memory_used);
+        assert!(memory::status(SQLITE_STATUS_MALLOC_SIZE, false).unwrap().highwater > 0);
+
+        drop(db);
+    }
+
+    #[test]
+    fn library_version() {
+        let v: Vec<i32> = version().split('.').map(|n| n.parse().unwrap()).collect();
+        assert_eq!(version_number(), v[0] * 1000000 + v[1] * 1000 + v.get(2).cloned().unwrap_or(0));
+        assert!(source_id().len() > 20);
+
+        let options = compile_options();
+        assert!(options.iter().any(|o| o.starts_with("THREADSAFE=")));
+        assert!(compile_option_used("THREADSAFE"));
+        assert!(compile_option_used("SQLITE_THREADSAFE"));
+
+        assert_eq!(require_features(&[]), Ok(()));
+        assert_eq!(require_features(&["THREADSAFE", "ENABLE_JSON1"]), Ok(()));
+        let err = require_features(&["THREADSAFE", "ENABLE_NOTHING", "SQLITE_ENABLE_NOPE"]).unwrap_err();
+        assert_eq!(err.missing, vec!["ENABLE_NOTHING", "SQLITE_ENABLE_NOPE"]);
+        assert_eq!(err.to_string(), format!("SQLite {} was compiled without ENABLE_NOTHING, SQLITE_ENABLE_NOPE",
+                                            version()));
+    }
+
+    #[test]
+    fn pragmas() {
+        use pragma::*;
+        use std::time::Duration;
+
+        let path = temp_path("pragmas.db");
+        let mut db = open(&path).unwrap();
+        assert_eq!(db.journal_mode(), Ok(JournalMode::Delete));
+        assert_eq!(db.set_journal_mode(JournalMode::Wal), Ok(JournalMode::Wal));
+        assert_eq!(db.journal_mode(), Ok(JournalMode::Wal));
+        assert_eq!(checked_open().set_journal_mode(JournalMode::Wal), Ok(JournalMode::Memory));
+
+        db.set_synchronous(Synchronous::Normal).unwrap();
+        assert_eq!(db.synchronous(), Ok(Synchronous::Normal));
+        db.set_foreign_keys(true).unwrap();
+        assert_eq!(db.foreign_keys(), Ok(true));
+        db.set_user_version(42).unwrap();
+        assert_eq!(db.user_version(), Ok(42));
+        db.set_application_id(-1).unwrap();
+        assert_eq!(db.application_id(), Ok(-1));
+        db.set_cache_size(-4000).unwrap();
+        assert_eq!(db.cache_size(), Ok(-4000));
+        assert!(db.set_mmap_size(1 << 20).unwrap() >= 0);
+        db.set_temp_store(TempStore::Memory).unwrap();
+        assert_eq!(db.temp_store(), Ok(TempStore::Memory));
+        assert_eq!(db.page_size(), Ok(4096));
+        assert_eq!(db.set_busy_timeout(250), SQLITE_OK);
+        assert_eq!(db.busy_timeout(), Ok(Duration::from_millis(250)));
+
+        db.exec("ATTACH ':memory:' AS \"odd \"\"name\"\"\"").unwrap();
+        db.pragma_update(Some("odd \"name\""), "user_version", &Integer(7)).unwrap();
+        let rows = db.pragma_query(Some("odd \"name\""), "user_version").unwrap();
+        assert_eq!(rows[0].get("user_version"), Some(&Integer(7)));
+        assert_eq!(db.user_version(), Ok(42));
+        let rows = db.pragma_query(None, "database_list").unwrap();
+        assert_eq!(rows.len(), 2);
+
+        assert_eq!(db.pragma_query(None, "user_version; DROP TABLE x").unwrap_err(), SQLITE_MISUSE);
+        db.exec("CREATE TABLE t (x)").unwrap();
+        db.pragma_update(None, "user_version", &Text("1'; DROP TABLE t; --".to_string())).ok();
+        assert!(db.exec("SELECT * FROM t").is_ok());
+        for f in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(db.pragma_update(None, "analysis_limit", &Float64(*f)).unwrap_err(), SQLITE_MISUSE);
+        }
+        assert!(db.pragma_update(None, "cache_size", &Float64(-500.0)).is_ok());
+    }
+
+    #[test]
+    fn schema_introspection() {
+        use schema::*;
+
+        let mut db = checked_open();
+        db.exec("CREATE TABLE author (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL UNIQUE); \
+                 CREATE TABLE book ( \
+                     isbn TEXT, edition INT DEFAULT 1, author INT REFERENCES author ON DELETE CASCADE, \
+                     title TEXT COLLATE NOCASE, slug TEXT AS (lower(title)), len INT AS (length(title)) STORED, \
+                     PRIMARY KEY (isbn, edition)) WITHOUT ROWID; \
+                 CREATE INDEX book_title ON book (title DESC) WHERE title IS NOT NULL; \
+                 CREATE VIEW titles AS SELECT title FROM book; \
+                 CREATE TRIGGER no_empty BEFORE INSERT ON book BEGIN SELECT 1; END; \
+                 ATTACH ':memory:' AS other; \
+                 CREATE TABLE other.t (x);").unwrap();
+        db.load_generate_series().unwrap();
+
+        let tables = db.tables("main").unwrap();
+        let names: Vec<&str> = tables.iter().map(|t| &*t.name).collect();
+        assert_eq!(names, vec!["author", "book", "titles"]);
+
+        let author = &tables[0];
+        assert_eq!(author.kind, TableKind::Table);
+        assert!(!author.without_rowid);
+        assert_eq!(author.column("id").unwrap().primary_key, 1);
+        assert_eq!(author.indexes.len(), 1);
+        assert_eq!(author.indexes[0].origin, IndexOrigin::Unique);
+        assert_eq!(author.indexes[0].columns[0].name, Some("name".to_string()));
+
+        let book = db.table("main", "BOOK").unwrap().unwrap();
+        assert!(book.without_rowid);
+        assert!(book.sql.as_ref().unwrap().contains("WITHOUT ROWID"));
+        assert_eq!(book.columns.len(), 6);
+        assert_eq!(book.column("edition").unwrap().default, Some("1".to_string()));
+        assert_eq!(book.column("edition").unwrap().primary_key, 2);
+        assert_eq!(book.column("slug").unwrap().generated, Some(Generated::Virtual));
+        assert_eq!(book.column("len").unwrap().generated, Some(Generated::Stored));
+        assert_eq!(book.column("title").unwrap().generated, None);
+        let index = book.indexes.iter().find(|i| i.name == "book_title").unwrap();
+        assert!(index.partial && !index.unique);
+        assert_eq!(index.columns, vec![IndexColumn { name: Some("title".to_string()), descending: true,
+                                                     collation: "NOCASE".to_string() }]);
+        assert!(book.indexes.iter().any(|i| i.origin == IndexOrigin::PrimaryKey));
+        assert_eq!(book.foreign_keys, vec![ForeignKey {
+            table: "author".to_string(), from: vec!["author".to_string()], to: vec![None],
+            on_update: "NO ACTION".to_string(), on_delete: "CASCADE".to_string(),
+        }]);
+
+        assert_eq!(tables[2].kind, TableKind::View);
+        assert_eq!(tables[2].columns[0].name, "title");
+
+        let triggers = db.triggers("main").unwrap();
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].table, "book");
+
+        let other = db.tables("other").unwrap();
+        assert_eq!(other.len(), 1);
+        assert_eq!(other[0].name, "t");
+        assert_eq!(db.table("main", "t").unwrap(), None);
+    }
+
+    #[test]
+    fn migrations() {
+        use migrations::*;
+
+        fn count(db: &Database, sql: &str) -> isize {
+            let mut c = checked_prepare(db, sql);
+            assert_eq!(c.step(), SQLITE_ROW);
+            c.get_int(0)
+        }
+
+        let migrations = || Migrations::new()
+            .up_down("users", "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)", "DROP TABLE users")
+            .up_fn("seed", |db| db.exec("INSERT INTO users (name) VALUES ('root')").map(|_| ()),
+                   Some(Box::new(|db: &mut Database| db.exec("DELETE FROM users").map(|_| ()))))
+            .up("email", "ALTER TABLE users ADD COLUMN email TEXT");
+
+        let mut db = checked_open();
+        assert_eq!(migrations().validate(&mut db).unwrap(), 0);
+        assert_eq!(count(&db, "SELECT count(*) FROM sqlite_schema WHERE name = '_migrations'"), 0);
+        let dry = migrations().dry_run(true).to_latest(&mut db).unwrap();
+        assert_eq!(dry, vec![1, 2, 3]);
+        assert_eq!(db.user_version(), Ok(0));
+        assert_eq!(count(&db, "SELECT count(*) FROM sqlite_schema WHERE name IN ('users', '_migrations')"), 0);
+
+        assert_eq!(migrations().to_version(&mut db, 2).unwrap(), vec![1, 2]);
+        assert_eq!(db.user_version(), Ok(2));
+        assert_eq!(count(&db, "SELECT count(*) FROM users"), 1);
+        assert_eq!(migrations().to_latest(&mut db).unwrap(), vec![3]);
+        assert_eq!(migrations().to_latest(&mut db).unwrap(), Vec::<i32>::new());
+        assert_eq!(count(&db, "SELECT count(*) FROM _migrations"), 3);
+
+        match migrations().to_version(&mut db, 1) {
+            Err(MigrationError::Irreversible { version: 3 }) => (),
+            r => panic!("unexpected {:?}", r),
+        }
+        db.exec("ALTER TABLE users DROP COLUMN email; PRAGMA user_version = 2;").unwrap();
+        assert_eq!(migrations().to_version(&mut db, 0).unwrap(), vec![2, 1]);
+        assert_eq!(count(&db, "SELECT count(*) FROM sqlite_schema WHERE name = 'users'"), 0);
+        assert_eq!(count(&db, "SELECT count(*) FROM _migrations"), 1);
+        db.exec("DELETE FROM _migrations").unwrap();
+
+        migrations().to_latest(&mut db).unwrap();
+        let changed = Migrations::new()
+            .up_down("users", "CREATE TABLE users (id INTEGER PRIMARY KEY)", "DROP TABLE users")
+            .up("seed", "").up("email", "");
+        match changed.to_latest(&mut db) {
+            Err(MigrationError::Changed { version: 1 }) => (),
+            r => panic!("unexpected {:?}", r),
+        }
+        match Migrations::new().up("one", "").to_latest(&mut db) {
+            Err(MigrationError::TooNew { version: 3, latest: 1 }) => (),
+            r => panic!("unexpected {:?}", r),
+        }
+        db.exec("INSERT INTO _migrations VALUES (0, 'zero', '')").unwrap();
+        match migrations().to_latest(&mut db) {
+            Err(MigrationError::Unknown { version: 0 }) => (),
+            r => panic!("unexpected {:?}", r),
+        }
+        db.exec("DELETE FROM _migrations WHERE version = 0").unwrap();
+
+        // a failing migration is rolled back.
+        let failing = migrations().up("broken", "CREATE TABLE t (x); INSERT INTO nope VALUES (1)");
+        match failing.to_latest(&mut db) {
+            Err(MigrationError::Failed { version: 4, code: SQLITE_ERROR, ref message }) =>
+                assert_eq!(message, "no such table: nope"),
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(db.user_version(), Ok(3));
+        assert_eq!(count(&db, "SELECT count(*) FROM sqlite_schema WHERE name = 't'"), 0);
+    }
+
+    #[test]
+    fn migrations_from_directory() {
+        use migrations::*;
+
+        let dir = temp_path("migrations");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir(&dir).unwrap();
+        fs::write(format!("{}/0001_create.up.sql", dir), "CREATE TABLE t (x);").unwrap();
+        fs::write(format!("{}/0001_create.down.sql", dir), "DROP TABLE t;").unwrap();
+        fs::write(format!("{}/2_fill.sql", dir), "INSERT INTO t VALUES (1), (2);").unwrap();
+        fs::write(format!("{}/README", dir), "not a migration").unwrap();
+
+        let migrations = Migrations::from_directory(&dir).unwrap();
+        assert_eq!(migrations.latest(), 2);
+        let mut db = checked_open();
+        assert_eq!(migrations.to_latest(&mut db).unwrap(), vec![1, 2]);
+        let mut c = checked_prepare(&db, "SELECT sum(x) FROM t");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 3);
+
+        fs::write(format!("{}/4_gap.sql", dir), "").unwrap();
+        match Migrations::from_directory(&dir) {
+            Err(MigrationError::InvalidFile(path)) => assert!(path.ends_with("4_gap.sql")),
+            r => panic!("unexpected {:?}", r.map(|m| m.latest())),
+        }
+        fs::remove_file(format!("{}/4_gap.sql", dir)).unwrap();
+        fs::write(format!("{}/oops.sql", dir), "").unwrap();
+        assert!(Migrations::from_directory(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_rows() {
+        use de::*;
+        use std::collections::BTreeMap;
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        enum Role { Admin, User }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Prefs { theme: String, sizes: Vec<i32> }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct User {
+            id: i64,
+            name: String,
+            email: Option<String>,
+            active: bool,
+            score: f64,
+            role: Role,
+            #[serde(deserialize_with = "::de::json")]
+            prefs: Prefs,
+            #[serde(default, deserialize_with = "::de::json_option")]
+            extra: Option<BTreeMap<String, i32>>,
+        }
+
+        let db = checked_open();
+        db.exec("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT, active INT, score REAL, \
+                                     role TEXT, prefs TEXT, extra TEXT); \
+                 INSERT INTO users VALUES (1, 'ann', 'ann@example.com', 1, 4.5, 'Admin', \
+                                           '{\"theme\": \"dark\", \"sizes\": [1, 2]}', '{\"a\": 1}'), \
+                                          (2, 'bob', NULL, 0, 3, 'User', '{\"theme\": \"light\", \"sizes\": []}', NULL);").unwrap();
+
+        let mut c = checked_prepare(&db, "SELECT * FROM users ORDER BY id");
+        let users: Vec<User> = c.deserialize_rows().collect::<Result<_, _>>().unwrap();
+        assert_eq!(users[0], User {
+            id: 1, name: "ann".to_string(), email: Some("ann@example.com".to_string()), active: true, score: 4.5,
+            role: Role::Admin, prefs: Prefs { theme: "dark".to_string(), sizes: vec![1, 2] },
+            extra: Some(vec![("a".to_string(), 1)].into_iter().collect()),
+        });
+        assert_eq!(users[1].email, None);
+        assert_eq!(users[1].score, 3.0);
+        assert_eq!(users[1].extra, None);
+
+        let mut c = checked_prepare(&db, "SELECT id, name FROM users ORDER BY id");
+        let pairs: Vec<(i64, String)> = c.deserialize_rows().map(|r| r.unwrap()).collect();
+        assert_eq!(pairs, vec![(1, "ann".to_string()), (2, "bob".to_string())]);
+        let mut c = checked_prepare(&db, "SELECT count(*) FROM users");
+        assert_eq!(c.deserialize_rows::<u32>().next(), Some(Ok(2)));
+
+        // borrowing from a row.
+        let mut c = checked_prepare(&db, "SELECT name FROM users WHERE id = 1");
+        let row = c.next_row().unwrap().unwrap();
+        assert_eq!(row.get("name"), Some(&Text("ann".to_string())));
+        let (name,): (&str,) = row.deserialize().unwrap();
+        assert_eq!(name, "ann");
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Small { id: i64, missing: i32 }
+        let mut c = checked_prepare(&db, "SELECT id FROM users");
+        assert_eq!(c.deserialize_rows::<Small>().next().unwrap().unwrap_err(),
+                   DeserializeError::MissingColumn("missing".to_string()));
+        let mut c = checked_prepare(&db, "SELECT id, name, email, 1 AS missing FROM users");
+        assert_eq!(c.deserialize_rows::<Small>().next().unwrap().unwrap_err(),
+                   DeserializeError::ExtraColumns(vec!["name".to_string(), "email".to_string()]));
+        let mut c = checked_prepare(&db, "SELECT id, 'x' AS missing FROM users");
+        match c.deserialize_rows::<Small>().next().unwrap().unwrap_err() {
+            DeserializeError::Column { ref column, .. } => assert_eq!(column, "missing"),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn bind_serialized() {
+        use ser::*;
+        use std::collections::HashMap;
+
+        #[derive(Serialize)]
+        #[allow(dead_code)]
+        enum Role { Admin, Guest(String) }
+
+        #[derive(Serialize)]
+        struct Address { city: String }
+
+        #[derive(Serialize)]
+        struct User {
+            id: i64,
+            name: &'static str,
+            email: Option<String>,
+            active: bool,
+            role: Role,
+            address: Address,
+            tags: Vec<&'static str>,
+            unused: u8,
+        }
+
+        let db = checked_open();
+        db.exec("CREATE TABLE users (id, name, email, active, role, address, tags)").unwrap();
+        let user = User {
+            id: 7, name: "ann", email: None, active: true, role: Role::Admin,
+            address: Address { city: "Paris".to_string() }, tags: vec!["a", "b"], unused: 0,
+        };
+        {
+            let mut c = checked_prepare(&db, "INSERT INTO users VALUES (:id, :name, @email, $active, :role, :address, :tags)");
+            c.bind_serialized(&user).unwrap();
+            assert_eq!(c.step(), SQLITE_DONE);
+        }
+        {
+            let mut c = checked_prepare(&db, "SELECT id, name, typeof(email), active, role, \
+                                              json_extract(address, '$.city'), json_array_length(tags) FROM users");
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_int(0), 7);
+            assert_eq!(c.get_text(1), Some("ann"));
+            assert_eq!(c.get_text(2), Some("null"));
+            assert_eq!(c.get_int(3), 1);
+            assert_eq!(c.get_text(4), Some("Admin"));
+            assert_eq!(c.get_text(5), Some("Paris"));
+            assert_eq!(c.get_int(6), 2);
+        }
+
+        let mut params = HashMap::new();
+        params.insert("name", Role::Guest("x".to_string()));
+        let mut c = checked_prepare(&db, "SELECT json_extract(:name, '$.Guest')");
+        c.bind_serialized(&params).unwrap();
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(0), Some("x"));
+
+        let mut c = checked_prepare(&db, "SELECT :id, :missing, ?, :other");
+        assert_eq!(c.bind_serialized(&user),
+                   Err(SerializeError::UnboundParameters(vec![":missing".to_string(), "?3".to_string(),
+                                                              ":other".to_string()])));
+        assert!(c.bind_serialized(&42).is_err());
+        assert!(to_bind_arg(&u64::MAX).is_err());
+    }
+
+    #[test]
+    fn datetime_conversions() {
+        use datetime::*;
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let t = UNIX_EPOCH + Duration::new(1709210096, 500_000_000);
+        let db = checked_open();
+        let mut c = checked_prepare(&db, "SELECT '2024-02-29 12:34:56.5', unixepoch('2024-02-29 12:34:56'), \
+                                          julianday('2024-02-29 12:34:56.5'), '2024-02-29T14:34:56.5+02:00', \
+                                          '2024-02-29', '2024-02-30', x'00'");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get::<SystemTime>(0), Ok(t));
+        assert_eq!(c.get::<SystemTime>(1), Ok(t - Duration::from_millis(500)));
+        assert_eq!(c.get::<SystemTime>(2), Ok(t));
+        assert_eq!(c.get::<SystemTime>(3), Ok(t));
+        assert_eq!(c.get::<SystemTime>(4), Ok(UNIX_EPOCH + Duration::from_secs(1709164800)));
+        assert!(c.get::<SystemTime>(5).is_err());
+        assert_eq!(c.get::<SystemTime>(6),
+                   Err(FromSqlError::InvalidType { expected: "TEXT, INTEGER or REAL", found: ColumnType::SQLITE_BLOB }));
+
+        let mut c = checked_prepare(&db, "SELECT ?1, datetime(?1), typeof(?2), ?2, typeof(?3), julianday(?1) - ?3");
+        assert_eq!(c.bind_params(&[t.to_sql(), UnixTime(t).to_sql(), JulianDay(t).to_sql()]), SQLITE_OK);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(0), Some("2024-02-29 12:34:56.500000000"));
+        assert_eq!(c.get_text(1), Some("2024-02-29 12:34:56"));
+        assert_eq!(c.get_text(2), Some("integer"));
+        assert_eq!(c.get_i64(3), 1709210096);
+        assert_eq!(c.get_text(4), Some("real"));
+        assert!(c.get_f64(5).abs() < 1e-8);
+        assert_eq!(c.get::<JulianDay<SystemTime>>(5).map(|d| d.0), Ok(UNIX_EPOCH - Duration::from_secs(210866760000)));
+
+        let before_epoch = Timestamp::parse("1969-12-31T23:59:59.25Z").unwrap();
+        let t = SystemTime::from_timestamp(before_epoch).unwrap();
+        assert_eq!(t, UNIX_EPOCH - Duration::from_millis(750));
+        assert_eq!(t.to_iso8601(), "1969-12-31 23:59:59.250000000");
+        assert_eq!(Timestamp::parse("2024-02-29 24:00"), None);
+        assert_eq!(Timestamp::parse("2024-02-29T10:00 -0130").map(|t| t.unix_time()), Some(1709206200));
+
+        let year_10000 = UNIX_EPOCH + Duration::from_secs(253402300800);
+        assert_eq!(year_10000.to_sql(), BindArg::Integer64(253402300800));
+        assert_eq!((year_10000 - Duration::from_secs(1)).to_sql(),
+                   BindArg::Text("9999-12-31 23:59:59.000000000".to_string()));
+    }
+
+    #[test]
+    fn datetime_ordering() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let mut db = checked_open();
+        checked_exec(&mut db, "CREATE TABLE t (at TEXT)");
+        let second = UNIX_EPOCH + Duration::from_secs(1709210096);
+        let mut times = vec![second + Duration::from_millis(500), second, second + Duration::new(1, 0),
+                             second + Duration::from_nanos(1), UNIX_EPOCH - Duration::from_millis(250), UNIX_EPOCH];
+        for t in &times {
+            let mut c = checked_prepare(&db, "INSERT INTO t VALUES (?)");
+            assert_eq!(c.bind_params(&[t.to_sql()]), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_DONE);
+        }
+        let mut c = checked_prepare(&db, "SELECT at FROM t ORDER BY at");
+        let mut stored = Vec::new();
+        while c.step() == SQLITE_ROW {
+            stored.push(c.get::<SystemTime>(0).unwrap());
+        }
+        times.sort();
+        assert_eq!(stored, times);
+
+        let mut c = checked_prepare(&db, "SELECT count(*) FROM t WHERE at >= datetime(?1) AND at < datetime(?1, '+1 second')");
+        assert_eq!(c.bind_params(&[second.to_sql()]), SQLITE_OK);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 3);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn datetime_chrono() {
+        use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+        use datetime::*;
+
+        let db = checked_open();
+        let t = DateTime::from_timestamp(1709210096, 123_456_000).unwrap();
+        let mut c = checked_prepare(&db, "SELECT ?1, ?2, ?3, ?4, '2024-02-29T12:34:56+05:00'");
+        assert_eq!(c.bind_params(&[t.to_sql(), t.naive_utc().to_sql(), t.date_naive().to_sql(),
+                                   UnixTime(t.date_naive()).to_sql()]), SQLITE_OK);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(0), Some("2024-02-29 12:34:56.123456000"));
+        assert_eq!(c.get_text(1), Some("2024-02-29 12:34:56.123456000"));
+        assert_eq!(c.get_text(2), Some("2024-02-29"));
+        assert_eq!(c.get_i64(3), 1709164800);
+        assert_eq!(c.get::<DateTime<Utc>>(0), Ok(t));
+        assert_eq!(c.get::<NaiveDateTime>(1), Ok(t.naive_utc()));
+        assert_eq!(c.get::<NaiveDate>(2), Ok(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()));
+        assert_eq!(c.get::<NaiveDate>(3), Ok(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()));
+        assert_eq!(c.get::<DateTime<Utc>>(4).map(|t| t.timestamp()), Ok(1709192096));
+        assert_eq!(c.get::<NaiveDateTime>(4).map(|t| t.and_utc().timestamp()), Ok(1709210096));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn datetime_time() {
+        use datetime::*;
+        use time::{OffsetDateTime, UtcOffset};
+
+        let db = checked_open();
+        let t = OffsetDateTime::from_unix_timestamp(1709210096).unwrap()
+            .to_offset(UtcOffset::from_hms(-3, -30, 0).unwrap());
+        let mut c = checked_prepare(&db, "SELECT ?1, substr(?1, 1, 19) = datetime(?1), ?2, unixepoch(?2), ?3, \
+                                          '2024-02-29 12:34:56', ?4, unixepoch(?4)");
+        let seconds_offset = t.to_offset(UtcOffset::from_hms(1, 0, 30).unwrap());
+        assert_eq!(c.bind_params(&[t.to_sql(), WithOffset(t).to_sql(), JulianDay(t).to_sql(),
+                                   WithOffset(seconds_offset).to_sql()]), SQLITE_OK);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(0), Some("2024-02-29 12:34:56.000000000"));
+        assert_eq!(c.get_i64(1), 1);
+        assert_eq!(c.get::<OffsetDateTime>(0), Ok(t));
+        assert_eq!(c.get::<OffsetDateTime>(0).map(|t| t.offset()), Ok(UtcOffset::UTC));
+        assert_eq!(c.get_text(2), Some("2024-02-29 09:04:56.000000000-03:30"));
+        assert_eq!(c.get_i64(3), 1709210096);
+        assert_eq!(c.get::<WithOffset>(2).map(|t| t.0.offset()), Ok(t.offset()));
+        assert_eq!(c.get::<OffsetDateTime>(4).map(|t| t.unix_timestamp()), Ok(1709210096));
+        assert_eq!(c.get::<OffsetDateTime>(5), Ok(t));
+        assert_eq!(c.get_text(6), Some("2024-02-29 13:34:56.000000000+01:00"));
+        assert_eq!(c.get_i64(7), 1709210096);
+
+        let before_year_0 = OffsetDateTime::from_unix_timestamp(-62167219201).unwrap();
+        assert_eq!(before_year_0.to_sql(), BindArg::Integer64(-62167219201));
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_values() {
+        use json::*;
+        use serde_json::Value;
+
+        let value: Value = serde_json::from_str(r#"{"a":[1,true,null],"b c":"x\"y","d":1.5}"#).unwrap();
+        let db = checked_open();
+        db.exec("CREATE TABLE docs (id INTEGER PRIMARY KEY, doc); \
+                 CREATE TABLE n (x); INSERT INTO n VALUES (1), (2), (3), (4), (5)").unwrap();
+        {
+            let mut c = checked_prepare(&db, "INSERT INTO docs (doc) VALUES (?), (?)");
+            assert_eq!(c.bind_params(&[value.to_sql(), Jsonb(&value).to_sql()]), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_DONE);
+        }
+        let mut c = checked_prepare(&db, "SELECT doc, typeof(doc) FROM docs ORDER BY id");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get::<Value>(0), Ok(value.clone()));
+        assert_eq!(c.get_text(1), Some("text"));
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get::<Value>(0), Ok(value.clone()));
+        assert_eq!(c.get_text(1), Some("blob"));
+
+        let path = JsonPath::root().key("b c");
+        assert_eq!(path.as_str(), "$.\"b c\"");
+        let sql = format!("SELECT {}, {} FROM docs WHERE id = 1",
+                          extract("doc", &path), extract("doc", &JsonPath::root().key("a").from_end(3)));
+        let mut c = checked_prepare(&db, &sql);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_text(0), Some("x\"y"));
+        assert_eq!(c.get_i64(1), 1);
+        assert_eq!(JsonPath::root().key("it's").index(2).to_sql_literal(), "'$.\"it''s\"[2]'");
+        assert_eq!(JsonPath::root().try_key("a\"b"), None);
+
+        let mut c = checked_prepare(&db, &format!("SELECT count(*) FROM n WHERE {}", in_array("x", "?")));
+        c.bind_json_array(1, &[1, 3, 5, 7]).unwrap();
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_int(0), 3);
+        let mut c = checked_prepare(&db, &format!("SELECT value FROM {}", each("?", Some(&JsonPath::root().key("a")))));
+        assert_eq!(c.bind_param(1, &Json(&value).to_sql()), SQLITE_OK);
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get_i64(0), 1);
+        let mut c = checked_prepare(&db, "SELECT json_array(1, 2)");
+        assert_eq!(c.step(), SQLITE_ROW);
+        assert_eq!(c.get::<Json<Vec<i32>>>(0), Ok(Json(vec![1, 2])));
+
+        let unserializable = [std::collections::BTreeMap::from([(vec![1], 2)])];
+        assert!(Json::try_new(&unserializable).is_err());
+        assert!(Jsonb::try_new(&unserializable).is_err());
+        let mut c = checked_prepare(&db, "SELECT ?");
+        assert_eq!(c.bind_json_array(1, &unserializable), Err(SQLITE_MISMATCH));
+        assert_eq!(c.bind_param(1, &Jsonb::try_new(&[1, 2]).unwrap().to_sql()), SQLITE_OK);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn jsonb_codec() {
+        use json::*;
+        use serde_json::Value;
+
+        let value: Value = serde_json::from_str(r#"{"a":[1,true,null]}"#).unwrap();
+        let bytes = to_jsonb(&value);
+        assert_eq!(bytes, vec![0x7c, 0x17, b'a', 0x4b, 0x13, b'1', 0x01, 0x00]);
+        assert_eq!(from_jsonb(&bytes), Ok(value));
+
+        let long = Value::from("twenty characters...");
+        assert_eq!(&to_jsonb(&long)[..2], &[0xc7, 20]);
+        assert_eq!(from_jsonb(&to_jsonb(&long)), Ok(long));
+        assert_eq!(from_jsonb(b"\xa8a\\nb\\u00e9"), Ok(Value::from("a\nb\u{e9}")));
+        assert_eq!(from_jsonb(b"\x54-0x1F"), Ok(Value::from(-31)));
+        assert_eq!(from_jsonb(b"\x26.5"), Ok(Value::from(0.5)));
+        assert!(from_jsonb(b"\x13").is_err());
+        assert!(from_jsonb(b"\x131\x00").is_err());
+
+        // produced by jsonb() of SQLite 3.51.2.
+        let value: Value = serde_json::from_str(r#"{"a":[1,true,null],"d":1.5,"e":"text"}"#).unwrap();
+        let sqlite = b"\xcc\x14\x17a\x4b\x131\x01\x00\x17d\x351.5\x17e\x47text";
+        assert_eq!(to_jsonb(&value), &sqlite[..]);
+        assert_eq!(from_jsonb(sqlite), Ok(value));
+        assert_eq!(from_jsonb(b"\x68a\\nb\xc3\xa9"), Ok(Value::from("a\nb\u{e9}")));
+        let json5: Value = serde_json::from_str(r#"{"k":-31,"f":0.5,"g":5.0,"s":"x"}"#).unwrap();
+        assert_eq!(from_jsonb(b"\xcc\x16\x17k\x54-0x1F\x17f\x26.5\x17g\x265.\x17s\x17x"), Ok(json5));
+        let mixed: Value = serde_json::from_str(r#"[-12,1e3,"a\"b",{}]"#).unwrap();
+        assert_eq!(from_jsonb(b"\xcb\x0e\x33-12\x351e3\x48a\\\"b\x0c"), Ok(mixed));
+        assert_eq!(&to_jsonb(&Value::from("x".repeat(300)))[..3], &[0xd7, 0x01, 0x2c]);
+        // and which it reads back as "a\"b".
+        assert_eq!(to_jsonb(&Value::from("a\"b")), b"\x3aa\"b");
+
+        let mut nested = Value::Null;
+        for _ in 0..1000 {
+            nested = Value::Array(vec![nested]);
+        }
+        assert!(from_jsonb(&to_jsonb(&nested)).is_ok());
+        let nested = Value::Array(vec![nested]);
+        assert_eq!(from_jsonb(&to_jsonb(&nested)), Err(FromSqlError::Other("malformed JSONB".to_string())));
+
+        // SQLite 3.45 and later produce the same encoding.
+        if version_number() >= 3045000 {
+            let value: Value = serde_json::from_str(r#"{"a":[1,true,null],"d":1.5,"e":"text"}"#).unwrap();
+            let db = checked_open();
+            let mut c = checked_prepare(&db, "SELECT jsonb(?1), json(?2)");
+            assert_eq!(c.bind_params(&[value.to_sql(), Blob(to_jsonb(&value))]), SQLITE_OK);
+            assert_eq!(c.step(), SQLITE_ROW);
+            assert_eq!(c.get_blob(0), Some(&to_jsonb(&value)[..]));
+            assert_eq!(c.get::<Value>(1), Ok(value));
+        }
+    }
+
     #[test]
     fn sendable_db() {
         let db = checked_open();
-        thread::scoped(move || {
+        thread::spawn(move || {
             let mut c = checked_prepare(&db, "select 1 + 1");
             c.step();
             assert_eq!(c.get_int(0), 2);
-        }).join();
+        }).join().unwrap();
     }
 }
 
@@ -0,0 +1,239 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Status counters of connections and statements.
+//!
+//! Besides reading single counters, `Database::status_snapshot` and
+//! `Cursor::status_snapshot` read them all at once. Snapshots can be
+//! subtracted from each other to measure the activity in between.
+
+use cursor::Cursor;
+use ffi::*;
+use libc::c_int;
+use std::ops::Sub;
+use database::Database;
+use types::*;
+use types::ResultCode::*;
+use self::DbStatus::*;
+use self::StmtStatus::*;
+
+/// See http://www.sqlite.org/c3ref/c_dbstatus_options.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub enum DbStatus {
+    SQLITE_DBSTATUS_LOOKASIDE_USED      =  0,
+    SQLITE_DBSTATUS_CACHE_USED          =  1,
+    SQLITE_DBSTATUS_SCHEMA_USED         =  2,
+    SQLITE_DBSTATUS_STMT_USED           =  3,
+    SQLITE_DBSTATUS_LOOKASIDE_HIT       =  4,
+    SQLITE_DBSTATUS_LOOKASIDE_MISS_SIZE =  5,
+    SQLITE_DBSTATUS_LOOKASIDE_MISS_FULL =  6,
+    SQLITE_DBSTATUS_CACHE_HIT           =  7,
+    SQLITE_DBSTATUS_CACHE_MISS          =  8,
+    SQLITE_DBSTATUS_CACHE_WRITE         =  9,
+    SQLITE_DBSTATUS_DEFERRED_FKS        = 10,
+    SQLITE_DBSTATUS_CACHE_USED_SHARED   = 11,
+    SQLITE_DBSTATUS_CACHE_SPILL         = 12,
+}
+
+/// See http://www.sqlite.org/c3ref/c_stmtstatus_counter.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub enum StmtStatus {
+    SQLITE_STMTSTATUS_FULLSCAN_STEP =  1,
+    SQLITE_STMTSTATUS_SORT          =  2,
+    SQLITE_STMTSTATUS_AUTOINDEX     =  3,
+    SQLITE_STMTSTATUS_VM_STEP       =  4,
+    SQLITE_STMTSTATUS_REPREPARE     =  5,
+    SQLITE_STMTSTATUS_RUN           =  6,
+    SQLITE_STMTSTATUS_FILTER_MISS   =  7,
+    SQLITE_STMTSTATUS_FILTER_HIT    =  8,
+    SQLITE_STMTSTATUS_MEMUSED       = 99,
+}
+
+/// The current and highest values of a connection counter. Which of them
+/// is meaningful depends on the counter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct StatusValue {
+    pub current: i64,
+    pub highwater: i64,
+}
+
+/// All the counters of a connection at some point.
+///
+/// Memory is in bytes; the cache counters are in pages and sum up all the
+/// attached databases.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DbStatusSnapshot {
+    pub lookaside_used: i64,
+    pub lookaside_hit: i64,
+    pub lookaside_miss_size: i64,
+    pub lookaside_miss_full: i64,
+    pub cache_used: i64,
+    pub cache_used_shared: i64,
+    pub cache_hit: i64,
+    pub cache_miss: i64,
+    pub cache_write: i64,
+    pub cache_spill: i64,
+    pub schema_used: i64,
+    pub stmt_used: i64,
+    pub deferred_fks: i64,
+}
+
+impl DbStatusSnapshot {
+    /// The share of page lookups served by the cache, if there were any.
+    pub fn cache_hit_ratio(&self) -> Option<f64> {
+        let lookups = self.cache_hit + self.cache_miss;
+        if lookups == 0 { None } else { Some(self.cache_hit as f64 / lookups as f64) }
+    }
+}
+
+impl Sub for DbStatusSnapshot {
+    type Output = DbStatusSnapshot;
+
+    fn sub(self, earlier: DbStatusSnapshot) -> DbStatusSnapshot {
+        DbStatusSnapshot {
+            lookaside_used: self.lookaside_used - earlier.lookaside_used,
+            lookaside_hit: self.lookaside_hit - earlier.lookaside_hit,
+            lookaside_miss_size: self.lookaside_miss_size - earlier.lookaside_miss_size,
+            lookaside_miss_full: self.lookaside_miss_full - earlier.lookaside_miss_full,
+            cache_used: self.cache_used - earlier.cache_used,
+            cache_used_shared: self.cache_used_shared - earlier.cache_used_shared,
+            cache_hit: self.cache_hit - earlier.cache_hit,
+            cache_miss: self.cache_miss - earlier.cache_miss,
+            cache_write: self.cache_write - earlier.cache_write,
+            cache_spill: self.cache_spill - earlier.cache_spill,
+            schema_used: self.schema_used - earlier.schema_used,
+            stmt_used: self.stmt_used - earlier.stmt_used,
+            deferred_fks: self.deferred_fks - earlier.deferred_fks,
+        }
+    }
+}
+
+/// All the counters of a statement at some point.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct StmtStatusSnapshot {
+    pub fullscan_steps: i64,
+    pub sorts: i64,
+    pub autoindexes: i64,
+    pub vm_steps: i64,
+    pub reprepares: i64,
+    pub runs: i64,
+    pub filter_misses: i64,
+    pub filter_hits: i64,
+    pub memory_used: i64,
+}
+
+impl Sub for StmtStatusSnapshot {
+    type Output = StmtStatusSnapshot;
+
+    fn sub(self, earlier: StmtStatusSnapshot) -> StmtStatusSnapshot {
+        StmtStatusSnapshot {
+            fullscan_steps: self.fullscan_steps - earlier.fullscan_steps,
+            sorts: self.sorts - earlier.sorts,
+            autoindexes: self.autoindexes - earlier.autoindexes,
+            vm_steps: self.vm_steps - earlier.vm_steps,
+            reprepares: self.reprepares - earlier.reprepares,
+            runs: self.runs - earlier.runs,
+            filter_misses: self.filter_misses - earlier.filter_misses,
+            filter_hits: self.filter_hits - earlier.filter_hits,
+            memory_used: self.memory_used - earlier.memory_used,
+        }
+    }
+}
+
+impl Database {
+
+    /// Reads a counter of the connection, resetting its highest value (or
+    /// its current one, for the cache counters) when `reset` is set.
+    /// See http://www.sqlite.org/c3ref/db_status.html
+    pub fn status(&self, op: DbStatus, reset: bool) -> SqliteResult<StatusValue> {
+        let mut current = 0;
+        let mut highwater = 0;
+        let r = unsafe { sqlite3_db_status(self.dbh, op as c_int, &mut current, &mut highwater, reset as c_int) };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        Ok(StatusValue { current: current as i64, highwater: highwater as i64 })
+    }
+
+    /// Reads all the counters of the connection.
+    ///
+    /// Counters that the linked SQLite is too old to know about
+    /// (`cache_used_shared` needs 3.14, `cache_spill` 3.23) read as 0.
+    pub fn status_snapshot(&self) -> SqliteResult<DbStatusSnapshot> {
+        let current = |op| self.status(op, false).map(|v| v.current);
+        let highwater = |op| self.status(op, false).map(|v| v.highwater);
+        let newer = |op| match current(op) {
+            Err(SQLITE_ERROR) => Ok(0),
+            r => r,
+        };
+        Ok(DbStatusSnapshot {
+            lookaside_used: current(SQLITE_DBSTATUS_LOOKASIDE_USED)?,
+            lookaside_hit: highwater(SQLITE_DBSTATUS_LOOKASIDE_HIT)?,
+            lookaside_miss_size: highwater(SQLITE_DBSTATUS_LOOKASIDE_MISS_SIZE)?,
+            lookaside_miss_full: highwater(SQLITE_DBSTATUS_LOOKASIDE_MISS_FULL)?,
+            cache_used: current(SQLITE_DBSTATUS_CACHE_USED)?,
+            cache_used_shared: newer(SQLITE_DBSTATUS_CACHE_USED_SHARED)?,
+            cache_hit: current(SQLITE_DBSTATUS_CACHE_HIT)?,
+            cache_miss: current(SQLITE_DBSTATUS_CACHE_MISS)?,
+            cache_write: current(SQLITE_DBSTATUS_CACHE_WRITE)?,
+            cache_spill: newer(SQLITE_DBSTATUS_CACHE_SPILL)?,
+            schema_used: current(SQLITE_DBSTATUS_SCHEMA_USED)?,
+            stmt_used: current(SQLITE_DBSTATUS_STMT_USED)?,
+            deferred_fks: current(SQLITE_DBSTATUS_DEFERRED_FKS)?,
+        })
+    }
+}
+
+impl<'db> Cursor<'db> {
+
+    /// Reads a counter of the statement, resetting it when `reset` is set.
+    /// See http://www.sqlite.org/c3ref/stmt_status.html
+    pub fn status(&self, op: StmtStatus, reset: bool) -> i64 {
+        unsafe { sqlite3_stmt_status(self.stmt(), op as c_int, reset as c_int) as i64 }
+    }
+
+    /// Reads all the counters of the statement.
+    pub fn status_snapshot(&self) -> StmtStatusSnapshot {
+        StmtStatusSnapshot {
+            fullscan_steps: self.status(SQLITE_STMTSTATUS_FULLSCAN_STEP, false),
+            sorts: self.status(SQLITE_STMTSTATUS_SORT, false),
+            autoindexes: self.status(SQLITE_STMTSTATUS_AUTOINDEX, false),
+            vm_steps: self.status(SQLITE_STMTSTATUS_VM_STEP, false),
+            reprepares: self.status(SQLITE_STMTSTATUS_REPREPARE, false),
+            runs: self.status(SQLITE_STMTSTATUS_RUN, false),
+            filter_misses: self.status(SQLITE_STMTSTATUS_FILTER_MISS, false),
+            filter_hits: self.status(SQLITE_STMTSTATUS_FILTER_HIT, false),
+            memory_used: self.status(SQLITE_STMTSTATUS_MEMUSED, false),
+        }
+    }
+}
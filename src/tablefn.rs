@@ -0,0 +1,339 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Table-valued functions backed by Rust closures, built on eponymous
+//! virtual tables.
+//!
+//! Besides `Database::create_table_function`, this provides two built-in
+//! functions: `generate_series(start, stop, step)` and `rarray(?)`, which
+//! turns an array bound with `Cursor::bind_array` into a table, as in
+//! `SELECT * FROM t WHERE id IN rarray(?)`.
+//! See http://www.sqlite.org/vtab.html#table_valued_functions
+
+use cursor::Cursor;
+use ffi::*;
+use libc::{c_int, c_void, c_char};
+use std::rc::Rc;
+use std::sync::Arc;
+use database::Database;
+use types::*;
+use types::BindArg::*;
+use types::ResultCode::*;
+use vtab::*;
+
+type Rows = Box<dyn Iterator<Item = Vec<BindArg>>>;
+type RowsFn = dyn Fn(&[BindArg]) -> VTabResult<Rows> + Send + Sync;
+
+pub struct FunctionSpec {
+    columns: usize,
+    args: usize,
+    f: Box<RowsFn>,
+}
+
+/// Uses the usable `=` constraints on the `args` hidden columns following
+/// the `columns` regular ones. `idx_num` is the mask of arguments given.
+fn best_args_index(info: &mut IndexInfo, columns: usize, args: usize) -> VTabResult<()> {
+    let mut mask = 0;
+    let mut by_arg = vec![None; args];
+    for (i, c) in info.constraints().iter().enumerate() {
+        let arg = c.column as usize;
+        if c.column < 0 || arg < columns || arg >= columns + args || c.op != ConstraintOp::Eq {
+            continue;
+        }
+        if !c.usable {
+            // this plan does not work out, have SQLite try another one.
+            return Err(VTabError::from(SQLITE_CONSTRAINT));
+        }
+        by_arg[arg - columns] = Some(i);
+    }
+    let mut argv_index = 0;
+    for (arg, constraint) in by_arg.into_iter().enumerate() {
+        if let Some(i) = constraint {
+            argv_index += 1;
+            mask |= 1 << arg;
+            info.set_argv_index(i, argv_index);
+            info.set_omit(i, true);
+        }
+    }
+    info.set_idx_num(mask);
+    info.set_estimated_cost(1000.0 / (1 + argv_index) as f64);
+    Ok(())
+}
+
+/// Spreads the constraint values of `filter` back over all arguments.
+fn args_of(idx_num: i32, args: usize, values: &[Value]) -> Vec<BindArg> {
+    let mut values = values.iter();
+    (0..args).map(|arg| {
+        if idx_num & (1 << arg) != 0 {
+            values.next().map(|v| v.to_bind_arg()).unwrap_or(Null)
+        } else {
+            Null
+        }
+    }).collect()
+}
+
+/// The virtual table behind `create_table_function`.
+pub struct FunctionTable {
+    spec: Arc<FunctionSpec>,
+}
+
+/// A cursor over the rows returned by a table-valued function.
+pub struct FunctionCursor {
+    spec: Arc<FunctionSpec>,
+    args: Vec<BindArg>,
+    rows: Option<Rows>,
+    row: Option<Vec<BindArg>>,
+    rowid: i64,
+}
+
+impl VTab for FunctionTable {
+    type Aux = (Arc<FunctionSpec>, String);
+    type Cursor = FunctionCursor;
+
+    fn connect(aux: &Self::Aux, _args: &[&str]) -> VTabResult<(String, FunctionTable)> {
+        Ok((aux.1.clone(), FunctionTable { spec: aux.0.clone() }))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> VTabResult<()> {
+        best_args_index(info, self.spec.columns, self.spec.args)
+    }
+
+    fn open(&self) -> VTabResult<FunctionCursor> {
+        Ok(FunctionCursor { spec: self.spec.clone(), args: Vec::new(), rows: None, row: None, rowid: 0 })
+    }
+}
+
+impl VTabCursor for FunctionCursor {
+    fn filter(&mut self, idx_num: i32, _idx_str: Option<&str>, args: &[Value]) -> VTabResult<()> {
+        self.args = args_of(idx_num, self.spec.args, args);
+        self.rows = Some((self.spec.f)(&self.args)?);
+        self.rowid = 0;
+        self.next()
+    }
+
+    fn next(&mut self) -> VTabResult<()> {
+        self.row = self.rows.as_mut().and_then(|rows| rows.next());
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.row.is_none()
+    }
+
+    fn column(&self, i: i32) -> VTabResult<BindArg> {
+        let i = i as usize;
+        let row = self.row.as_ref().unwrap();
+        Ok(if i < self.spec.columns {
+            row.get(i).cloned().unwrap_or(Null)
+        } else {
+            self.args[i - self.spec.columns].clone()
+        })
+    }
+
+    fn rowid(&self) -> VTabResult<i64> {
+        Ok(self.rowid)
+    }
+}
+
+/// Iterates `start`, `start + step`, ... up to `stop` included.
+struct Series {
+    next: Option<i64>,
+    stop: i64,
+    step: i64,
+}
+
+impl Iterator for Series {
+    type Item = Vec<BindArg>;
+
+    fn next(&mut self) -> Option<Vec<BindArg>> {
+        let v = self.next?;
+        if (self.step > 0 && v > self.stop) || (self.step < 0 && v < self.stop) {
+            return None;
+        }
+        self.next = v.checked_add(self.step);
+        Some(vec![Integer64(v)])
+    }
+}
+
+fn generate_series(args: &[BindArg]) -> VTabResult<Series> {
+    fn int(arg: &BindArg, default: i64) -> VTabResult<i64> {
+        match *arg {
+            Null => Ok(default),
+            Integer(v) => Ok(v as i64),
+            Integer64(v) => Ok(v),
+            Float64(v) if v.fract() == 0.0 => Ok(v as i64),
+            _ => Err(VTabError::message("generate_series: arguments must be integers")),
+        }
+    }
+    if args[0] == Null {
+        return Err(VTabError::message("generate_series: missing start value"));
+    }
+    let step = int(&args[2], 1)?;
+    if step == 0 {
+        return Err(VTabError::message("generate_series: step must not be zero"));
+    }
+    let stop = int(&args[1], if step > 0 { i64::MAX } else { i64::MIN })?;
+    Ok(Series { next: Some(int(&args[0], 0)?), stop, step })
+}
+
+/// An array bound to a statement with `Cursor::bind_array`.
+pub type Array = Rc<Vec<BindArg>>;
+
+/// Builds an `Array` of integers.
+pub fn int_array(values: Vec<i64>) -> Array {
+    Rc::new(values.into_iter().map(Integer64).collect())
+}
+
+/// Builds an `Array` of strings.
+pub fn text_array(values: Vec<String>) -> Array {
+    Rc::new(values.into_iter().map(Text).collect())
+}
+
+// SQLite compares pointer types with strcmp, and keeps the type string for
+// as long as the value is bound, which a string literal outlives.
+const ARRAY_POINTER_TYPE: &[u8] = b"rarray\0";
+
+extern "C" fn free_array(p: *mut c_void) {
+    unsafe { drop(Box::from_raw(p as *mut Array)); }
+}
+
+impl<'db> Cursor<'db> {
+    /// Binds an array, to be read through the `rarray` table-valued function.
+    /// See http://www.sqlite.org/carray.html
+    pub fn bind_array(&mut self, i: isize, values: Array) -> ResultCode {
+        let p = Box::into_raw(Box::new(values));
+        unsafe {
+            // the destructor is called even if binding fails.
+            sqlite3_bind_pointer(self.stmt(), i as c_int, p as *mut c_void,
+                                 ARRAY_POINTER_TYPE.as_ptr() as *const c_char, Some(free_array))
+        }
+    }
+}
+
+/// The `rarray` eponymous virtual table.
+pub struct ArrayTable;
+
+/// A cursor over an `Array`.
+pub struct ArrayCursor {
+    values: Option<Array>,
+    pos: usize,
+}
+
+impl VTab for ArrayTable {
+    type Aux = ();
+    type Cursor = ArrayCursor;
+
+    fn connect(_aux: &(), _args: &[&str]) -> VTabResult<(String, ArrayTable)> {
+        Ok(("CREATE TABLE x (value, pointer HIDDEN)".to_string(), ArrayTable))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> VTabResult<()> {
+        best_args_index(info, 1, 1)
+    }
+
+    fn open(&self) -> VTabResult<ArrayCursor> {
+        Ok(ArrayCursor { values: None, pos: 0 })
+    }
+}
+
+impl VTabCursor for ArrayCursor {
+    fn filter(&mut self, idx_num: i32, _idx_str: Option<&str>, args: &[Value]) -> VTabResult<()> {
+        self.values = None;
+        self.pos = 0;
+        if idx_num & 1 != 0 {
+            let p = unsafe {
+                sqlite3_value_pointer(args[0].as_ptr(), ARRAY_POINTER_TYPE.as_ptr() as *const c_char)
+            };
+            if !p.is_null() {
+                self.values = Some(unsafe { &*(p as *const Array) }.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> VTabResult<()> {
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.values.as_ref().is_none_or(|values| self.pos >= values.len())
+    }
+
+    fn column(&self, i: i32) -> VTabResult<BindArg> {
+        Ok(if i == 0 { self.values.as_ref().unwrap()[self.pos].clone() } else { Null })
+    }
+
+    fn rowid(&self) -> VTabResult<i64> {
+        Ok(self.pos as i64 + 1)
+    }
+}
+
+impl Database {
+
+    /// Registers a table-valued function named `name`, returning rows of
+    /// `columns` for the values of its arguments `args`.
+    ///
+    /// Arguments are hidden columns of the function's table; in
+    /// `SELECT * FROM name(?1, ?2)` they are passed to `f` in order, with
+    /// `Null` for those omitted. Rows shorter than `columns` are padded with `Null`.
+    pub fn create_table_function<F, I>(&mut self, name: &str, columns: &[&str], args: &[&str], f: F)
+                                       -> SqliteResult<()>
+        where F: Fn(&[BindArg]) -> VTabResult<I> + Send + Sync + 'static,
+              I: IntoIterator<Item = Vec<BindArg>>,
+              I::IntoIter: 'static
+    {
+        let decls: Vec<String> = columns.iter().map(|c| quote_identifier(c))
+            .chain(args.iter().map(|a| format!("{} HIDDEN", quote_identifier(a))))
+            .collect();
+        let schema = format!("CREATE TABLE x ({})", decls.join(", "));
+        let spec = FunctionSpec {
+            columns: columns.len(),
+            args: args.len(),
+            f: Box::new(move |args| f(args).map(|rows| Box::new(rows.into_iter()) as Rows)),
+        };
+        self.create_eponymous_module::<FunctionTable>(name, (Arc::new(spec), schema))
+    }
+
+    /// Registers the `generate_series(start, stop, step)` table-valued function,
+    /// returning a single `value` column. `stop` defaults to the largest
+    /// integer, and `step` to 1.
+    pub fn load_generate_series(&mut self) -> SqliteResult<()> {
+        self.create_table_function("generate_series", &["value"], &["start", "stop", "step"], generate_series)
+    }
+
+    /// Registers the `rarray(?)` table-valued function, returning the values
+    /// of an array bound with `Cursor::bind_array` as a `value` column.
+    pub fn load_array_module(&mut self) -> SqliteResult<()> {
+        self.create_eponymous_module::<ArrayTable>("rarray", ())
+    }
+}
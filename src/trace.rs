@@ -0,0 +1,254 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Statement tracing and profiling, built on `sqlite3_trace_v2`.
+//!
+//! A closure registered through `Database::trace` receives a `TraceEvent`
+//! for every kind of event selected in the mask. `LogTracer` is a ready-made
+//! adapter which forwards statements and their running time to the `log` crate.
+
+use ffi::*;
+use libc::{c_int, c_uint, c_void, c_char};
+use log::LogLevel;
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+use database::Database;
+use types::*;
+use types::ResultCode::*;
+
+/// Invoked when a prepared statement starts running.
+pub const SQLITE_TRACE_STMT: u32 = 0x01;
+/// Invoked when a prepared statement finishes, with its running time.
+pub const SQLITE_TRACE_PROFILE: u32 = 0x02;
+/// Invoked whenever a prepared statement generates a single row of result.
+pub const SQLITE_TRACE_ROW: u32 = 0x04;
+/// Invoked when the database connection closes.
+pub const SQLITE_TRACE_CLOSE: u32 = 0x08;
+
+pub type TraceHook = Box<dyn FnMut(TraceEvent) + Send>;
+
+/// A statement as seen from within a trace callback.
+///
+/// It is only valid for the duration of the callback.
+pub struct TracedStatement<'a> {
+    stmt: *mut stmt,
+    _marker: PhantomData<&'a stmt>,
+}
+
+impl<'a> TracedStatement<'a> {
+    /// Returns the SQL text used to prepare the statement, with parameters
+    /// left as placeholders.
+    /// See http://www.sqlite.org/c3ref/expanded_sql.html
+    pub fn sql(&self) -> Option<String> {
+        unsafe {
+            let sql = sqlite3_sql(self.stmt);
+            if sql.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(sql).to_string_lossy().into_owned())
+            }
+        }
+    }
+
+    /// Returns the SQL text of the statement with bound parameters expanded
+    /// in place.
+    /// See http://www.sqlite.org/c3ref/expanded_sql.html
+    pub fn expanded_sql(&self) -> Option<String> {
+        unsafe {
+            let sql = sqlite3_expanded_sql(self.stmt);
+            if sql.is_null() {
+                None
+            } else {
+                let r = CStr::from_ptr(sql).to_string_lossy().into_owned();
+                sqlite3_free(sql as *mut c_void);
+                Some(r)
+            }
+        }
+    }
+}
+
+/// An event reported to a trace callback.
+pub enum TraceEvent<'a> {
+    /// A statement started running. The text is the unexpanded SQL, or
+    /// a comment naming the trigger for statements run by a trigger.
+    Statement(TracedStatement<'a>, &'a str),
+    /// A statement finished running after the given (wall clock) time.
+    Profile(TracedStatement<'a>, Duration),
+    /// A statement produced a row.
+    Row(TracedStatement<'a>),
+    /// The database connection is being closed.
+    Close,
+}
+
+extern "C" fn trace_callback(kind: c_uint, ctx: *mut c_void, p: *mut c_void, x: *mut c_void) -> c_int {
+    let traced = TracedStatement { stmt: p as *mut stmt, _marker: PhantomData };
+    let event = match kind {
+        SQLITE_TRACE_STMT => {
+            let text = unsafe { CStr::from_ptr(x as *const c_char) };
+            TraceEvent::Statement(traced, text.to_str().unwrap_or(""))
+        }
+        SQLITE_TRACE_PROFILE => {
+            let nanos = unsafe { *(x as *const i64) };
+            TraceEvent::Profile(traced, Duration::from_nanos(nanos as u64))
+        }
+        SQLITE_TRACE_ROW => TraceEvent::Row(traced),
+        SQLITE_TRACE_CLOSE => TraceEvent::Close,
+        _ => return 0,
+    };
+    let hook = unsafe { &mut *(ctx as *mut TraceHook) };
+    // unwinding into SQLite is not an option; a panicking tracer just loses the event.
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(event)));
+    0
+}
+
+impl Database {
+
+    /// Registers a trace callback for the events selected by `mask`
+    /// (a combination of the `SQLITE_TRACE_*` constants), replacing any
+    /// previously registered one.
+    /// See http://www.sqlite.org/c3ref/trace_v2.html
+    pub fn trace<F>(&mut self, mask: u32, f: F) -> SqliteResult<()>
+        where F: FnMut(TraceEvent) + Send + 'static
+    {
+        let mut hook: Box<TraceHook> = Box::new(Box::new(f));
+        let r = unsafe {
+            sqlite3_trace_v2(self.dbh, mask as c_uint, Some(trace_callback),
+                             &mut *hook as *mut TraceHook as *mut c_void)
+        };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        self.trace_hook = Some(hook);
+        Ok(())
+    }
+
+    /// Removes the trace callback, if any.
+    /// See http://www.sqlite.org/c3ref/trace_v2.html
+    pub fn untrace(&mut self) -> SqliteResult<()> {
+        let r = unsafe {
+            sqlite3_trace_v2(self.dbh, 0, None, ptr::null_mut())
+        };
+        if r != SQLITE_OK {
+            return Err(r);
+        }
+        self.trace_hook = None;
+        Ok(())
+    }
+
+    /// Forwards statements run on this connection to the `log` crate
+    /// as configured by `tracer`.
+    pub fn trace_to_log(&mut self, tracer: LogTracer) -> SqliteResult<()> {
+        let mut mask = 0;
+        if tracer.statement_level.is_some() { mask |= SQLITE_TRACE_STMT; }
+        if tracer.profile_level.is_some() { mask |= SQLITE_TRACE_PROFILE; }
+        if mask == 0 {
+            return self.untrace();
+        }
+        self.trace(mask, move |event| tracer.log(&event))
+    }
+}
+
+/// Logs traced statements through the `log` crate under the `sqlite3::trace` target.
+///
+/// By default, statements are logged at `Debug` when they start and at `Info`
+/// with their elapsed time when they finish. Bound parameter values are
+/// included in the logged SQL unless `redact_params` is set.
+#[derive(Clone, Copy, Debug)]
+pub struct LogTracer {
+    statement_level: Option<LogLevel>,
+    profile_level: Option<LogLevel>,
+    redact_params: bool,
+}
+
+impl Default for LogTracer {
+    fn default() -> LogTracer {
+        LogTracer {
+            statement_level: Some(LogLevel::Debug),
+            profile_level: Some(LogLevel::Info),
+            redact_params: false,
+        }
+    }
+}
+
+impl LogTracer {
+    pub fn new() -> LogTracer {
+        LogTracer::default()
+    }
+
+    /// Sets the level statements are logged at when they start, or `None`
+    /// to not log them.
+    pub fn statement_level(mut self, level: Option<LogLevel>) -> LogTracer {
+        self.statement_level = level;
+        self
+    }
+
+    /// Sets the level statements are logged at with their elapsed time
+    /// once finished, or `None` to not log them.
+    pub fn profile_level(mut self, level: Option<LogLevel>) -> LogTracer {
+        self.profile_level = level;
+        self
+    }
+
+    /// Logs the SQL text with placeholders instead of bound values.
+    pub fn redact_params(mut self, redact: bool) -> LogTracer {
+        self.redact_params = redact;
+        self
+    }
+
+    fn sql_of(&self, stmt: &TracedStatement) -> String {
+        let sql = if self.redact_params { stmt.sql() } else { stmt.expanded_sql() };
+        sql.unwrap_or_default()
+    }
+
+    fn log(&self, event: &TraceEvent) {
+        match *event {
+            TraceEvent::Statement(ref stmt, text) => {
+                if let Some(level) = self.statement_level {
+                    // statements run by triggers are reported as `-- TRIGGER name`.
+                    if text.starts_with("--") {
+                        log!(target: "sqlite3::trace", level, "{}", text);
+                    } else {
+                        log!(target: "sqlite3::trace", level, "{}", self.sql_of(stmt));
+                    }
+                }
+            }
+            TraceEvent::Profile(ref stmt, elapsed) => {
+                if let Some(level) = self.profile_level {
+                    log!(target: "sqlite3::trace", level, "{} ({:?})", self.sql_of(stmt), elapsed);
+                }
+            }
+            TraceEvent::Row(..) | TraceEvent::Close => {}
+        }
+    }
+}
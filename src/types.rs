@@ -104,7 +104,7 @@ impl fmt::Debug for ResultCode {
     }
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum BindArg {
     Text(String),
     StaticText(&'static str),
@@ -115,7 +115,7 @@ pub enum BindArg {
     Null,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ColumnType {
     SQLITE_INTEGER,
     SQLITE_FLOAT,
@@ -124,10 +124,29 @@ pub enum ColumnType {
     SQLITE_NULL,
 }
 
+/// Flags for `open_with_flags`.
+/// See http://www.sqlite.org/c3ref/c_open_autoproxy.html
+pub const SQLITE_OPEN_READONLY: i32     = 0x00000001;
+pub const SQLITE_OPEN_READWRITE: i32    = 0x00000002;
+pub const SQLITE_OPEN_CREATE: i32       = 0x00000004;
+pub const SQLITE_OPEN_URI: i32          = 0x00000040;
+pub const SQLITE_OPEN_MEMORY: i32       = 0x00000080;
+pub const SQLITE_OPEN_NOMUTEX: i32      = 0x00008000;
+pub const SQLITE_OPEN_FULLMUTEX: i32    = 0x00010000;
+pub const SQLITE_OPEN_SHAREDCACHE: i32  = 0x00020000;
+pub const SQLITE_OPEN_PRIVATECACHE: i32 = 0x00040000;
+
 pub type SqliteResult<T> = Result<T, ResultCode>;
 
 pub type RowMap = HashMap<String, BindArg>;
 
+/// Quotes `name` for use as an SQL identifier, doubling embedded quotes.
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
 pub enum dbh {}
 pub enum stmt {}
+pub enum value {}
+pub enum context {}
 pub enum _notused {}
@@ -0,0 +1,708 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Virtual tables implemented in Rust.
+//!
+//! A virtual table module is a type implementing `VTab`, whose cursors
+//! implement `VTabCursor`. It is registered on a connection with
+//! `Database::create_module` (usable with `CREATE VIRTUAL TABLE`) or
+//! `Database::create_eponymous_module` (usable directly by its name, e.g. as
+//! a table-valued function). The C structures SQLite expects are handled
+//! internally; a panic in any method is turned into an `SQLITE_ERROR`.
+//! See http://www.sqlite.org/vtab.html
+
+use ffi::*;
+use libc::{c_int, c_void, c_char};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::str;
+use database::Database;
+use types::*;
+use types::BindArg::*;
+use types::ColumnType::*;
+use types::ResultCode::*;
+
+/// An error reported by a virtual table, with an optional message shown to
+/// the user instead of the generic one for `code`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct VTabError {
+    pub code: ResultCode,
+    pub message: Option<String>,
+}
+
+impl VTabError {
+    /// An `SQLITE_ERROR` with the given message.
+    pub fn message<S: Into<String>>(message: S) -> VTabError {
+        VTabError { code: SQLITE_ERROR, message: Some(message.into()) }
+    }
+}
+
+impl From<ResultCode> for VTabError {
+    fn from(code: ResultCode) -> VTabError {
+        VTabError { code, message: None }
+    }
+}
+
+impl fmt::Display for VTabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.message {
+            Some(ref msg) => write!(f, "{} ({:?})", msg, self.code),
+            None => write!(f, "{:?}", self.code),
+        }
+    }
+}
+
+pub type VTabResult<T> = Result<T, VTabError>;
+
+/// An SQL value passed to a virtual table, valid for the duration of the call.
+#[derive(Clone, Copy)]
+pub struct Value<'a> {
+    ptr: *mut value,
+    _marker: PhantomData<&'a value>,
+}
+
+impl<'a> fmt::Debug for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Value ptr={:?}>", self.ptr)
+    }
+}
+
+impl<'a> Value<'a> {
+    pub(crate) unsafe fn from_raw(ptr: *mut value) -> Value<'a> {
+        Value { ptr, _marker: PhantomData }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut value {
+        self.ptr
+    }
+
+    /// See http://www.sqlite.org/c3ref/value_blob.html
+    pub fn get_type(&self) -> ColumnType {
+        match unsafe { sqlite3_value_type(self.ptr) } {
+            1 => SQLITE_INTEGER,
+            2 => SQLITE_FLOAT,
+            3 => SQLITE_TEXT,
+            4 => SQLITE_BLOB,
+            _ => SQLITE_NULL,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        unsafe { sqlite3_value_type(self.ptr) == 5 }
+    }
+
+    pub fn get_i64(&self) -> i64 {
+        unsafe { sqlite3_value_int64(self.ptr) }
+    }
+
+    pub fn get_f64(&self) -> f64 {
+        unsafe { sqlite3_value_double(self.ptr) }
+    }
+
+    pub fn get_text(&self) -> Option<&'a str> {
+        let ptr = unsafe { sqlite3_value_text(self.ptr) } as *const u8;
+        let len = unsafe { sqlite3_value_bytes(self.ptr) } as usize;
+        if ptr.is_null() {
+            None
+        } else {
+            unsafe { str::from_utf8(slice::from_raw_parts(ptr, len)).ok() }
+        }
+    }
+
+    pub fn get_blob(&self) -> Option<&'a [u8]> {
+        let ptr = unsafe { sqlite3_value_blob(self.ptr) };
+        let len = unsafe { sqlite3_value_bytes(self.ptr) } as usize;
+        if ptr.is_null() {
+            None
+        } else {
+            unsafe { Some(slice::from_raw_parts(ptr, len)) }
+        }
+    }
+
+    /// Copies the value, according to its type.
+    pub fn to_bind_arg(&self) -> BindArg {
+        match self.get_type() {
+            SQLITE_INTEGER => Integer64(self.get_i64()),
+            SQLITE_FLOAT => Float64(self.get_f64()),
+            SQLITE_TEXT => Text(self.get_text().unwrap_or("").to_string()),
+            SQLITE_BLOB => Blob(self.get_blob().unwrap_or(&[]).to_vec()),
+            SQLITE_NULL => Null,
+        }
+    }
+}
+
+/// Sets the result of a function or column to `value`.
+pub(crate) fn set_result(ctx: *mut context, value: &BindArg) {
+    unsafe {
+        match *value {
+            Text(ref v) => sqlite3_result_text(ctx, v.as_ptr() as *const c_char, v.len() as c_int,
+                                               -1isize as *mut c_void), // SQLITE_TRANSIENT
+            StaticText(v) => sqlite3_result_text(ctx, v.as_ptr() as *const c_char, v.len() as c_int,
+                                                 ptr::null_mut()), // SQLITE_STATIC
+            Blob(ref v) => sqlite3_result_blob(ctx, v.as_ptr(), v.len() as c_int,
+                                               -1isize as *mut c_void), // SQLITE_TRANSIENT
+            Float64(v) => sqlite3_result_double(ctx, v),
+            Integer(v) => sqlite3_result_int64(ctx, v as i64),
+            Integer64(v) => sqlite3_result_int64(ctx, v),
+            Null => sqlite3_result_null(ctx),
+        }
+    }
+}
+
+/// Copies `s` into memory obtained from `sqlite3_malloc`, as SQLite expects
+/// for error messages and index strings.
+pub(crate) fn sqlite_string(s: &str) -> *mut c_char {
+    let s = s.replace('\0', "");
+    unsafe {
+        let p = sqlite3_malloc(s.len() as c_int + 1) as *mut c_char;
+        if !p.is_null() {
+            ptr::copy_nonoverlapping(s.as_ptr() as *const c_char, p, s.len());
+            *p.add(s.len()) = 0;
+        }
+        p
+    }
+}
+
+/// The operator of a constraint in the `WHERE` clause.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConstraintOp {
+    Eq,
+    Gt,
+    Le,
+    Lt,
+    Ge,
+    Match,
+    Like,
+    Glob,
+    Regexp,
+    Ne,
+    IsNot,
+    IsNotNull,
+    IsNull,
+    Is,
+    Limit,
+    Offset,
+    /// An overloaded function, see `xFindFunction`.
+    Function(u8),
+}
+
+impl ConstraintOp {
+    fn from_raw(op: u8) -> ConstraintOp {
+        match op {
+            2 => ConstraintOp::Eq,
+            4 => ConstraintOp::Gt,
+            8 => ConstraintOp::Le,
+            16 => ConstraintOp::Lt,
+            32 => ConstraintOp::Ge,
+            64 => ConstraintOp::Match,
+            65 => ConstraintOp::Like,
+            66 => ConstraintOp::Glob,
+            67 => ConstraintOp::Regexp,
+            68 => ConstraintOp::Ne,
+            69 => ConstraintOp::IsNot,
+            70 => ConstraintOp::IsNotNull,
+            71 => ConstraintOp::IsNull,
+            72 => ConstraintOp::Is,
+            73 => ConstraintOp::Limit,
+            74 => ConstraintOp::Offset,
+            op => ConstraintOp::Function(op),
+        }
+    }
+}
+
+/// A constraint on a column (`-1` for the rowid) the table may make use of.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IndexConstraint {
+    pub column: i32,
+    pub op: ConstraintOp,
+    /// Unusable constraints must not be given an argument index.
+    pub usable: bool,
+}
+
+/// A term of the `ORDER BY` clause.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IndexOrderBy {
+    pub column: i32,
+    pub desc: bool,
+}
+
+/// The query planning information passed to `VTab::best_index`.
+/// See http://www.sqlite.org/vtab.html#the_xbestindex_method
+pub struct IndexInfo<'a> {
+    raw: &'a mut sqlite3_index_info,
+}
+
+impl<'a> IndexInfo<'a> {
+    /// Returns the constraints of the `WHERE` clause applying to the table.
+    pub fn constraints(&self) -> Vec<IndexConstraint> {
+        let raw = unsafe { slice::from_raw_parts(self.raw.aConstraint, self.raw.nConstraint as usize) };
+        raw.iter().map(|c| IndexConstraint {
+            column: c.iColumn,
+            op: ConstraintOp::from_raw(c.op),
+            usable: c.usable != 0,
+        }).collect()
+    }
+
+    /// Returns the terms of the `ORDER BY` clause.
+    pub fn order_by(&self) -> Vec<IndexOrderBy> {
+        let raw = unsafe { slice::from_raw_parts(self.raw.aOrderBy, self.raw.nOrderBy as usize) };
+        raw.iter().map(|o| IndexOrderBy { column: o.iColumn, desc: o.desc != 0 }).collect()
+    }
+
+    fn usage(&mut self, constraint: usize) -> &mut sqlite3_index_constraint_usage {
+        assert!(constraint < self.raw.nConstraint as usize, "constraint index out of bounds");
+        unsafe { &mut *self.raw.aConstraintUsage.add(constraint) }
+    }
+
+    /// Requests the right-hand value of the `constraint`-th constraint to be
+    /// passed to `VTabCursor::filter` at `args[argv_index - 1]`.
+    pub fn set_argv_index(&mut self, constraint: usize, argv_index: i32) {
+        self.usage(constraint).argvIndex = argv_index;
+    }
+
+    /// Tells SQLite it need not double check the `constraint`-th constraint.
+    pub fn set_omit(&mut self, constraint: usize, omit: bool) {
+        self.usage(constraint).omit = omit as u8;
+    }
+
+    /// Tells SQLite the rows come out in the requested order.
+    pub fn set_order_by_consumed(&mut self, consumed: bool) {
+        self.raw.orderByConsumed = consumed as c_int;
+    }
+
+    pub fn set_estimated_cost(&mut self, cost: f64) {
+        self.raw.estimatedCost = cost;
+    }
+
+    pub fn set_estimated_rows(&mut self, rows: i64) {
+        self.raw.estimatedRows = rows;
+    }
+
+    /// Tells SQLite at most one row is returned.
+    pub fn set_unique(&mut self, unique: bool) {
+        // SQLITE_INDEX_SCAN_UNIQUE
+        if unique { self.raw.idxFlags |= 1; } else { self.raw.idxFlags &= !1; }
+    }
+
+    /// Sets the number passed to `VTabCursor::filter` as `idx_num`.
+    pub fn set_idx_num(&mut self, idx_num: i32) {
+        self.raw.idxNum = idx_num;
+    }
+
+    /// Sets the string passed to `VTabCursor::filter` as `idx_str`.
+    pub fn set_idx_str(&mut self, idx_str: &str) {
+        unsafe {
+            if self.raw.needToFreeIdxStr != 0 {
+                sqlite3_free(self.raw.idxStr as *mut c_void);
+            }
+        }
+        self.raw.idxStr = sqlite_string(idx_str);
+        self.raw.needToFreeIdxStr = 1;
+    }
+
+    /// Returns the mask of columns used by the statement; the bit 63 stands
+    /// for all columns from the 64th on.
+    pub fn columns_used(&self) -> u64 {
+        self.raw.colUsed
+    }
+}
+
+/// A change requested through `INSERT`, `UPDATE` or `DELETE`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum VTabUpdate {
+    Delete(i64),
+    /// The new row, with its rowid if one was given.
+    Insert(Option<i64>, Vec<BindArg>),
+    /// The old rowid, the new rowid and the new row.
+    Update(i64, i64, Vec<BindArg>),
+}
+
+/// A virtual table.
+///
+/// Tables and their `Aux` data are owned by the connection, which is `Send`,
+/// so they must be `Send` too.
+pub trait VTab: Sized + Send + 'static {
+    /// The data given to `create_module`, shared by all tables of the module.
+    type Aux: Send + 'static;
+    type Cursor: VTabCursor;
+
+    /// Connects to an existing table (`xConnect`), returning the
+    /// `CREATE TABLE` statement declaring its columns and the table.
+    ///
+    /// `args` are the module name, the database name, the table name and
+    /// the arguments given in `CREATE VIRTUAL TABLE`, if any.
+    fn connect(aux: &Self::Aux, args: &[&str]) -> VTabResult<(String, Self)>;
+
+    /// Creates a table on `CREATE VIRTUAL TABLE` (`xCreate`).
+    /// By default it is the same as `connect`.
+    fn create(aux: &Self::Aux, args: &[&str]) -> VTabResult<(String, Self)> {
+        Self::connect(aux, args)
+    }
+
+    /// Chooses how to run a query (`xBestIndex`).
+    fn best_index(&self, info: &mut IndexInfo) -> VTabResult<()>;
+
+    /// Opens a new cursor (`xOpen`).
+    fn open(&self) -> VTabResult<Self::Cursor>;
+
+    /// Called on `DROP TABLE` before the table is dropped (`xDestroy`).
+    fn destroy(&mut self) -> VTabResult<()> {
+        Ok(())
+    }
+
+    /// Changes the table contents (`xUpdate`), returning the rowid of an
+    /// inserted row. Tables are read-only by default.
+    fn update(&mut self, _change: VTabUpdate) -> VTabResult<i64> {
+        Err(VTabError::from(SQLITE_READONLY))
+    }
+
+    fn begin(&mut self) -> VTabResult<()> { Ok(()) }
+    fn sync(&mut self) -> VTabResult<()> { Ok(()) }
+    fn commit(&mut self) -> VTabResult<()> { Ok(()) }
+    fn rollback(&mut self) -> VTabResult<()> { Ok(()) }
+}
+
+/// A cursor over a virtual table.
+pub trait VTabCursor: Sized {
+    /// Starts a new search (`xFilter`) with the `idx_num` and `idx_str`
+    /// chosen by `VTab::best_index`, and the requested constraint values.
+    fn filter(&mut self, idx_num: i32, idx_str: Option<&str>, args: &[Value]) -> VTabResult<()>;
+
+    /// Advances to the next row (`xNext`).
+    fn next(&mut self) -> VTabResult<()>;
+
+    /// Returns whether the cursor is past the last row (`xEof`).
+    fn eof(&self) -> bool;
+
+    /// Returns the `i`-th column of the current row (`xColumn`).
+    fn column(&self, i: i32) -> VTabResult<BindArg>;
+
+    /// Returns the rowid of the current row (`xRowid`).
+    fn rowid(&self) -> VTabResult<i64>;
+}
+
+struct ModuleData<T: VTab> {
+    module: sqlite3_module,
+    aux: T::Aux,
+}
+
+#[repr(C)]
+struct VTabHandle<T> {
+    base: sqlite3_vtab,
+    vtab: T,
+}
+
+#[repr(C)]
+struct CursorHandle<C> {
+    base: sqlite3_vtab_cursor,
+    cursor: C,
+}
+
+/// Sets the error message of a table, replacing the previous one.
+unsafe fn set_vtab_error(vtab: *mut sqlite3_vtab, message: &str) {
+    if !(*vtab).zErrMsg.is_null() {
+        sqlite3_free((*vtab).zErrMsg as *mut c_void);
+    }
+    (*vtab).zErrMsg = sqlite_string(message);
+}
+
+/// Runs `f`, turning errors and panics into a result code and a message.
+fn guard<T, F>(f: F) -> Result<T, (c_int, Option<String>)>
+    where F: FnOnce() -> VTabResult<T>
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(e)) => Err((e.code as c_int, e.message)),
+        Err(..) => Err((SQLITE_ERROR as c_int, Some("panic in virtual table".to_string()))),
+    }
+}
+
+/// Like `guard`, reporting the error message on the table.
+fn guard_vtab<F>(vtab: *mut sqlite3_vtab, f: F) -> c_int
+    where F: FnOnce() -> VTabResult<()>
+{
+    match guard(f) {
+        Ok(()) => SQLITE_OK as c_int,
+        Err((code, message)) => {
+            if let Some(message) = message {
+                unsafe { set_vtab_error(vtab, &message); }
+            }
+            code
+        }
+    }
+}
+
+extern "C" fn x_create<T: VTab>(db: *mut dbh, aux: *mut c_void, argc: c_int, argv: *const *const c_char,
+                                pp_vtab: *mut *mut sqlite3_vtab, err: *mut *mut c_char) -> c_int {
+    connect_or_create::<T>(db, aux, argc, argv, pp_vtab, err, true)
+}
+
+extern "C" fn x_connect<T: VTab>(db: *mut dbh, aux: *mut c_void, argc: c_int, argv: *const *const c_char,
+                                 pp_vtab: *mut *mut sqlite3_vtab, err: *mut *mut c_char) -> c_int {
+    connect_or_create::<T>(db, aux, argc, argv, pp_vtab, err, false)
+}
+
+fn connect_or_create<T: VTab>(db: *mut dbh, aux: *mut c_void, argc: c_int, argv: *const *const c_char,
+                              pp_vtab: *mut *mut sqlite3_vtab, err: *mut *mut c_char, create: bool) -> c_int {
+    let data = unsafe { &*(aux as *const ModuleData<T>) };
+    let args: Vec<&str> = unsafe { slice::from_raw_parts(argv, argc as usize) }.iter()
+        .map(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().unwrap_or(""))
+        .collect();
+    let r = guard(|| {
+        let (schema, vtab) = if create { T::create(&data.aux, &args)? } else { T::connect(&data.aux, &args)? };
+        let schema = CString::new(schema).map_err(|_| VTabError::message("invalid table declaration"))?;
+        let r = unsafe { sqlite3_declare_vtab(db, schema.as_ptr()) };
+        if r != SQLITE_OK {
+            let msg = unsafe { CStr::from_ptr(sqlite3_errmsg(db)) }.to_string_lossy().into_owned();
+            return Err(VTabError { code: r, message: Some(msg) });
+        }
+        Ok(vtab)
+    });
+    match r {
+        Ok(vtab) => {
+            let handle = Box::new(VTabHandle {
+                base: sqlite3_vtab { pModule: ptr::null(), nRef: 0, zErrMsg: ptr::null_mut() },
+                vtab,
+            });
+            unsafe { *pp_vtab = Box::into_raw(handle) as *mut sqlite3_vtab; }
+            SQLITE_OK as c_int
+        }
+        Err((code, message)) => {
+            if let Some(message) = message {
+                unsafe { *err = sqlite_string(&message); }
+            }
+            code
+        }
+    }
+}
+
+fn vtab_of<'a, T>(vtab: *mut sqlite3_vtab) -> &'a mut T {
+    unsafe { &mut (*(vtab as *mut VTabHandle<T>)).vtab }
+}
+
+fn cursor_of<'a, C>(cursor: *mut sqlite3_vtab_cursor) -> &'a mut C {
+    unsafe { &mut (*(cursor as *mut CursorHandle<C>)).cursor }
+}
+
+extern "C" fn x_best_index<T: VTab>(vtab: *mut sqlite3_vtab, info: *mut sqlite3_index_info) -> c_int {
+    guard_vtab(vtab, || {
+        let mut info = IndexInfo { raw: unsafe { &mut *info } };
+        vtab_of::<T>(vtab).best_index(&mut info)
+    })
+}
+
+extern "C" fn x_disconnect<T: VTab>(vtab: *mut sqlite3_vtab) -> c_int {
+    let handle = unsafe { Box::from_raw(vtab as *mut VTabHandle<T>) };
+    unsafe {
+        if !handle.base.zErrMsg.is_null() {
+            sqlite3_free(handle.base.zErrMsg as *mut c_void);
+        }
+    }
+    let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(handle)));
+    SQLITE_OK as c_int
+}
+
+extern "C" fn x_destroy<T: VTab>(vtab: *mut sqlite3_vtab) -> c_int {
+    let r = guard_vtab(vtab, || vtab_of::<T>(vtab).destroy());
+    if r != SQLITE_OK as c_int {
+        return r;
+    }
+    x_disconnect::<T>(vtab)
+}
+
+extern "C" fn x_open<T: VTab>(vtab: *mut sqlite3_vtab, pp_cursor: *mut *mut sqlite3_vtab_cursor) -> c_int {
+    guard_vtab(vtab, || {
+        let cursor = vtab_of::<T>(vtab).open()?;
+        let handle = Box::new(CursorHandle {
+            base: sqlite3_vtab_cursor { pVtab: ptr::null_mut() },
+            cursor,
+        });
+        unsafe { *pp_cursor = Box::into_raw(handle) as *mut sqlite3_vtab_cursor; }
+        Ok(())
+    })
+}
+
+extern "C" fn x_close<T: VTab>(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    let handle = unsafe { Box::from_raw(cursor as *mut CursorHandle<T::Cursor>) };
+    let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(handle)));
+    SQLITE_OK as c_int
+}
+
+extern "C" fn x_filter<T: VTab>(cursor: *mut sqlite3_vtab_cursor, idx_num: c_int, idx_str: *const c_char,
+                                argc: c_int, argv: *mut *mut value) -> c_int {
+    guard_vtab(unsafe { (*cursor).pVtab }, || {
+        let idx_str = if idx_str.is_null() {
+            None
+        } else {
+            unsafe { CStr::from_ptr(idx_str) }.to_str().ok()
+        };
+        let args: Vec<Value> = if argc == 0 {
+            Vec::new()
+        } else {
+            unsafe { slice::from_raw_parts(argv, argc as usize) }.iter()
+                .map(|&v| unsafe { Value::from_raw(v) })
+                .collect()
+        };
+        cursor_of::<T::Cursor>(cursor).filter(idx_num, idx_str, &args)
+    })
+}
+
+extern "C" fn x_next<T: VTab>(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    guard_vtab(unsafe { (*cursor).pVtab }, || cursor_of::<T::Cursor>(cursor).next())
+}
+
+extern "C" fn x_eof<T: VTab>(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    // a panicking cursor ends the scan.
+    panic::catch_unwind(AssertUnwindSafe(|| cursor_of::<T::Cursor>(cursor).eof())).unwrap_or(true) as c_int
+}
+
+extern "C" fn x_column<T: VTab>(cursor: *mut sqlite3_vtab_cursor, ctx: *mut context, i: c_int) -> c_int {
+    guard_vtab(unsafe { (*cursor).pVtab }, || {
+        let value = cursor_of::<T::Cursor>(cursor).column(i)?;
+        set_result(ctx, &value);
+        Ok(())
+    })
+}
+
+extern "C" fn x_rowid<T: VTab>(cursor: *mut sqlite3_vtab_cursor, rowid: *mut i64) -> c_int {
+    guard_vtab(unsafe { (*cursor).pVtab }, || {
+        unsafe { *rowid = cursor_of::<T::Cursor>(cursor).rowid()?; }
+        Ok(())
+    })
+}
+
+extern "C" fn x_update<T: VTab>(vtab: *mut sqlite3_vtab, argc: c_int, argv: *mut *mut value,
+                                rowid: *mut i64) -> c_int {
+    guard_vtab(vtab, || {
+        let args: Vec<Value> = unsafe { slice::from_raw_parts(argv, argc as usize) }.iter()
+            .map(|&v| unsafe { Value::from_raw(v) })
+            .collect();
+        let values = || args[2..].iter().map(|v| v.to_bind_arg()).collect();
+        let change = if args.len() == 1 {
+            VTabUpdate::Delete(args[0].get_i64())
+        } else if args[0].is_null() {
+            let new_rowid = if args[1].is_null() { None } else { Some(args[1].get_i64()) };
+            VTabUpdate::Insert(new_rowid, values())
+        } else {
+            VTabUpdate::Update(args[0].get_i64(), args[1].get_i64(), values())
+        };
+        let is_insert = matches!(change, VTabUpdate::Insert(..));
+        let new_rowid = vtab_of::<T>(vtab).update(change)?;
+        if is_insert {
+            unsafe { *rowid = new_rowid; }
+        }
+        Ok(())
+    })
+}
+
+extern "C" fn x_begin<T: VTab>(vtab: *mut sqlite3_vtab) -> c_int {
+    guard_vtab(vtab, || vtab_of::<T>(vtab).begin())
+}
+
+extern "C" fn x_sync<T: VTab>(vtab: *mut sqlite3_vtab) -> c_int {
+    guard_vtab(vtab, || vtab_of::<T>(vtab).sync())
+}
+
+extern "C" fn x_commit<T: VTab>(vtab: *mut sqlite3_vtab) -> c_int {
+    guard_vtab(vtab, || vtab_of::<T>(vtab).commit())
+}
+
+extern "C" fn x_rollback<T: VTab>(vtab: *mut sqlite3_vtab) -> c_int {
+    guard_vtab(vtab, || vtab_of::<T>(vtab).rollback())
+}
+
+extern "C" fn x_free_module<T: VTab>(data: *mut c_void) {
+    let data = unsafe { Box::from_raw(data as *mut ModuleData<T>) };
+    let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(data)));
+}
+
+fn module<T: VTab>(eponymous_only: bool) -> sqlite3_module {
+    sqlite3_module {
+        iVersion: 1,
+        xCreate: if eponymous_only { None } else { Some(x_create::<T>) },
+        xConnect: Some(x_connect::<T>),
+        xBestIndex: Some(x_best_index::<T>),
+        xDisconnect: Some(x_disconnect::<T>),
+        xDestroy: Some(x_destroy::<T>),
+        xOpen: Some(x_open::<T>),
+        xClose: Some(x_close::<T>),
+        xFilter: Some(x_filter::<T>),
+        xNext: Some(x_next::<T>),
+        xEof: Some(x_eof::<T>),
+        xColumn: Some(x_column::<T>),
+        xRowid: Some(x_rowid::<T>),
+        xUpdate: Some(x_update::<T>),
+        xBegin: Some(x_begin::<T>),
+        xSync: Some(x_sync::<T>),
+        xCommit: Some(x_commit::<T>),
+        xRollback: Some(x_rollback::<T>),
+        xFindFunction: None,
+        xRename: None,
+        xSavepoint: None,
+        xRelease: None,
+        xRollbackTo: None,
+        xShadowName: None,
+        xIntegrity: None,
+    }
+}
+
+impl Database {
+
+    fn register_module<T: VTab>(&mut self, name: &str, aux: T::Aux, eponymous_only: bool) -> SqliteResult<()> {
+        let name = CString::new(name.as_bytes()).unwrap();
+        let data = Box::into_raw(Box::new(ModuleData::<T> { module: module::<T>(eponymous_only), aux }));
+        // SQLite takes ownership of `data`, freeing it even if this fails.
+        let r = unsafe {
+            sqlite3_create_module_v2(self.dbh, name.as_ptr(), &(*data).module, data as *mut c_void,
+                                     Some(x_free_module::<T>))
+        };
+        if r == SQLITE_OK { Ok(()) } else { Err(r) }
+    }
+
+    /// Registers a virtual table module, to be used with
+    /// `CREATE VIRTUAL TABLE ... USING name(...)`.
+    /// See http://www.sqlite.org/c3ref/create_module.html
+    pub fn create_module<T: VTab>(&mut self, name: &str, aux: T::Aux) -> SqliteResult<()> {
+        self.register_module::<T>(name, aux, false)
+    }
+
+    /// Registers an eponymous-only virtual table module, which exists as
+    /// a table named `name` in every schema and cannot be created with
+    /// `CREATE VIRTUAL TABLE`.
+    /// See http://www.sqlite.org/vtab.html#eponymous_only_virtual_tables
+    pub fn create_eponymous_module<T: VTab>(&mut self, name: &str, aux: T::Aux) -> SqliteResult<()> {
+        self.register_module::<T>(name, aux, true)
+    }
+}
@@ -0,0 +1,73 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+
+// `config::install_logger` reconfigures the library process-wide, so it is
+// tested in its own binary, before anything else has used SQLite.
+
+extern crate log;
+extern crate sqlite3;
+
+use sqlite3::config;
+use std::sync::Mutex;
+
+static LOGGED: Mutex<Vec<(log::LogLevel, String, String)>> = Mutex::new(Vec::new());
+
+struct CapturingLogger;
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::LogRecord) {
+        LOGGED.lock().unwrap().push((record.level(), record.target().to_string(), record.args().to_string()));
+    }
+}
+
+#[test]
+fn install_logger_routes_errors() {
+    log::set_logger(|max| {
+        max.set(log::LogLevelFilter::Trace);
+        Box::new(CapturingLogger)
+    }).unwrap();
+
+    assert_eq!(config::install_logger(), Ok(()));
+    assert_eq!(config::install_logger(), Ok(()));
+
+    let db = sqlite3::open(":memory:").unwrap();
+    assert!(db.prepare("SELECT q FRO test", &None).is_err());
+
+    let logged = LOGGED.lock().unwrap();
+    assert!(logged.iter().any(|&(level, ref target, ref msg)| {
+        level == log::LogLevel::Error && target == "sqlite3::log" && msg.contains("syntax error")
+    }), "{:?}", *logged);
+}
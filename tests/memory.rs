@@ -0,0 +1,59 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+
+// The heap limits are process-wide, so they are tested in their own binary
+// rather than next to tests running queries concurrently.
+
+extern crate sqlite3;
+
+use sqlite3::memory;
+
+#[test]
+fn heap_limits() {
+    let db = sqlite3::open(":memory:").unwrap();
+
+    let soft = memory::set_soft_heap_limit(1 << 20);
+    assert_eq!(memory::soft_heap_limit(), 1 << 20);
+    assert_eq!(memory::stats().unwrap().soft_heap_limit, 1 << 20);
+    memory::set_soft_heap_limit(soft);
+    assert_eq!(memory::soft_heap_limit(), soft);
+
+    let hard = memory::set_hard_heap_limit(1 << 20);
+    assert_eq!(memory::hard_heap_limit(), 1 << 20);
+    // allocations beyond the hard limit fail.
+    assert!(db.exec("SELECT zeroblob(2000000) || zeroblob(2000000)").is_err());
+    memory::set_hard_heap_limit(hard);
+    assert_eq!(memory::hard_heap_limit(), hard);
+    assert!(db.exec("SELECT zeroblob(2000000) || zeroblob(2000000)").is_ok());
+
+    assert!(memory::release_memory(1 << 20) >= 0);
+}